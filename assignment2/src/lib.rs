@@ -1,3 +1,7 @@
+pub mod verifier;
+
+use verifier::VerifyError;
+
 // ---------- Value Type ----------
 pub type Value = u8;
 
@@ -121,6 +125,7 @@ pub struct VirtualMachine {
 pub enum InterpretResult {
     InterpretSuccess,
     InterpretCompileError,
+    InvalidChunk(VerifyError),
     InterpretRuntimeError,
 }
 
@@ -134,6 +139,10 @@ impl VirtualMachine {
     }
 
     pub fn interpret(&mut self, chunk: Chunk) -> InterpretResult {
+        // Reject malformed bytecode before running any of it
+        if let Err(error) = verifier::verify(&chunk) {
+            return InterpretResult::InvalidChunk(error);
+        }
         self.chunk = Some(chunk);
         self.ip = 0;
         self.run()
@@ -163,7 +172,7 @@ impl VirtualMachine {
 
                 Some(OpCode::OpNegate) => {
                     if let Some(value) = self.stack.pop() {
-                        self.stack.push((-((value as i8)) as u8) as u8);
+                        self.stack.push(-(value as i8) as u8);
                     } else {
                         return InterpretResult::InterpretRuntimeError;
                     }
@@ -218,6 +227,7 @@ impl VirtualMachine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::verifier::VerifyErrorKind;
 
     #[test]
     fn test_basic_addition() {
@@ -233,4 +243,18 @@ mod tests {
         assert_eq!(result, InterpretResult::InterpretSuccess);
         assert_eq!(vm.stack.pop().unwrap(), 12);
     }
+
+    #[test]
+    fn test_rejects_unverified_chunk() {
+        let mut chunk = Chunk::init_chunk();
+        chunk.write_to_chunk(OpCode::OpConstant.to_u8(), 0);
+
+        let mut vm = VirtualMachine::init_machine();
+        let expected = VerifyError {
+            offset: 0,
+            kind: VerifyErrorKind::MissingOperand,
+        };
+        assert_eq!(vm.interpret(chunk), InterpretResult::InvalidChunk(expected));
+        assert!(vm.stack.is_empty());
+    }
 }
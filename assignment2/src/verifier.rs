@@ -0,0 +1,173 @@
+use std::fmt;
+
+use crate::{Chunk, OpCode};
+
+// Largest stack depth a verified chunk may reach
+pub const STACK_MAX: usize = 256;
+
+// ---------- Verification Errors ----------
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    InvalidOpcode(u8),
+    MissingOperand,
+    ConstantOutOfRange { index: usize, count: usize },
+    StackUnderflow { depth: usize, needed: usize },
+    StackOverflow { depth: usize },
+    MissingReturn,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04} ", self.offset)?;
+        match &self.kind {
+            VerifyErrorKind::InvalidOpcode(byte) => write!(f, "invalid opcode {}", byte),
+            VerifyErrorKind::MissingOperand => write!(f, "operand runs past end of chunk"),
+            VerifyErrorKind::ConstantOutOfRange { index, count } => {
+                write!(f, "constant index {} out of range ({} constants)", index, count)
+            }
+            VerifyErrorKind::StackUnderflow { depth, needed } => {
+                write!(f, "stack underflow (depth {}, needs {})", depth, needed)
+            }
+            VerifyErrorKind::StackOverflow { depth } => {
+                write!(f, "stack overflow (depth {}, max {})", depth, STACK_MAX)
+            }
+            VerifyErrorKind::MissingReturn => write!(f, "execution falls off end of chunk"),
+        }
+    }
+}
+
+// ---------- Verifier ----------
+// Walks the chunk tracking the abstract stack depth before each instruction.
+// Returns the maximum depth reached, or the first problem found.
+pub fn verify(chunk: &Chunk) -> Result<usize, VerifyError> {
+    let mut offset = 0;
+    let mut depth = 0;
+    let mut max_depth = 0;
+
+    while offset < chunk.code.len() {
+        let error = |kind| VerifyError { offset, kind };
+        let byte = chunk.code[offset];
+        let op = OpCode::from_u8(byte).ok_or_else(|| error(VerifyErrorKind::InvalidOpcode(byte)))?;
+
+        if op == OpCode::OpConstant {
+            let index = *chunk
+                .code
+                .get(offset + 1)
+                .ok_or_else(|| error(VerifyErrorKind::MissingOperand))? as usize;
+            if index >= chunk.values.len() {
+                return Err(error(VerifyErrorKind::ConstantOutOfRange {
+                    index,
+                    count: chunk.values.len(),
+                }));
+            }
+        }
+
+        let (pops, pushes) = stack_effect(op);
+        if depth < pops {
+            return Err(error(VerifyErrorKind::StackUnderflow { depth, needed: pops }));
+        }
+        depth = depth - pops + pushes;
+        if depth > STACK_MAX {
+            return Err(error(VerifyErrorKind::StackOverflow { depth }));
+        }
+        max_depth = max_depth.max(depth);
+
+        if op == OpCode::OpReturn {
+            return Ok(max_depth);
+        }
+        offset += instruction_len(op);
+    }
+
+    Err(VerifyError {
+        offset: chunk.code.len(),
+        kind: VerifyErrorKind::MissingReturn,
+    })
+}
+
+// Number of values popped and pushed by an instruction
+fn stack_effect(op: OpCode) -> (usize, usize) {
+    match op {
+        OpCode::OpReturn => (0, 0),
+        OpCode::OpConstant => (0, 1),
+        OpCode::OpNegate => (1, 1),
+        OpCode::OpAdd
+        | OpCode::OpSubtract
+        | OpCode::OpMultiply
+        | OpCode::OpDivide
+        | OpCode::OpModulo => (2, 1),
+    }
+}
+
+// Size in bytes of an instruction including its operands
+fn instruction_len(op: OpCode) -> usize {
+    match op {
+        OpCode::OpConstant => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_chunk() {
+        let mut chunk = Chunk::init_chunk();
+        chunk.add_constant(1);
+        chunk.add_constant(2);
+        chunk.write_to_chunk(OpCode::OpAdd.to_u8(), 0);
+        chunk.write_to_chunk(OpCode::OpReturn.to_u8(), 0);
+
+        assert_eq!(verify(&chunk), Ok(2));
+    }
+
+    #[test]
+    fn test_rejects_bad_chunks() {
+        let mut chunk = Chunk::init_chunk();
+        chunk.write_to_chunk(99, 0);
+        assert_eq!(verify(&chunk).unwrap_err().kind, VerifyErrorKind::InvalidOpcode(99));
+
+        let mut chunk = Chunk::init_chunk();
+        chunk.write_to_chunk(OpCode::OpConstant.to_u8(), 0);
+        assert_eq!(verify(&chunk).unwrap_err().kind, VerifyErrorKind::MissingOperand);
+
+        let mut chunk = Chunk::init_chunk();
+        chunk.write_to_chunk(OpCode::OpConstant.to_u8(), 0);
+        chunk.write_to_chunk(3, 0);
+        assert_eq!(
+            verify(&chunk).unwrap_err().kind,
+            VerifyErrorKind::ConstantOutOfRange { index: 3, count: 0 }
+        );
+
+        let mut chunk = Chunk::init_chunk();
+        chunk.add_constant(1);
+        chunk.write_to_chunk(OpCode::OpAdd.to_u8(), 0);
+        let error = verify(&chunk).unwrap_err();
+        assert_eq!(error.offset, 2);
+        assert_eq!(error.kind, VerifyErrorKind::StackUnderflow { depth: 1, needed: 2 });
+
+        let mut chunk = Chunk::init_chunk();
+        chunk.add_constant(1);
+        assert_eq!(
+            verify(&chunk).unwrap_err(),
+            VerifyError { offset: 2, kind: VerifyErrorKind::MissingReturn }
+        );
+    }
+
+    #[test]
+    fn test_rejects_stack_overflow() {
+        let mut chunk = Chunk::init_chunk();
+        for _ in 0..=STACK_MAX {
+            chunk.add_constant(1);
+        }
+        let error = verify(&chunk).unwrap_err();
+        assert_eq!(error.offset, STACK_MAX * 2);
+        assert_eq!(error.kind, VerifyErrorKind::StackOverflow { depth: STACK_MAX + 1 });
+    }
+}
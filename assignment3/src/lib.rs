@@ -1,4 +1,9 @@
 pub mod scanner;
+pub mod repl;
+
+use std::fmt::Write;
+
+use lox_diagnostics::{codes, render_all, Diagnostic};
 use scanner::*;
 
 #[derive(Debug)]
@@ -17,31 +22,53 @@ impl VirtualMachine {
 
     // Interpret Lox source code
     pub fn interpret(&mut self, source_code: &str) -> InterpretResult {
-        self.compile(source_code);
-        InterpretResult::InterpretSuccess
+        let diagnostics = self.compile(source_code);
+        if diagnostics.is_empty() {
+            InterpretResult::InterpretSuccess
+        } else {
+            eprint!("{}", render_all(&diagnostics, source_code, "<script>"));
+            InterpretResult::InterpretCompileError
+        }
     }
 
-    // Compile by scanning and printing tokens
-    pub fn compile(&mut self, source_code: &str) {
-        let mut scanner: Scanner = Scanner::init_scanner(source_code);
-        let mut line: usize = 0;
+    // Compile by scanning and printing tokens. Scanner errors are returned
+    // as diagnostics.
+    pub fn compile(&mut self, source_code: &str) -> Vec<Diagnostic> {
+        let (listing, diagnostics) = list_tokens(source_code);
+        print!("{}", listing);
+        diagnostics
+    }
+}
 
-        loop {
-            let token: Token = scanner.scan_token();
+// One line per token, the line number shown when it changes, along with a
+// diagnostic for each scanner error
+pub fn list_tokens(source_code: &str) -> (String, Vec<Diagnostic>) {
+    let mut scanner: Scanner = Scanner::init_scanner(source_code);
+    let mut line: usize = 0;
+    let mut listing = String::new();
+    let mut diagnostics = Vec::new();
 
-            if token.line != line {
-                print!("{:4} ", token.line);
-                line = token.line;
-            } else {
-                print!("   | ");
-            }
+    loop {
+        let token: Token = scanner.scan_token();
+
+        if token.line != line {
+            let _ = write!(listing, "{:4} ", token.line);
+            line = token.line;
+        } else {
+            listing.push_str("   | ");
+        }
 
-            let text = String::from_utf8_lossy(&token.value);
-            println!("{:?} {}, {:?}", token.token_type, token.value.len(), text);
+        let text = String::from_utf8_lossy(&token.value);
+        let _ = writeln!(listing, "{:?} {}, {:?}", token.token_type, token.value.len(), text);
 
-            if let TokenType::TokenEof = token.token_type {
-                break;
+        match token.token_type {
+            TokenType::TokenError => {
+                diagnostics.push(Diagnostic::error(codes::INVALID_TOKEN, text, token.span()));
             }
+            TokenType::TokenEof => break,
+            _ => {}
         }
     }
+
+    (listing, diagnostics)
 }
@@ -1,11 +1,12 @@
 use std::env;
 use std::fs;
+use assignment3::repl::Repl;
 use assignment3::*;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        eprintln!("Usage: cargo run -- <lox_source_file>");
+        Repl::default().run_interactive();
         return;
     }
 
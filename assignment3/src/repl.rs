@@ -0,0 +1,140 @@
+use std::io::{self, Stderr, Stdout, Write};
+
+use lox_diagnostics::render_all;
+use lox_repl::LineHandler;
+
+use crate::list_tokens;
+use crate::scanner::{Scanner, TokenType};
+
+pub const PROMPT: &str = "> ";
+pub const CONTINUATION_PROMPT: &str = "... ";
+
+// There is only a scanner so far: input is listed as tokens, nothing runs
+// and no state carries over between inputs
+const HELP: &str = "\
+Enter Lox source to see the tokens the scanner produces.
+Input continues while brackets or a string are open. A blank line submits it anyway.
+  :help           show this message
+  :tokens <code>  show the tokens for one line of code";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplStatus {
+    Done,
+    NeedMore,
+}
+
+// Line-at-a-time driver. Listings go to `output` and scanner errors to
+// `errors`, so tests can capture each on its own.
+pub struct Repl<W: Write, E: Write> {
+    buffer: String,
+    output: W,
+    errors: E,
+}
+
+impl Default for Repl<Stdout, Stderr> {
+    fn default() -> Self {
+        Self::new(io::stdout(), io::stderr())
+    }
+}
+
+impl<W: Write, E: Write> Repl<W, E> {
+    pub fn new(output: W, errors: E) -> Self {
+        Self {
+            buffer: String::new(),
+            output,
+            errors,
+        }
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    pub fn errors(&self) -> &E {
+        &self.errors
+    }
+
+    // Reads lines from the terminal, with editing and history, until end of input
+    pub fn run_interactive(&mut self) {
+        lox_repl::run(self);
+    }
+
+    pub fn feed(&mut self, line: &str) -> ReplStatus {
+        if self.buffer.is_empty() {
+            if line.trim().is_empty() {
+                return ReplStatus::Done;
+            }
+            if let Some(command) = line.trim().strip_prefix(':') {
+                self.command(command);
+                return ReplStatus::Done;
+            }
+        }
+
+        let submit = line.trim().is_empty();
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        if !submit && !is_complete(&self.buffer) {
+            return ReplStatus::NeedMore;
+        }
+
+        let source = std::mem::take(&mut self.buffer);
+        self.tokens(&source);
+        ReplStatus::Done
+    }
+
+    // Output is best effort; a closed pipe shouldn't end the session
+    fn tokens(&mut self, source: &str) {
+        let (listing, diagnostics) = list_tokens(source);
+        let _ = write!(self.output, "{}", listing);
+        if !diagnostics.is_empty() {
+            let _ = write!(self.errors, "{}", render_all(&diagnostics, source, "<repl>"));
+        }
+    }
+
+    fn command(&mut self, command: &str) {
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "help" => {
+                let _ = writeln!(self.output, "{}", HELP);
+            }
+            "tokens" => self.tokens(argument.trim()),
+            _ => {
+                let _ = writeln!(self.errors, "Unknown command ':{}'. Try :help.", name);
+            }
+        }
+    }
+}
+
+impl<W: Write, E: Write> LineHandler for Repl<W, E> {
+    fn prompt(&self) -> &'static str {
+        Repl::prompt(self)
+    }
+
+    fn feed(&mut self, line: &str) {
+        Repl::feed(self, line);
+    }
+}
+
+// Input is complete once every bracket is closed and no string is left open
+fn is_complete(source: &str) -> bool {
+    let mut scanner = Scanner::init_scanner(source);
+    let mut depth: i32 = 0;
+    loop {
+        let token = scanner.scan_token();
+        match token.token_type {
+            TokenType::TokenLeftParen | TokenType::TokenLeftBrace => depth += 1,
+            TokenType::TokenRightParen | TokenType::TokenRightBrace => depth -= 1,
+            TokenType::TokenError if token.value == b"Unterminated string literal" => return false,
+            TokenType::TokenEof => return depth <= 0,
+            _ => {}
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use lox_diagnostics::Span;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     TokenLeftParen, TokenRightParen,
@@ -25,8 +27,18 @@ pub enum TokenType {
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub value: Vec<u8>,
+    pub value: Vec<u8>, // lexeme, or the message for TokenError
+    pub length: usize,  // length of the lexeme in the source
     pub line: usize,
+    pub column: usize,  // 1-based, in characters
+    pub offset: usize,  // byte offset of the lexeme in the source
+}
+
+impl Token {
+    // Location of the token in the source, for diagnostics
+    pub fn span(&self) -> Span {
+        Span::new(self.offset, self.offset + self.length, self.line, self.column)
+    }
 }
 
 pub struct Scanner {
@@ -34,6 +46,9 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    line_start: usize, // byte offset where the current line begins
+    start_line: usize,
+    start_column: usize,
 }
 
 impl Scanner {
@@ -43,6 +58,9 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
@@ -77,13 +95,11 @@ impl Scanner {
         loop {
             match self.peek() as char {
                 ' ' | '\r' | '\t' => { self.advance(); },
-                '\n' => { self.line += 1; self.advance(); },
-                '/' => {
-                    if self.peek_next() as char == '/' {
-                        while self.peek() != b'\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else { return; }
+                '\n' => { self.advance(); self.newline(); },
+                '/' if self.peek_next() as char == '/' => {
+                    while self.peek() != b'\n' && !self.is_at_end() {
+                        self.advance();
+                    }
                 }
                 _ => return,
             }
@@ -92,8 +108,7 @@ impl Scanner {
 
     fn get_literal_string(&mut self) -> Token {
         while self.peek() != b'"' && !self.is_at_end() {
-            if self.peek() == b'\n' { self.line += 1; }
-            self.advance();
+            if self.advance() == b'\n' { self.newline(); }
         }
 
         if self.is_at_end() {
@@ -131,7 +146,10 @@ impl Scanner {
         Token {
             token_type,
             value: self.source[self.start..self.current].to_vec(),
-            line: self.line,
+            length: self.current - self.start,
+            line: self.start_line,
+            column: self.start_column,
+            offset: self.start,
         }
     }
 
@@ -139,13 +157,32 @@ impl Scanner {
         Token {
             token_type: TokenType::TokenError,
             value: message.as_bytes().to_vec(),
-            line: self.line,
+            length: self.current - self.start,
+            line: self.start_line,
+            column: self.start_column,
+            offset: self.start,
         }
     }
 
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    // Remember where the next token starts
+    fn mark_start(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        // Count characters rather than bytes so columns match what editors show
+        self.start_column = 1 + self.source[self.line_start..self.start]
+            .iter()
+            .filter(|&&b| b & 0xC0 != 0x80)
+            .count();
+    }
+
     pub fn scan_token(&mut self) -> Token {
         self.skip_whitespace();
-        self.start = self.current;
+        self.mark_start();
 
         if self.is_at_end() {
             return self.make_token(TokenType::TokenEof);
@@ -0,0 +1,76 @@
+use assignment3::repl::{Repl, ReplStatus, CONTINUATION_PROMPT, PROMPT};
+
+fn repl() -> Repl<Vec<u8>, Vec<u8>> {
+    Repl::new(Vec::new(), Vec::new())
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[test]
+fn test_tokens_are_listed() {
+    let mut repl = repl();
+    assert_eq!(repl.feed("1 + 2"), ReplStatus::Done);
+    assert_eq!(
+        text(repl.output()),
+        "   1 TokenNumber 1, \"1\"\n   | TokenPlus 1, \"+\"\n   | TokenNumber 1, \"2\"\n   2 TokenEof 0, \"\"\n"
+    );
+    assert_eq!(text(repl.errors()), "");
+}
+
+#[test]
+fn test_unbalanced_input_continues() {
+    let mut repl = repl();
+    assert_eq!(repl.feed("(1 +"), ReplStatus::NeedMore);
+    assert_eq!(repl.prompt(), CONTINUATION_PROMPT);
+    assert_eq!(repl.feed("{ 2"), ReplStatus::NeedMore);
+    assert_eq!(repl.feed("})"), ReplStatus::Done);
+    assert_eq!(repl.prompt(), PROMPT);
+    let output = text(repl.output());
+    assert!(output.starts_with("   1 TokenLeftParen 1, \"(\"\n"));
+    assert!(output.contains("   3 TokenRightBrace 1, \"}\"\n   | TokenRightParen 1, \")\"\n"));
+
+    // A blank line submits whatever is pending
+    let mut repl = self::repl();
+    assert_eq!(repl.feed("(("), ReplStatus::NeedMore);
+    assert_eq!(repl.feed(""), ReplStatus::Done);
+    assert_eq!(repl.prompt(), PROMPT);
+}
+
+#[test]
+fn test_open_string_continues() {
+    let mut repl = repl();
+    assert_eq!(repl.feed("\"ab"), ReplStatus::NeedMore);
+    assert_eq!(repl.feed("cd\""), ReplStatus::Done);
+    assert!(text(repl.output()).starts_with("   1 TokenString 7, \"\\\"ab\\ncd\\\"\"\n"));
+    assert_eq!(text(repl.errors()), "");
+}
+
+#[test]
+fn test_scanner_errors_go_to_errors() {
+    let mut repl = repl();
+    repl.feed("1 @ 2");
+    assert!(text(repl.output()).contains("TokenError 18, \"Unknown character.\""));
+    let errors = text(repl.errors());
+    assert!(errors.contains("error[E0001]: Unknown character."));
+    assert!(errors.contains("--> <repl>:1:3"));
+}
+
+#[test]
+fn test_meta_commands() {
+    let mut repl = repl();
+    // :tokens takes one line, so an open string is reported, not continued
+    assert_eq!(repl.feed(":tokens \"ab"), ReplStatus::Done);
+    repl.feed(":tokens )");
+    repl.feed(":help");
+    repl.feed(":nope");
+
+    let output = text(repl.output());
+    assert!(output.starts_with("   1 TokenError 27, \"Unterminated string literal\"\n"));
+    assert!(output.contains("   1 TokenRightParen 1, \")\"\n"));
+    assert!(output.contains(":tokens <code>"));
+    let errors = text(repl.errors());
+    assert!(errors.contains("error[E0001]: Unterminated string literal"));
+    assert!(errors.ends_with("Unknown command ':nope'. Try :help.\n"));
+}
@@ -1,41 +1,90 @@
+use lox_diagnostics::{codes, column_of, Diagnostic, Span};
+
 use crate::vm::{Chunk, OpCode};
 
 pub struct Compiler {
     source: String,
+    fold: bool,
 }
 
 impl Compiler {
     pub fn new(source: String) -> Self {
-        Self { source }
+        Self { source, fold: false }
+    }
+
+    // Compile `1 + 2` straight to the constant 3
+    pub fn with_folding(mut self, fold: bool) -> Self {
+        self.fold = fold;
+        self
     }
 
-    pub fn compile(&mut self) -> Chunk {
+    pub fn compile(&mut self) -> Result<Chunk, Vec<Diagnostic>> {
         let mut chunk = Chunk::new();
 
         // Very simple expression parser for demo (supports + - * /)
         let tokens: Vec<&str> = self.source.split_whitespace().collect();
 
         if tokens.len() == 1 {
-            let val: f64 = tokens[0].parse().unwrap();
-            chunk.write(OpCode::OpConstant(val));
+            let val = self.number(tokens[0])?;
+            chunk.write_constant(val);
         } else if tokens.len() == 3 {
-            let a: f64 = tokens[0].parse().unwrap();
+            let a = self.number(tokens[0])?;
             let op = tokens[1];
-            let b: f64 = tokens[2].parse().unwrap();
+            let b = self.number(tokens[2])?;
 
-            chunk.write(OpCode::OpConstant(a));
-            chunk.write(OpCode::OpConstant(b));
+            let op = match op {
+                "+" => OpCode::OpAdd,
+                "-" => OpCode::OpSubtract,
+                "*" => OpCode::OpMultiply,
+                "/" => OpCode::OpDivide,
+                _ => {
+                    return Err(vec![
+                        Diagnostic::error(codes::EXPECTED_TOKEN, "Expect operator.", self.span_of(op))
+                            .with_help("supported operators are `+`, `-`, `*` and `/`"),
+                    ]);
+                }
+            };
 
-            match op {
-                "+" => chunk.write(OpCode::OpAdd),
-                "-" => chunk.write(OpCode::OpSubtract),
-                "*" => chunk.write(OpCode::OpMultiply),
-                "/" => chunk.write(OpCode::OpDivide),
-                _ => {}
+            match op.apply(a, b) {
+                Some(value) if self.fold => chunk.write_constant(value),
+                _ => {
+                    chunk.write_constant(a);
+                    chunk.write_constant(b);
+                    chunk.write(op);
+                }
             }
+        } else if tokens.len() == 2 {
+            let end = self.source.trim_end().len();
+            return Err(vec![Diagnostic::error(
+                codes::EXPECTED_EXPRESSION,
+                "Expect expression.",
+                self.span_at(end, end),
+            )]);
+        } else if tokens.len() > 3 {
+            return Err(vec![
+                Diagnostic::error(codes::EXPECTED_TOKEN, "Expect end of expression.", self.span_of(tokens[3]))
+                    .with_help("only a number or a single binary operation is supported"),
+            ]);
         }
 
         chunk.write(OpCode::OpReturn);
-        chunk
+        Ok(chunk)
+    }
+
+    fn number(&self, token: &str) -> Result<f64, Vec<Diagnostic>> {
+        token.parse().map_err(|_| {
+            vec![Diagnostic::error(codes::EXPECTED_EXPRESSION, "Expect number.", self.span_of(token))]
+        })
+    }
+
+    // Span of a word borrowed from `self.source`
+    fn span_of(&self, token: &str) -> Span {
+        let start = token.as_ptr() as usize - self.source.as_ptr() as usize;
+        self.span_at(start, start + token.len())
+    }
+
+    fn span_at(&self, start: usize, end: usize) -> Span {
+        let line = 1 + self.source[..start].matches('\n').count();
+        Span::new(start, end, line, column_of(&self.source, start))
     }
 }
@@ -1,3 +1,5 @@
 pub mod scanner;
 pub mod compiler;
 pub mod vm;
+pub mod verifier;
+pub mod repl;
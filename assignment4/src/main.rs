@@ -1,17 +1,27 @@
-use assignment4::vm::VirtualMachine;
+use assignment4::repl::Repl;
+use assignment4::vm::{InterpretResult, VirtualMachine};
+use lox_diagnostics::render_all;
 use std::env;
 use std::fs;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: assignment4 <file>");
-        return;
+    match args.len() {
+        1 => Repl::default().run_interactive(),
+        2 => {
+            let source = fs::read_to_string(&args[1]).expect("Failed to read file");
+            run(&source, &args[1]);
+        }
+        _ => eprintln!("Usage: assignment4 [file]"),
     }
+}
 
-    let source = fs::read_to_string(&args[1]).expect("Failed to read file");
+fn run(source: &str, name: &str) {
     let mut vm = VirtualMachine::new();
-    let result = vm.interpret(&source);
-
-    println!("Result: {:?}", result);
+    match vm.interpret(source) {
+        InterpretResult::CompileError(diagnostics) => {
+            eprint!("{}", render_all(&diagnostics, source, name));
+        }
+        result => println!("Result: {:?}", result),
+    }
 }
@@ -0,0 +1,206 @@
+use std::fmt::Write as _;
+use std::io::{self, Stderr, Stdout, Write};
+
+use lox_diagnostics::render_all;
+use lox_repl::LineHandler;
+
+use crate::compiler::Compiler;
+use crate::scanner::{Scanner, TokenType};
+use crate::vm::{InterpretResult, VirtualMachine};
+
+pub const PROMPT: &str = "> ";
+pub const CONTINUATION_PROMPT: &str = "... ";
+
+const HELP: &str = "\
+Enter a number or a binary expression such as 1 + 2; its value is printed.
+Input continues while parentheses are open. A blank line submits it anyway.
+  :help           show this message
+  :disasm [expr]  disassemble an expression, or the last one that ran
+  :tokens <expr>  show the tokens the scanner produces
+  :reset          start again with a fresh VM";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplStatus {
+    Done,
+    NeedMore,
+}
+
+// Line-at-a-time driver. Values go to `output` and errors to `errors`, so
+// tests can capture each on its own.
+pub struct Repl<W: Write, E: Write> {
+    vm: VirtualMachine,
+    buffer: String,
+    last_source: Option<String>, // the last input that compiled, for :disasm
+    output: W,
+    errors: E,
+}
+
+impl Default for Repl<Stdout, Stderr> {
+    fn default() -> Self {
+        Self::new(io::stdout(), io::stderr())
+    }
+}
+
+impl<W: Write, E: Write> Repl<W, E> {
+    pub fn new(output: W, errors: E) -> Self {
+        Self {
+            vm: VirtualMachine::new(),
+            buffer: String::new(),
+            last_source: None,
+            output,
+            errors,
+        }
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    pub fn errors(&self) -> &E {
+        &self.errors
+    }
+
+    // Reads lines from the terminal, with editing and history, until end of input
+    pub fn run_interactive(&mut self) {
+        lox_repl::run(self);
+    }
+
+    pub fn feed(&mut self, line: &str) -> ReplStatus {
+        if self.buffer.is_empty() {
+            if line.trim().is_empty() {
+                return ReplStatus::Done;
+            }
+            if let Some(command) = line.trim().strip_prefix(':') {
+                self.command(command);
+                return ReplStatus::Done;
+            }
+        }
+
+        let submit = line.trim().is_empty();
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        if !submit && !is_complete(&self.buffer) {
+            return ReplStatus::NeedMore;
+        }
+
+        let source = std::mem::take(&mut self.buffer);
+        self.execute(&source);
+        ReplStatus::Done
+    }
+
+    // Output is best effort; a closed pipe shouldn't end the session
+    fn execute(&mut self, source: &str) {
+        match self.vm.interpret(source) {
+            InterpretResult::Ok(value) => {
+                let _ = writeln!(self.output, "{}", value);
+            }
+            InterpretResult::CompileError(diagnostics) => {
+                let _ = write!(self.errors, "{}", render_all(&diagnostics, source, "<repl>"));
+                return;
+            }
+            InterpretResult::InvalidChunk(error) => {
+                let _ = writeln!(self.errors, "Invalid chunk: {}", error);
+            }
+            InterpretResult::RuntimeError => {
+                let _ = writeln!(self.errors, "Runtime error.");
+            }
+        }
+        self.last_source = Some(source.to_string());
+    }
+
+    fn command(&mut self, command: &str) {
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let argument = argument.trim();
+        match name {
+            "help" => {
+                let _ = writeln!(self.output, "{}", HELP);
+            }
+            "disasm" => self.disasm(argument),
+            "tokens" => {
+                let _ = write!(self.output, "{}", token_listing(argument));
+            }
+            "reset" => {
+                self.vm = VirtualMachine::new();
+                self.last_source = None;
+            }
+            _ => {
+                let _ = writeln!(self.errors, "Unknown command ':{}'. Try :help.", name);
+            }
+        }
+    }
+
+    // Compiles without running so the listing can be inspected first
+    fn disasm(&mut self, source: &str) {
+        let source = if source.is_empty() {
+            match &self.last_source {
+                Some(source) => source.clone(),
+                None => {
+                    let _ = writeln!(self.errors, "Nothing has run yet.");
+                    return;
+                }
+            }
+        } else {
+            source.to_string()
+        };
+        match Compiler::new(source.clone()).compile() {
+            Ok(chunk) => {
+                let _ = write!(self.output, "{}", chunk.disassemble());
+            }
+            Err(diagnostics) => {
+                let _ = write!(self.errors, "{}", render_all(&diagnostics, &source, "<repl>"));
+            }
+        }
+    }
+}
+
+impl<W: Write, E: Write> LineHandler for Repl<W, E> {
+    fn prompt(&self) -> &'static str {
+        Repl::prompt(self)
+    }
+
+    fn feed(&mut self, line: &str) {
+        Repl::feed(self, line);
+    }
+}
+
+// One line per token, the line number shown when it changes
+fn token_listing(source: &str) -> String {
+    let mut scanner = Scanner::init_scanner(source);
+    let mut listing = String::new();
+    let mut line = 0;
+    loop {
+        let token = scanner.scan_token();
+        if token.line != line {
+            let _ = write!(listing, "{:4} ", token.line);
+            line = token.line;
+        } else {
+            listing.push_str("   | ");
+        }
+        let _ = writeln!(listing, "{:?} {}, {:?}", token.token_type, token.lexeme.len(), token.lexeme);
+        if token.token_type == TokenType::TokenEof {
+            return listing;
+        }
+    }
+}
+
+// Input is complete once every parenthesis is closed
+fn is_complete(source: &str) -> bool {
+    let mut scanner = Scanner::init_scanner(source);
+    let mut depth: i32 = 0;
+    loop {
+        match scanner.scan_token().token_type {
+            TokenType::TokenLeftParen => depth += 1,
+            TokenType::TokenRightParen => depth -= 1,
+            TokenType::TokenEof => return depth <= 0,
+            _ => {}
+        }
+    }
+}
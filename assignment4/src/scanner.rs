@@ -1,5 +1,16 @@
-#[derive(Debug, Clone)]
+use lox_diagnostics::Span;
+
+// Only what the expression compiler accepts: numbers, the four arithmetic
+// operators and parentheses
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
+    TokenLeftParen,
+    TokenRightParen,
+    TokenMinus,
+    TokenPlus,
+    TokenSlash,
+    TokenStar,
+    TokenNumber,
     TokenEof,
     TokenError,
 }
@@ -9,6 +20,15 @@ pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: usize,
+    pub column: usize, // 1-based, in characters
+    pub offset: usize, // byte offset of the lexeme in the source
+}
+
+impl Token {
+    // Location of the token in the source, for diagnostics
+    pub fn span(&self) -> Span {
+        Span::new(self.offset, self.offset + self.lexeme.len(), self.line, self.column)
+    }
 }
 
 pub struct Scanner {
@@ -16,6 +36,7 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: usize,
+    line_start: usize, // byte offset where the current line begins
 }
 
 impl Scanner {
@@ -25,15 +46,59 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
         }
     }
 
     pub fn scan_token(&mut self) -> Token {
+        self.skip_whitespace();
+        self.start = self.current;
         if self.is_at_end() {
             return self.make_token(TokenType::TokenEof);
         }
-        self.advance();
-        self.make_token(TokenType::TokenError)
+        let token_type = match self.advance() {
+            b'(' => TokenType::TokenLeftParen,
+            b')' => TokenType::TokenRightParen,
+            b'-' => TokenType::TokenMinus,
+            b'+' => TokenType::TokenPlus,
+            b'/' => TokenType::TokenSlash,
+            b'*' => TokenType::TokenStar,
+            ch if ch.is_ascii_digit() => self.number(),
+            _ => TokenType::TokenError,
+        };
+        self.make_token(token_type)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&ch) = self.source.get(self.current) {
+            match ch {
+                b' ' | b'\r' | b'\t' => self.current += 1,
+                b'\n' => {
+                    self.current += 1;
+                    self.line += 1;
+                    self.line_start = self.current;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    // Digits with an optional fraction, as in `12` or `0.25`
+    fn number(&mut self) -> TokenType {
+        while self.peek().is_ascii_digit() {
+            self.current += 1;
+        }
+        if self.peek() == b'.' && self.source.get(self.current + 1).is_some_and(u8::is_ascii_digit) {
+            self.current += 1;
+            while self.peek().is_ascii_digit() {
+                self.current += 1;
+            }
+        }
+        TokenType::TokenNumber
+    }
+
+    fn peek(&self) -> u8 {
+        self.source.get(self.current).copied().unwrap_or(0)
     }
 
     fn is_at_end(&self) -> bool {
@@ -49,8 +114,12 @@ impl Scanner {
     fn make_token(&self, token_type: TokenType) -> Token {
         Token {
             token_type,
-            lexeme: String::from_utf8(self.source.clone()).unwrap_or_default(),
+            lexeme: String::from_utf8_lossy(&self.source[self.start..self.current]).into_owned(),
             line: self.line,
+            column: 1 + String::from_utf8_lossy(&self.source[self.line_start..self.start])
+                .chars()
+                .count(),
+            offset: self.start,
         }
     }
 }
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use crate::vm::{Chunk, OpCode};
+
+// Largest stack depth a verified chunk may reach
+pub const STACK_MAX: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    UnknownOpcode { byte: u8 },
+    TruncatedInstruction,
+    ConstantOutOfRange { index: usize, count: usize },
+    StackUnderflow { depth: usize, needed: usize },
+    StackOverflow { depth: usize },
+    MissingReturn,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04} ", self.offset)?;
+        match &self.kind {
+            VerifyErrorKind::UnknownOpcode { byte } => write!(f, "unknown opcode {}", byte),
+            VerifyErrorKind::TruncatedInstruction => write!(f, "instruction runs past end of chunk"),
+            VerifyErrorKind::ConstantOutOfRange { index, count } => {
+                write!(f, "constant index {} out of range ({} constants)", index, count)
+            }
+            VerifyErrorKind::StackUnderflow { depth, needed } => {
+                write!(f, "stack underflow (depth {}, needs {})", depth, needed)
+            }
+            VerifyErrorKind::StackOverflow { depth } => {
+                write!(f, "stack overflow (depth {}, max {})", depth, STACK_MAX)
+            }
+            VerifyErrorKind::MissingReturn => write!(f, "execution falls off end of chunk"),
+        }
+    }
+}
+
+// Decodes every instruction and tracks the abstract stack depth before each
+// one, so `run` never meets a bad byte or pops an empty stack. Returns the
+// maximum depth reached.
+pub fn verify(chunk: &Chunk) -> Result<usize, VerifyError> {
+    let mut depth = 0;
+    let mut max_depth = 0;
+    let mut offset = 0;
+
+    while let Some(&byte) = chunk.code.get(offset) {
+        let error = |kind| VerifyError { offset, kind };
+        let op = OpCode::from_byte(byte).ok_or(error(VerifyErrorKind::UnknownOpcode { byte }))?;
+        if offset + op.size() > chunk.code.len() {
+            return Err(error(VerifyErrorKind::TruncatedInstruction));
+        }
+        if op == OpCode::OpConstant {
+            let index = chunk.code[offset + 1] as usize;
+            let count = chunk.constants.len();
+            if index >= count {
+                return Err(error(VerifyErrorKind::ConstantOutOfRange { index, count }));
+            }
+        }
+
+        let (pops, pushes) = stack_effect(op);
+        if depth < pops {
+            return Err(VerifyError {
+                offset,
+                kind: VerifyErrorKind::StackUnderflow { depth, needed: pops },
+            });
+        }
+        depth = depth - pops + pushes;
+        if depth > STACK_MAX {
+            return Err(VerifyError {
+                offset,
+                kind: VerifyErrorKind::StackOverflow { depth },
+            });
+        }
+        max_depth = max_depth.max(depth);
+
+        if op == OpCode::OpReturn {
+            return Ok(max_depth);
+        }
+        offset += op.size();
+    }
+
+    Err(VerifyError {
+        offset: chunk.code.len(),
+        kind: VerifyErrorKind::MissingReturn,
+    })
+}
+
+// Number of values popped and pushed by an instruction
+fn stack_effect(op: OpCode) -> (usize, usize) {
+    match op {
+        OpCode::OpConstant => (0, 1),
+        OpCode::OpAdd | OpCode::OpSubtract | OpCode::OpMultiply | OpCode::OpDivide => (2, 1),
+        OpCode::OpReturn => (1, 0),
+    }
+}
@@ -1,6 +1,10 @@
-#[derive(Debug, Clone)]
+use std::fmt::Write;
+
+// Instructions are stored as bytes: an opcode, then for OpConstant a
+// one-byte index into the chunk's constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
-    OpConstant(f64),
+    OpConstant,
     OpAdd,
     OpSubtract,
     OpMultiply,
@@ -8,33 +12,129 @@ pub enum OpCode {
     OpReturn,
 }
 
+impl OpCode {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            OpCode::OpConstant => 0,
+            OpCode::OpAdd => 1,
+            OpCode::OpSubtract => 2,
+            OpCode::OpMultiply => 3,
+            OpCode::OpDivide => 4,
+            OpCode::OpReturn => 5,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        match byte {
+            0 => Some(OpCode::OpConstant),
+            1 => Some(OpCode::OpAdd),
+            2 => Some(OpCode::OpSubtract),
+            3 => Some(OpCode::OpMultiply),
+            4 => Some(OpCode::OpDivide),
+            5 => Some(OpCode::OpReturn),
+            _ => None,
+        }
+    }
+
+    // Bytes the instruction takes up, opcode included
+    pub fn size(self) -> usize {
+        match self {
+            OpCode::OpConstant => 2,
+            _ => 1,
+        }
+    }
+
+    // Result of an arithmetic op on its operands; None for anything else.
+    // Constant folding uses this too, so it always matches the VM.
+    pub fn apply(self, a: f64, b: f64) -> Option<f64> {
+        match self {
+            OpCode::OpAdd => Some(a + b),
+            OpCode::OpSubtract => Some(a - b),
+            OpCode::OpMultiply => Some(a * b),
+            OpCode::OpDivide => Some(a / b),
+            OpCode::OpConstant | OpCode::OpReturn => None,
+        }
+    }
+}
+
 pub struct Chunk {
-    pub code: Vec<OpCode>,
+    pub code: Vec<u8>,
+    pub constants: Vec<f64>,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chunk {
     pub fn new() -> Self {
-        Self { code: Vec::new() }
+        Self {
+            code: Vec::new(),
+            constants: Vec::new(),
+        }
     }
 
     pub fn write(&mut self, opcode: OpCode) {
-        self.code.push(opcode);
+        self.code.push(opcode.to_byte());
+    }
+
+    // Adds `value` to the constants and writes the OpConstant that pushes it
+    pub fn write_constant(&mut self, value: f64) {
+        let index = u8::try_from(self.constants.len()).expect("at most 256 constants per chunk");
+        self.constants.push(value);
+        self.write(OpCode::OpConstant);
+        self.code.push(index);
+    }
+
+    // One instruction per line, for the REPL's :disasm. Expects a chunk from
+    // the compiler, whose OpConstants all have a valid index.
+    pub fn disassemble(&self) -> String {
+        let mut listing = String::new();
+        let mut offset = 0;
+        while let Some(&byte) = self.code.get(offset) {
+            let Some(op) = OpCode::from_byte(byte) else {
+                let _ = writeln!(listing, "{:04} unknown opcode {}", offset, byte);
+                offset += 1;
+                continue;
+            };
+            if op == OpCode::OpConstant {
+                let index = self.code[offset + 1];
+                let value = self.constants[index as usize];
+                let _ = writeln!(listing, "{:04} {:<16} {:4} '{}'", offset, "OpConstant", index, value);
+            } else {
+                let _ = writeln!(listing, "{:04} {:?}", offset, op);
+            }
+            offset += op.size();
+        }
+        listing
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum InterpretResult {
     Ok(f64),
+    CompileError(Vec<Diagnostic>),
+    InvalidChunk(VerifyError),
     RuntimeError,
 }
 
 use crate::compiler::Compiler;
+use lox_diagnostics::Diagnostic;
+use crate::verifier::{self, VerifyError};
 
 pub struct VirtualMachine {
     chunk: Chunk,
     stack: Vec<f64>,
 }
 
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl VirtualMachine {
     pub fn new() -> Self {
         Self {
@@ -45,39 +145,39 @@ impl VirtualMachine {
 
     pub fn interpret(&mut self, source_code: &str) -> InterpretResult {
         let mut compiler = Compiler::new(source_code.to_string());
-        self.chunk = compiler.compile();
+        self.chunk = match compiler.compile() {
+            Ok(chunk) => chunk,
+            Err(diagnostics) => return InterpretResult::CompileError(diagnostics),
+        };
+
+        // Guarantees the stack pops in `run` can't underflow
+        if let Err(error) = verifier::verify(&self.chunk) {
+            return InterpretResult::InvalidChunk(error);
+        }
 
         self.run()
     }
 
+    // Only runs verified chunks, so every opcode and operand byte is valid
     fn run(&mut self) -> InterpretResult {
-        for op in &self.chunk.code {
+        let mut ip = 0;
+        while let Some(&byte) = self.chunk.code.get(ip) {
+            let op = OpCode::from_byte(byte).unwrap();
             match op {
-                OpCode::OpConstant(value) => self.stack.push(*value),
-                OpCode::OpAdd => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(a + b);
-                }
-                OpCode::OpSubtract => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(a - b);
-                }
-                OpCode::OpMultiply => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(a * b);
+                OpCode::OpConstant => {
+                    let index = self.chunk.code[ip + 1] as usize;
+                    self.stack.push(self.chunk.constants[index]);
                 }
-                OpCode::OpDivide => {
+                OpCode::OpAdd | OpCode::OpSubtract | OpCode::OpMultiply | OpCode::OpDivide => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(a / b);
+                    self.stack.push(op.apply(a, b).unwrap());
                 }
                 OpCode::OpReturn => {
                     return InterpretResult::Ok(self.stack.pop().unwrap());
                 }
             }
+            ip += op.size();
         }
 
         InterpretResult::RuntimeError
@@ -0,0 +1,86 @@
+use assignment4::repl::{Repl, ReplStatus, CONTINUATION_PROMPT, PROMPT};
+
+fn repl() -> Repl<Vec<u8>, Vec<u8>> {
+    Repl::new(Vec::new(), Vec::new())
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[test]
+fn test_values_echo() {
+    let mut repl = repl();
+    repl.feed("1 + 2");
+    repl.feed("7");
+    repl.feed("1 / 4");
+    assert_eq!(text(repl.output()), "3\n7\n0.25\n");
+    assert_eq!(text(repl.errors()), "");
+}
+
+#[test]
+fn test_unbalanced_input_continues() {
+    let mut repl = repl();
+    assert_eq!(repl.feed("(2 *"), ReplStatus::NeedMore);
+    assert_eq!(repl.prompt(), CONTINUATION_PROMPT);
+    assert_eq!(repl.feed("3"), ReplStatus::NeedMore);
+    // The compiler has no grouping yet, so the completed input is an error
+    assert_eq!(repl.feed(")"), ReplStatus::Done);
+    assert_eq!(repl.prompt(), PROMPT);
+    assert!(text(repl.errors()).contains("Expect end of expression."));
+
+    // A blank line submits whatever is pending
+    let mut repl = self::repl();
+    assert_eq!(repl.feed("4 * ("), ReplStatus::NeedMore);
+    assert_eq!(repl.feed(""), ReplStatus::Done);
+    assert_eq!(repl.prompt(), PROMPT);
+}
+
+#[test]
+fn test_errors_do_not_end_the_session() {
+    let mut repl = repl();
+    repl.feed("1 %");
+    repl.feed("1 % 2");
+    repl.feed("5 - 1");
+    assert_eq!(text(repl.output()), "4\n");
+    let errors = text(repl.errors());
+    assert!(errors.contains("error[E0002]: Expect expression."));
+    assert!(errors.contains("Expect operator."));
+}
+
+#[test]
+fn test_meta_commands() {
+    let mut repl = repl();
+    repl.feed(":disasm");
+    repl.feed("6 / 3");
+    repl.feed(":disasm");
+    repl.feed(":disasm 1 - 2");
+    repl.feed(":reset");
+    repl.feed(":disasm");
+    repl.feed(":help");
+    repl.feed(":nope");
+
+    let output = text(repl.output());
+    assert!(output.starts_with("2\n0000 OpConstant          0 '6'\n0002 OpConstant          1 '3'\n0004 OpDivide\n"));
+    assert!(output.contains("0002 OpConstant          1 '2'\n0004 OpSubtract\n0005 OpReturn\n"));
+    assert!(output.contains(":disasm [expr]"));
+    assert!(output.contains(":tokens <expr>"));
+    assert_eq!(
+        text(repl.errors()),
+        "Nothing has run yet.\nNothing has run yet.\nUnknown command ':nope'. Try :help.\n"
+    );
+}
+
+#[test]
+fn test_tokens_command() {
+    let mut repl = repl();
+    repl.feed(":tokens (1.5 + 2)");
+    repl.feed(":tokens 3 @");
+    assert_eq!(
+        text(repl.output()),
+        "   1 TokenLeftParen 1, \"(\"\n   | TokenNumber 3, \"1.5\"\n   | TokenPlus 1, \"+\"\n   \
+         | TokenNumber 1, \"2\"\n   | TokenRightParen 1, \")\"\n   | TokenEof 0, \"\"\n   \
+         1 TokenNumber 1, \"3\"\n   | TokenError 1, \"@\"\n   | TokenEof 0, \"\"\n"
+    );
+    assert_eq!(text(repl.errors()), "");
+}
@@ -1,4 +1,5 @@
-use assignment4::vm::{VirtualMachine, InterpretResult};
+use assignment4::verifier::{self, VerifyError, VerifyErrorKind};
+use assignment4::vm::{Chunk, OpCode, VirtualMachine, InterpretResult};
 
 fn run_expression(expr: &str) -> f64 {
     let mut vm = VirtualMachine::new();
@@ -40,3 +41,76 @@ fn test_division() {
     assert_eq!(run_expression("9 / 3"), 3.0);
     assert_eq!(run_expression("12 / 4"), 3.0);
 }
+
+#[test]
+fn test_return_on_empty_stack_is_rejected() {
+    let mut chunk = Chunk::new();
+    chunk.write(OpCode::OpReturn);
+    assert_eq!(
+        verifier::verify(&chunk),
+        Err(VerifyError {
+            offset: 0,
+            kind: VerifyErrorKind::StackUnderflow { depth: 0, needed: 1 },
+        })
+    );
+}
+
+#[test]
+fn test_rejects_malformed_bytecode() {
+    let verify = |code: Vec<u8>| {
+        let mut chunk = Chunk::new();
+        chunk.constants.push(1.0);
+        chunk.code = code;
+        verifier::verify(&chunk).map_err(|error| error.to_string())
+    };
+    let constant = OpCode::OpConstant.to_byte();
+    let (add, ret) = (OpCode::OpAdd.to_byte(), OpCode::OpReturn.to_byte());
+
+    assert_eq!(verify(vec![constant, 0, constant, 0, add, ret]), Ok(2));
+    assert_eq!(verify(vec![constant, 0, 42]), Err("0002 unknown opcode 42".to_string()));
+    assert_eq!(verify(vec![constant]), Err("0000 instruction runs past end of chunk".to_string()));
+    assert_eq!(
+        verify(vec![constant, 1, ret]),
+        Err("0000 constant index 1 out of range (1 constants)".to_string())
+    );
+}
+
+#[test]
+fn test_compile_errors_point_at_source() {
+    let mut vm = VirtualMachine::new();
+    match vm.interpret("1 %
+  3") {
+        InterpretResult::CompileError(diagnostics) => {
+            assert_eq!(diagnostics[0].message, "Expect operator.");
+            assert_eq!((diagnostics[0].span.line, diagnostics[0].span.column), (1, 3));
+        }
+        result => panic!("expected a compile error, got {:?}", result),
+    }
+
+    match vm.interpret("1 + x") {
+        InterpretResult::CompileError(diagnostics) => {
+            assert_eq!(diagnostics[0].message, "Expect number.");
+            assert_eq!(diagnostics[0].span.start, 4);
+        }
+        result => panic!("expected a compile error, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_constant_folding() {
+    use assignment4::compiler::Compiler;
+
+    let folded = Compiler::new("6 * 7".to_string()).with_folding(true).compile().unwrap();
+    assert_eq!(folded.code, [OpCode::OpConstant.to_byte(), 0, OpCode::OpReturn.to_byte()]);
+    assert_eq!(folded.constants, [42.0]);
+
+    let plain = Compiler::new("6 * 7".to_string()).compile().unwrap();
+    assert_eq!(plain.code.len(), 6);
+
+    // Division by zero folds to what the VM computes: infinity, or NaN for 0 / 0
+    let folded = Compiler::new("1 / 0".to_string()).with_folding(true).compile().unwrap();
+    assert_eq!(folded.constants, [f64::INFINITY]);
+    let folded = Compiler::new("0 / 0".to_string()).with_folding(true).compile().unwrap();
+    assert!(folded.constants[0].is_nan());
+    assert!(run_expression("0 / 0").is_nan());
+}
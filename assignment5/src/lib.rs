@@ -0,0 +1,5 @@
+pub mod output;
+pub mod packed;
+pub mod virtual_machine;
+
+pub use output::Output;
@@ -1,6 +1,4 @@
-mod virtual_machine;
-
-use virtual_machine::*;
+use assignment5::virtual_machine::*;
 
 fn main() {
     println!("Running sample program...");
@@ -10,5 +8,7 @@ fn main() {
     chunk.write(OpCode::OpReturn, 1);
 
     let mut vm = VirtualMachine::new(chunk);
-    vm.run();
+    if let Err(error) = vm.run() {
+        eprintln!("{}", error);
+    }
 }
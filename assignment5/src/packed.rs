@@ -0,0 +1,84 @@
+use crate::virtual_machine::Value;
+
+// === Packed Values ===
+// How a value is stored on the VM stack. By default that is the Value enum
+// itself (16 bytes). With the `nan-boxing` feature every value fits in one
+// u64 instead: numbers are their own bits, and nil and the booleans are tags
+// in the payload of a quiet NaN that no arithmetic result uses once NaNs are
+// made canonical.
+#[derive(Clone, Copy)]
+pub struct Packed(Repr);
+
+#[cfg(not(feature = "nan-boxing"))]
+type Repr = Value;
+
+#[cfg(feature = "nan-boxing")]
+type Repr = u64;
+
+#[cfg(feature = "nan-boxing")]
+mod bits {
+    pub const QNAN: u64 = 0x7ffc_0000_0000_0000;
+    pub const NIL: u64 = QNAN | 1;
+    pub const FALSE: u64 = QNAN | 2;
+    pub const TRUE: u64 = QNAN | 3;
+}
+
+impl Packed {
+    #[cfg(not(feature = "nan-boxing"))]
+    pub fn pack(value: Value) -> Self {
+        Packed(value)
+    }
+
+    #[cfg(not(feature = "nan-boxing"))]
+    pub fn unpack(self) -> Value {
+        self.0
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    pub fn pack(value: Value) -> Self {
+        Packed(match value {
+            Value::ValNumber(n) if n.is_nan() => f64::NAN.to_bits(),
+            Value::ValNumber(n) => n.to_bits(),
+            Value::ValNil => bits::NIL,
+            Value::ValBool(false) => bits::FALSE,
+            Value::ValBool(true) => bits::TRUE,
+        })
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    pub fn unpack(self) -> Value {
+        match self.0 {
+            bits::NIL => Value::ValNil,
+            bits::FALSE => Value::ValBool(false),
+            bits::TRUE => Value::ValBool(true),
+            raw => Value::ValNumber(f64::from_bits(raw)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_survive_packing() {
+        let values = [
+            Value::ValNil,
+            Value::ValBool(false),
+            Value::ValBool(true),
+            Value::ValNumber(-0.0),
+            Value::ValNumber(2.5),
+            Value::ValNumber(f64::INFINITY),
+        ];
+        for value in values {
+            assert_eq!(Packed::pack(value).unpack(), value);
+        }
+
+        // A NaN whose bits match the nil tag is still a number
+        let nan = Value::ValNumber(f64::from_bits(0x7ffc_0000_0000_0001));
+        assert!(matches!(Packed::pack(nan).unpack(), Value::ValNumber(n) if n.is_nan()));
+
+        let expected = if cfg!(feature = "nan-boxing") { 8 } else { size_of::<Value>() };
+        assert_eq!(size_of::<Packed>(), expected);
+    }
+}
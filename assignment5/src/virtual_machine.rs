@@ -1,9 +1,13 @@
 use std::fmt;
 
+use crate::output::Output;
+use crate::packed::Packed;
+
 // Number alias
 pub type Number = f64;
 
 // === Value Enum ===
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     ValBool(bool),
@@ -11,10 +15,24 @@ pub enum Value {
     ValNil,
 }
 
+impl Value {
+    // Lox formatting: 3 rather than 3.0, nil rather than ValNil
+    pub fn format(&self) -> String {
+        match self {
+            Value::ValBool(b) => b.to_string(),
+            Value::ValNumber(n) => n.to_string(),
+            Value::ValNil => "nil".to_string(),
+        }
+    }
+}
+
 // === OpCode Enum ===
-#[derive(Debug, Clone)]
+// Instructions are stored as bytes: an opcode, then for OpConstant a
+// one-byte index into the chunk's constants
+#[allow(dead_code, clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
-    OpConstant(Value),
+    OpConstant,
     OpAdd,
     OpSubtract,
     OpMultiply,
@@ -30,10 +48,40 @@ pub enum OpCode {
     OpReturn,
 }
 
+impl OpCode {
+    pub fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        const OPS: [OpCode; 14] = [
+            OpCode::OpConstant,
+            OpCode::OpAdd,
+            OpCode::OpSubtract,
+            OpCode::OpMultiply,
+            OpCode::OpDivide,
+            OpCode::OpNegate,
+            OpCode::OpNil,
+            OpCode::OpTrue,
+            OpCode::OpFalse,
+            OpCode::OpNot,
+            OpCode::OpEqual,
+            OpCode::OpGreater,
+            OpCode::OpLess,
+            OpCode::OpReturn,
+        ];
+        OPS.get(byte as usize).copied()
+    }
+}
+
 // === Chunk Structure ===
+// `lines` has an entry for every byte of `code`, operands included.
+// Constants are stored packed, like the stack, so OpConstant copies one
+// straight across.
 pub struct Chunk {
-    pub code: Vec<OpCode>,
+    pub code: Vec<u8>,
     pub lines: Vec<usize>,
+    pub constants: Vec<Packed>,
 }
 
 impl Chunk {
@@ -41,20 +89,71 @@ impl Chunk {
         Chunk {
             code: Vec::new(),
             lines: Vec::new(),
+            constants: Vec::new(),
         }
     }
 
     pub fn write(&mut self, op: OpCode, line: usize) {
-        self.code.push(op);
+        self.code.push(op.to_byte());
+        self.lines.push(line);
+    }
+
+    // Adds `value` to the constants and writes the OpConstant that pushes it
+    #[cfg(test)]
+    pub fn write_constant(&mut self, value: Value, line: usize) {
+        let index = u8::try_from(self.constants.len()).expect("at most 256 constants per chunk");
+        self.constants.push(Packed::pack(value));
+        self.write(OpCode::OpConstant, line);
+        self.code.push(index);
         self.lines.push(line);
     }
 }
 
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// === Runtime Errors ===
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeErrorKind {
+    TypeError,
+    StackUnderflow,
+    InvalidInstruction,
+}
+
+// One entry of a stack trace, innermost call first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub kind: RuntimeErrorKind,
+    pub line: usize,
+    pub trace: Vec<Frame>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in &self.trace {
+            write!(f, "\n[line {}] in {}", frame.line, frame.function)?;
+        }
+        Ok(())
+    }
+}
+
 // === VirtualMachine ===
 pub struct VirtualMachine {
     pub chunk: Chunk,
     pub ip: usize,
-    pub stack: Vec<Value>,
+    pub stack: Vec<Packed>,
+    pub output: Output,
 }
 
 impl VirtualMachine {
@@ -63,95 +162,119 @@ impl VirtualMachine {
             chunk,
             ip: 0,
             stack: Vec::new(),
+            output: Output::Stdout,
         }
     }
 
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
     // === Run method ===
-    pub fn run(&mut self) -> Option<Value> {
-        while self.ip < self.chunk.code.len() {
-            let instruction = self.chunk.code[self.ip].clone();
+    pub fn run(&mut self) -> Result<Option<Value>, RuntimeError> {
+        while let Some(&byte) = self.chunk.code.get(self.ip) {
             self.ip += 1;
+            let Some(op) = OpCode::from_byte(byte) else {
+                let message = format!("Unknown opcode {}.", byte);
+                return Err(self.runtime_error(RuntimeErrorKind::InvalidInstruction, &message));
+            };
 
-            match instruction {
-                OpCode::OpConstant(val) => self.stack.push(val),
-                OpCode::OpAdd => self.binary_op(|a, b| a + b),
-                OpCode::OpSubtract => self.binary_op(|a, b| a - b),
-                OpCode::OpMultiply => self.binary_op(|a, b| a * b),
-                OpCode::OpDivide => self.binary_op(|a, b| a / b),
-                OpCode::OpNegate => {
-                    if let Some(Value::ValNumber(n)) = self.stack.pop() {
-                        self.stack.push(Value::ValNumber(-n));
-                    } else {
-                        self.runtime_error("Operand must be a number.");
-                        return None;
-                    }
+            match op {
+                OpCode::OpConstant => {
+                    let constant = self
+                        .chunk
+                        .code
+                        .get(self.ip)
+                        .and_then(|&index| self.chunk.constants.get(index as usize))
+                        .copied();
+                    let Some(val) = constant else {
+                        let message = "Missing or invalid constant index.";
+                        return Err(self.runtime_error(RuntimeErrorKind::InvalidInstruction, message));
+                    };
+                    self.ip += 1;
+                    self.stack.push(val);
                 }
-                OpCode::OpNil => self.stack.push(Value::ValNil),
-                OpCode::OpTrue => self.stack.push(Value::ValBool(true)),
-                OpCode::OpFalse => self.stack.push(Value::ValBool(false)),
-                OpCode::OpNot => {
-                    if let Some(val) = self.stack.pop() {
-                        self.stack.push(Value::ValBool(self.is_falsey(val)));
-                    } else {
-                        self.runtime_error("Stack underflow on NOT.");
-                        return None;
+                OpCode::OpAdd => self.binary_op(|a, b| Value::ValNumber(a + b))?,
+                OpCode::OpSubtract => self.binary_op(|a, b| Value::ValNumber(a - b))?,
+                OpCode::OpMultiply => self.binary_op(|a, b| Value::ValNumber(a * b))?,
+                OpCode::OpDivide => self.binary_op(|a, b| Value::ValNumber(a / b))?,
+                OpCode::OpNegate => match self.pop()? {
+                    Value::ValNumber(n) => self.push(Value::ValNumber(-n)),
+                    _ => {
+                        return Err(self.runtime_error(
+                            RuntimeErrorKind::TypeError,
+                            "Operand must be a number.",
+                        ));
                     }
+                },
+                OpCode::OpNil => self.push(Value::ValNil),
+                OpCode::OpTrue => self.push(Value::ValBool(true)),
+                OpCode::OpFalse => self.push(Value::ValBool(false)),
+                OpCode::OpNot => {
+                    let val = self.pop()?;
+                    let falsey = self.is_falsey(val);
+                    self.push(Value::ValBool(falsey));
                 }
-                OpCode::OpEqual => self.binary_cmp(|a, b| a == b),
-                OpCode::OpGreater => self.binary_cmp(|a, b| a > b),
-                OpCode::OpLess => self.binary_cmp(|a, b| a < b),
+                OpCode::OpEqual => self.binary_op(|a, b| Value::ValBool(a == b))?,
+                OpCode::OpGreater => self.binary_op(|a, b| Value::ValBool(a > b))?,
+                OpCode::OpLess => self.binary_op(|a, b| Value::ValBool(a < b))?,
                 OpCode::OpReturn => {
-                    if let Some(val) = self.stack.last() {
-                        println!("=> {:?}", val);
-                        return Some(*val);
+                    if let Some(val) = self.stack.last().map(|val| val.unpack()) {
+                        self.output.write_line(&format!("=> {}", val.format()));
+                        return Ok(Some(val));
                     } else {
-                        println!("=> (empty stack)");
-                        return None;
+                        self.output.write_line("=> (empty stack)");
+                        return Ok(None);
                     }
                 }
             }
         }
-        None
+        Ok(None)
     }
 
     // === Helper functions ===
-    fn binary_op<F>(&mut self, op: F)
+    fn binary_op<F>(&mut self, op: F) -> Result<(), RuntimeError>
     where
-        F: Fn(f64, f64) -> f64,
+        F: Fn(f64, f64) -> Value,
     {
-        if let (Some(Value::ValNumber(b)), Some(Value::ValNumber(a))) =
-            (self.stack.pop(), self.stack.pop())
-        {
-            self.stack.push(Value::ValNumber(op(a, b)));
+        let b = self.pop()?;
+        let a = self.pop()?;
+        if let (Value::ValNumber(a), Value::ValNumber(b)) = (a, b) {
+            self.push(op(a, b));
+            Ok(())
         } else {
-            self.runtime_error("Operands must be numbers.");
+            Err(self.runtime_error(RuntimeErrorKind::TypeError, "Operands must be numbers."))
         }
     }
 
-    fn binary_cmp<F>(&mut self, cmp: F)
-    where
-        F: Fn(f64, f64) -> bool,
-    {
-        if let (Some(Value::ValNumber(b)), Some(Value::ValNumber(a))) =
-            (self.stack.pop(), self.stack.pop())
-        {
-            self.stack.push(Value::ValBool(cmp(a, b)));
-        } else {
-            self.runtime_error("Operands must be numbers.");
+    fn push(&mut self, val: Value) {
+        self.stack.push(Packed::pack(val));
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(val) => Ok(val.unpack()),
+            None => Err(self.runtime_error(RuntimeErrorKind::StackUnderflow, "Stack underflow.")),
         }
     }
 
     fn is_falsey(&self, val: Value) -> bool {
-        match val {
-            Value::ValBool(false) | Value::ValNil => true,
-            _ => false,
-        }
+        matches!(val, Value::ValBool(false) | Value::ValNil)
     }
 
-    fn runtime_error(&self, message: &str) {
-        println!("{}", message);
-        if self.ip < self.chunk.lines.len() {
-            println!("[line {}] in script", self.chunk.lines[self.ip]);
+    // Builds the error for the instruction that just executed and resets the stack
+    fn runtime_error(&mut self, kind: RuntimeErrorKind, message: &str) -> RuntimeError {
+        let line = self.chunk.lines.get(self.ip - 1).copied().unwrap_or(0);
+        self.stack.clear();
+        RuntimeError {
+            message: message.to_string(),
+            kind,
+            line,
+            trace: vec![Frame {
+                function: "script".to_string(),
+                line,
+            }],
         }
     }
 }
@@ -160,106 +283,179 @@ impl VirtualMachine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
-    fn make_vm_with_ops(ops: Vec<OpCode>) -> VirtualMachine {
+    // A VM for the chunk `build` writes, all on line 1
+    fn make_vm(build: impl FnOnce(&mut Chunk)) -> VirtualMachine {
         let mut chunk = Chunk::new();
-        for op in ops {
-            chunk.write(op, 1);
-        }
+        build(&mut chunk);
         VirtualMachine::new(chunk)
     }
 
     #[test]
     fn test_addition() {
-        let ops = vec![
-            OpCode::OpConstant(Value::ValNumber(3.0)),
-            OpCode::OpConstant(Value::ValNumber(4.0)),
-            OpCode::OpAdd,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValNumber(7.0)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNumber(3.0), 1);
+            chunk.write_constant(Value::ValNumber(4.0), 1);
+            chunk.write(OpCode::OpAdd, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValNumber(7.0))));
     }
 
     #[test]
     fn test_subtraction() {
-        let ops = vec![
-            OpCode::OpConstant(Value::ValNumber(10.0)),
-            OpCode::OpConstant(Value::ValNumber(3.0)),
-            OpCode::OpSubtract,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValNumber(7.0)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNumber(10.0), 1);
+            chunk.write_constant(Value::ValNumber(3.0), 1);
+            chunk.write(OpCode::OpSubtract, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValNumber(7.0))));
     }
 
     #[test]
     fn test_multiplication() {
-        let ops = vec![
-            OpCode::OpConstant(Value::ValNumber(6.0)),
-            OpCode::OpConstant(Value::ValNumber(7.0)),
-            OpCode::OpMultiply,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValNumber(42.0)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNumber(6.0), 1);
+            chunk.write_constant(Value::ValNumber(7.0), 1);
+            chunk.write(OpCode::OpMultiply, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValNumber(42.0))));
     }
 
     #[test]
     fn test_division() {
-        let ops = vec![
-            OpCode::OpConstant(Value::ValNumber(8.0)),
-            OpCode::OpConstant(Value::ValNumber(2.0)),
-            OpCode::OpDivide,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValNumber(4.0)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNumber(8.0), 1);
+            chunk.write_constant(Value::ValNumber(2.0), 1);
+            chunk.write(OpCode::OpDivide, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValNumber(4.0))));
     }
 
     #[test]
     fn test_negation() {
-        let ops = vec![
-            OpCode::OpConstant(Value::ValNumber(3.0)),
-            OpCode::OpNegate,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValNumber(-3.0)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNumber(3.0), 1);
+            chunk.write(OpCode::OpNegate, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValNumber(-3.0))));
     }
 
     #[test]
     fn test_not_operator() {
-        let ops = vec![
-            OpCode::OpFalse,
-            OpCode::OpNot,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValBool(true)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write(OpCode::OpFalse, 1);
+            chunk.write(OpCode::OpNot, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValBool(true))));
     }
 
     #[test]
     fn test_comparisons() {
-        let ops = vec![
-            OpCode::OpConstant(Value::ValNumber(5.0)),
-            OpCode::OpConstant(Value::ValNumber(3.0)),
-            OpCode::OpGreater,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValBool(true)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNumber(5.0), 1);
+            chunk.write_constant(Value::ValNumber(3.0), 1);
+            chunk.write(OpCode::OpGreater, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValBool(true))));
     }
 
     #[test]
     fn test_nil_and_boolean_push() {
-        let ops = vec![
-            OpCode::OpNil,
-            OpCode::OpTrue,
-            OpCode::OpFalse,
-            OpCode::OpReturn,
-        ];
-        let mut vm = make_vm_with_ops(ops);
-        assert_eq!(vm.run(), Some(Value::ValBool(false)));
+        let mut vm = make_vm(|chunk| {
+            chunk.write(OpCode::OpNil, 1);
+            chunk.write(OpCode::OpTrue, 1);
+            chunk.write(OpCode::OpFalse, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        assert_eq!(vm.run(), Ok(Some(Value::ValBool(false))));
+    }
+
+    #[test]
+    fn test_type_error_halts_with_line() {
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::ValNumber(1.0), 1);
+        chunk.write(OpCode::OpTrue, 2);
+        chunk.write(OpCode::OpAdd, 3);
+        chunk.write(OpCode::OpNil, 4);
+        chunk.write(OpCode::OpReturn, 4);
+        let mut vm = VirtualMachine::new(chunk);
+
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::TypeError);
+        assert_eq!(error.message, "Operands must be numbers.");
+        assert_eq!(error.line, 3);
+        assert_eq!(error.trace, vec![Frame { function: "script".to_string(), line: 3 }]);
+        assert_eq!(error.to_string(), "Operands must be numbers.\n[line 3] in script");
+        assert!(vm.stack.is_empty());
+        assert_eq!(vm.ip, 4);
+    }
+
+    #[test]
+    fn test_return_output_is_captured() {
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNumber(3.0), 1);
+            chunk.write(OpCode::OpReturn, 1);
+        })
+        .with_output(Output::Buffer(Vec::new()));
+        vm.run().unwrap();
+        assert_eq!(vm.output.captured(), Some("=> 3\n".as_bytes()));
+
+        let mut vm = make_vm(|chunk| {
+            chunk.write(OpCode::OpNil, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        })
+        .with_output(Output::Buffer(Vec::new()));
+        vm.run().unwrap();
+        assert_eq!(vm.output.captured(), Some("=> nil\n".as_bytes()));
+    }
+
+    #[test]
+    fn test_callback_output_receives_lines() {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let sink = Rc::clone(&lines);
+        let mut vm = make_vm(|chunk| {
+            chunk.write(OpCode::OpTrue, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        })
+        .with_output(Output::Callback(Box::new(move |line| sink.borrow_mut().push(line.to_string()))));
+        vm.run().unwrap();
+        assert_eq!(*lines.borrow(), vec!["=> true".to_string()]);
+        assert_eq!(vm.output.captured(), None);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut vm = make_vm(|chunk| {
+            chunk.write(OpCode::OpNegate, 1);
+            chunk.write(OpCode::OpReturn, 1);
+        });
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::StackUnderflow);
+        assert_eq!(error.line, 1);
+    }
+
+    #[test]
+    fn test_bad_bytes_are_runtime_errors() {
+        let mut vm = make_vm(|chunk| {
+            chunk.write_constant(Value::ValNil, 1);
+            chunk.code.push(99);
+            chunk.lines.push(2);
+        });
+        let error = vm.run().unwrap_err();
+        assert_eq!((error.kind, error.line), (RuntimeErrorKind::InvalidInstruction, 2));
+        assert_eq!(error.message, "Unknown opcode 99.");
+
+        // An OpConstant cut off before its index
+        let mut vm = make_vm(|chunk| chunk.write(OpCode::OpConstant, 1));
+        assert_eq!(vm.run().unwrap_err().kind, RuntimeErrorKind::InvalidInstruction);
     }
 }
@@ -0,0 +1,244 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use assignment6::compiler::Compiler;
+use assignment6::object::Heap;
+use assignment6::register::{translate, RegisterVm};
+use assignment6::virtual_machine::{Chunk, Op, Value, VirtualMachine};
+use assignment6::Output;
+
+// Tight numeric loops, where dispatch is most of the work
+const PROGRAMS: [(&str, &str); 3] = [
+    (
+        "sum",
+        "{ var total = 0; for (var i = 0; i < 100000; i = i + 1) total = total + i; print total; }",
+    ),
+    (
+        "nested",
+        "{ var n = 0; for (var i = 0; i < 300; i = i + 1) for (var j = 0; j < 300; j = j + 1) n = n + j; print n; }",
+    ),
+    (
+        "fib",
+        "for (var k = 0; k < 2000; k = k + 1) {
+           var a = 0; var b = 1;
+           for (var i = 0; i < 50; i = i + 1) { var t = a + b; a = b; b = t; }
+         }",
+    ),
+];
+
+// -O1, with or without the superinstructions -O2 adds
+fn compiler(superinstructions: bool) -> Compiler {
+    Compiler::new()
+        .with_folding(true)
+        .with_peephole(true)
+        .with_superinstructions(superinstructions)
+}
+
+fn machine(source: &str, compiler: &Compiler) -> VirtualMachine {
+    let mut vm = VirtualMachine::new(Chunk::new()).with_output(Output::Buffer(Vec::new()));
+    let chunk = compiler.compile(source, &mut vm.heap).unwrap();
+    vm.load(chunk);
+    vm
+}
+
+fn dispatches(source: &str, compiler: &Compiler) -> u64 {
+    let mut vm = machine(source, compiler);
+    let mut count = 0;
+    while !vm.is_done() {
+        vm.step().unwrap();
+        count += 1;
+    }
+    count
+}
+
+// The -O1 stack code translated for the register VM
+fn register_machine(source: &str) -> RegisterVm {
+    let mut heap = Heap::new();
+    let chunk = compiler(false).compile(source, &mut heap).unwrap();
+    let mut vm = RegisterVm::new(translate(&chunk).unwrap()).with_output(Output::Buffer(Vec::new()));
+    vm.heap = heap;
+    vm
+}
+
+fn register_dispatches(source: &str) -> u64 {
+    let mut vm = register_machine(source);
+    let mut count = 0;
+    while !vm.is_done() {
+        vm.step().unwrap();
+        count += 1;
+    }
+    count
+}
+
+// === Code layouts ===
+// Chunks used to hold a Vec<Op> whose jumps count instructions; they now
+// hold a byte stream whose jumps count bytes
+trait Layout {
+    // The instruction at `ip` and how far it moves ip on
+    fn fetch(&self, ip: usize) -> Option<(Op, usize)>;
+}
+
+impl Layout for [Op] {
+    #[inline(always)]
+    fn fetch(&self, ip: usize) -> Option<(Op, usize)> {
+        self.get(ip).map(|&op| (op, 1))
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Layout for Bytes<'_> {
+    #[inline(always)]
+    fn fetch(&self, ip: usize) -> Option<(Op, usize)> {
+        Op::decode(self.0, ip).map(|op| (op, op.size()))
+    }
+}
+
+// The chunk's code as the array of Op it used to be
+fn op_array(chunk: &Chunk) -> Vec<Op> {
+    let listing = chunk.listing();
+    let relative = |i: usize, op: Op| match op {
+        Op::OpJump(target) if target <= i => Op::OpLoop(i + 1 - target),
+        Op::OpJump(target) => Op::OpJump(target - (i + 1)),
+        Op::OpJumpIfFalse(target) => Op::OpJumpIfFalse(target - (i + 1)),
+        Op::OpJumpIfTrue(target) => Op::OpJumpIfTrue(target - (i + 1)),
+        op => op,
+    };
+    listing.code.iter().enumerate().map(|(i, &op)| relative(i, op)).collect()
+}
+
+fn number(value: Option<Value>) -> f64 {
+    match value {
+        Some(Value::ValNumber(n)) => n,
+        other => panic!("expected a number, got {:?}", other),
+    }
+}
+
+// A dispatch loop for the numbers and locals PROGRAMS use, without the VM's
+// hooks for tracing and undo. Returns what the program printed.
+fn run_layout<L: Layout + ?Sized>(code: &L, constants: &[Value]) -> Vec<f64> {
+    let mut stack: Vec<Value> = Vec::new();
+    let mut printed = Vec::new();
+    let mut ip = 0;
+    while let Some((op, size)) = code.fetch(ip) {
+        ip += size;
+        match op {
+            Op::OpConstant(k) => stack.push(constants[k]),
+            Op::OpNil => stack.push(Value::ValNil),
+            Op::OpPop => {
+                stack.pop();
+            }
+            Op::OpGetLocal(slot) => stack.push(stack[slot]),
+            Op::OpSetLocal(slot) => stack[slot] = *stack.last().unwrap(),
+            Op::OpGetLocals(a, b) => {
+                stack.push(stack[a]);
+                stack.push(stack[b]);
+            }
+            Op::OpAdd => {
+                let b = number(stack.pop());
+                let a = number(stack.pop());
+                stack.push(Value::ValNumber(a + b));
+            }
+            Op::OpLess => {
+                let b = number(stack.pop());
+                let a = number(stack.pop());
+                stack.push(Value::ValBool(a < b));
+            }
+            Op::OpAddConstant(k) => {
+                let a = number(stack.pop());
+                stack.push(Value::ValNumber(a + number(Some(constants[k]))));
+            }
+            Op::OpLessLocalConst(slot, k) => {
+                let less = number(Some(stack[slot])) < number(Some(constants[k]));
+                stack.push(Value::ValBool(less));
+            }
+            Op::OpJump(offset) => ip += offset,
+            Op::OpJumpIfFalse(offset) => {
+                if matches!(stack.last(), Some(Value::ValBool(false) | Value::ValNil)) {
+                    ip += offset;
+                }
+            }
+            Op::OpLoop(offset) => ip -= offset,
+            Op::OpPrint => printed.push(number(stack.pop())),
+            Op::OpReturn => break,
+            op => panic!("{} isn't used by the benchmark programs", op.name()),
+        }
+    }
+    printed
+}
+
+fn dispatch(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        let plain = dispatches(source, &compiler(false));
+        let fused = dispatches(source, &compiler(true));
+        println!(
+            "{}: {} instructions dispatched at -O1, {} at -O2 ({:.1}% fewer)",
+            name,
+            plain,
+            fused,
+            (plain - fused) as f64 * 100.0 / plain as f64
+        );
+        let registers = register_dispatches(source);
+        println!(
+            "{}: {} register instructions dispatched ({:.1}% fewer than -O1)",
+            name,
+            registers,
+            (plain - registers) as f64 * 100.0 / plain as f64
+        );
+        // What the same code took when chunks held a Vec<Op>
+        let chunk = compiler(true).compile(source, &mut Heap::new()).unwrap();
+        let ops = op_array(&chunk);
+        println!(
+            "{}: {} instructions in {} bytes of code, {} as an array of Op",
+            name,
+            ops.len(),
+            chunk.code.len(),
+            ops.len() * size_of::<Op>()
+        );
+
+        let mut group = c.benchmark_group(name);
+        for (level, superinstructions) in [("O1", false), ("O2", true)] {
+            let compiler = compiler(superinstructions);
+            group.bench_function(level, |b| {
+                b.iter_batched(
+                    || machine(source, &compiler),
+                    |mut vm| vm.run().unwrap(),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+        // The two layouts under one dispatch loop, so only the fetch differs
+        for (level, superinstructions) in [("O1", false), ("O2", true)] {
+            let chunk = compiler(superinstructions).compile(source, &mut Heap::new()).unwrap();
+            let ops = op_array(&chunk);
+            let constants: Vec<Value> = (0..chunk.constants.len()).map(|k| chunk.constant(k)).collect();
+            let bytes = Bytes(&chunk.code);
+            assert_eq!(run_layout(ops.as_slice(), &constants), run_layout(&bytes, &constants));
+            group.bench_function(format!("{}/ops", level), |b| {
+                b.iter(|| run_layout(black_box(ops.as_slice()), &constants))
+            });
+            group.bench_function(format!("{}/bytes", level), |b| {
+                b.iter(|| run_layout(black_box(&bytes), &constants))
+            });
+        }
+        group.bench_function("register", |b| {
+            b.iter_batched(
+                || register_machine(source),
+                |mut vm| vm.run().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        #[cfg(feature = "jit")]
+        group.bench_function("jit", |b| {
+            let compiler = compiler(true);
+            b.iter_batched(
+                || machine(source, &compiler).with_jit(assignment6::jit::DEFAULT_THRESHOLD),
+                |mut vm| vm.run().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
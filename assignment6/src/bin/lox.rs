@@ -0,0 +1,376 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::process::ExitCode;
+
+use assignment6::compiler::Compiler;
+use assignment6::debug::{dump_tokens, dump_tokens_json, scan_diagnostics};
+use assignment6::loxc::{self, MAGIC};
+use assignment6::object::Heap;
+use assignment6::virtual_machine::{Chunk, VirtualMachine};
+use assignment6::profile::DEFAULT_SAMPLE_INTERVAL;
+use assignment6::register::{self, RegisterChunk, RegisterVm};
+use assignment6::coverage;
+use assignment6::{Coverage, Output, Profile, Sampler, Trace, VmConfig};
+use lox_diagnostics::render_all;
+
+// Exit codes from BSD sysexits.h
+const EX_USAGE: u8 = 64;
+const EX_DATAERR: u8 = 65;
+const EX_SOFTWARE: u8 = 70;
+const EX_IOERR: u8 = 74;
+
+const USAGE: &str = "\
+Usage: lox <command> [options] <file>
+
+Commands:
+  run [options] <file>     run a .lox script or a compiled .loxc chunk
+  tokens [--json] <file>   list the tokens the scanner produces
+  disasm [--registers] <file>
+                           disassemble a script or a compiled chunk
+  compile <file> -o <out>  compile a script to a .loxc chunk
+  check <file>             compile only, reporting any errors
+  cover [-o <out.info>] <file>...
+                           run each file, then summarise line coverage on
+                           stderr and optionally write it in lcov format
+
+Optimization, for any command that compiles:
+  -O0                      compile the program as written (default)
+  -O1                      fold constants and run the peephole optimizer
+  -O2                      as -O1, then fuse common sequences into superinstructions
+
+Run options:
+  --registers              run on the register VM, translating the stack code
+  --trace[=json]           trace each instruction to stderr
+  --profile                report time per opcode, line and function to stderr
+  --profile-csv <out>      also write the profile as CSV
+  --sample <out>           write sampled call stacks in folded format for flamegraphs
+  --sample-every <n>       instructions between samples (default 1000)
+
+Exit status: 0 on success, 64 usage, 65 compile error, 70 runtime error, 74 I/O error";
+
+// Every failure path reports to stderr and carries its exit code back here
+type CliResult = Result<(), u8>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match dispatch(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+fn dispatch(args: &[String]) -> CliResult {
+    let Some((command, rest)) = args.split_first() else {
+        return Err(usage("missing command"));
+    };
+    let (compiler, rest) = optimization(rest)?;
+    let (rest, compiler) = (rest.as_slice(), &compiler);
+    match command.as_str() {
+        "run" => run(rest, compiler),
+        "tokens" => match rest {
+            [flag, path] if flag == "--json" => tokens(path, true),
+            [path] => tokens(path, false),
+            _ => Err(usage("tokens takes an optional --json and one file")),
+        },
+        "disasm" => match rest {
+            [flag, path] if flag == "--registers" => disasm(path, compiler, true),
+            [path] => disasm(path, compiler, false),
+            _ => Err(usage("disasm takes an optional --registers and one file")),
+        },
+        "compile" => match rest {
+            [path, flag, out] if flag == "-o" => compile(path, out, compiler),
+            [flag, out, path] if flag == "-o" => compile(path, out, compiler),
+            _ => Err(usage("compile needs a file and -o <out>")),
+        },
+        "check" => check(single_path(rest)?, compiler),
+        "cover" => cover(rest, compiler),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(usage(&format!("unknown command '{}'", other))),
+    }
+}
+
+fn usage(problem: &str) -> u8 {
+    eprintln!("lox: {}\n\n{}", problem, USAGE);
+    EX_USAGE
+}
+
+// Takes the -O flags out of `rest`; the last one given wins
+fn optimization(rest: &[String]) -> Result<(Compiler, Vec<String>), u8> {
+    let mut level = 0;
+    let mut others = Vec::new();
+    for arg in rest {
+        match arg.as_str() {
+            "-O0" => level = 0,
+            "-O1" => level = 1,
+            "-O2" => level = 2,
+            flag if flag.starts_with("-O") => {
+                return Err(usage(&format!("unknown optimization level '{}'", flag)));
+            }
+            _ => others.push(arg.clone()),
+        }
+    }
+    let compiler = Compiler::new()
+        .with_folding(level >= 1)
+        .with_peephole(level >= 1)
+        .with_superinstructions(level >= 2);
+    Ok((compiler, others))
+}
+
+fn single_path(rest: &[String]) -> Result<&str, u8> {
+    match rest {
+        [path] => Ok(path),
+        _ => Err(usage("expected exactly one file")),
+    }
+}
+
+// === Commands ===
+fn run(rest: &[String], compiler: &Compiler) -> CliResult {
+    let mut config = VmConfig::default();
+    let mut csv = None;
+    let mut folded = None;
+    let mut interval = DEFAULT_SAMPLE_INTERVAL;
+    let mut registers = false;
+    let mut path = None;
+    let mut args = rest.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--registers" => registers = true,
+            "--trace" => config.trace = Some(Trace::text(Output::Writer(Box::new(io::stderr())))),
+            "--trace=json" => config.trace = Some(Trace::json(Output::Writer(Box::new(io::stderr())))),
+            "--profile" => config.profile = Some(Profile::new()),
+            "--profile-csv" => {
+                let Some(out) = args.next() else {
+                    return Err(usage("--profile-csv needs an output file"));
+                };
+                csv = Some(out);
+                config.profile = Some(Profile::new());
+            }
+            "--sample" => {
+                let Some(out) = args.next() else {
+                    return Err(usage("--sample needs an output file"));
+                };
+                folded = Some(out);
+            }
+            "--sample-every" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => interval = n,
+                _ => return Err(usage("--sample-every needs a positive number")),
+            },
+            flag if flag.starts_with('-') => return Err(usage(&format!("unknown run option '{}'", flag))),
+            file if path.is_none() => path = Some(file),
+            _ => return Err(usage("expected exactly one file")),
+        }
+    }
+    let Some(path) = path else {
+        return Err(usage("expected exactly one file"));
+    };
+    if folded.is_some() {
+        config.sampler = Some(Sampler::new(interval));
+    }
+    if registers {
+        // The register VM has none of the stack VM's instrumentation
+        if config.trace.is_some() || config.profile.is_some() || folded.is_some() {
+            return Err(usage("--registers can't be combined with tracing, profiling or sampling"));
+        }
+        return run_registers(path, compiler);
+    }
+
+    let mut vm = VirtualMachine::new(Chunk::new()).with_config(config);
+    // Loops stay interpreted while tracing, profiling or sampling
+    #[cfg(feature = "jit")]
+    {
+        vm = vm.with_jit(assignment6::jit::DEFAULT_THRESHOLD);
+    }
+    let chunk = load(path, &mut vm.heap, compiler)?;
+    vm.load(chunk);
+    let result = vm.run().map_err(|error| {
+        eprintln!("{}", error);
+        EX_SOFTWARE
+    });
+
+    // Reported even when the run fails, since that may be what's being chased
+    if let Some(profile) = &vm.config.profile {
+        eprint!("{}", profile.report());
+        if let Some(out) = csv {
+            write_file(out, profile.to_csv())?;
+        }
+    }
+    if let (Some(sampler), Some(out)) = (&vm.config.sampler, folded) {
+        write_file(out, sampler.folded())?;
+    }
+    result
+}
+
+fn run_registers(path: &str, compiler: &Compiler) -> CliResult {
+    let mut heap = Heap::new();
+    let chunk = load(path, &mut heap, compiler)?;
+    let mut vm = RegisterVm::new(translate(path, &chunk)?);
+    vm.heap = heap;
+    vm.run().map_err(|error| {
+        eprintln!("{}", error);
+        EX_SOFTWARE
+    })
+}
+
+fn cover(rest: &[String], compiler: &Compiler) -> CliResult {
+    let mut out = None;
+    let mut paths = Vec::new();
+    let mut args = rest.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(file) => out = Some(file),
+                None => return Err(usage("-o needs an output file")),
+            },
+            flag if flag.starts_with('-') => return Err(usage(&format!("unknown cover option '{}'", flag))),
+            path => paths.push(path),
+        }
+    }
+    if paths.is_empty() {
+        return Err(usage("cover needs at least one file"));
+    }
+
+    // A failing script still reports what it reached; the first failure's
+    // status is returned
+    let mut status = Ok(());
+    let mut files = Vec::new();
+    for path in paths {
+        let config = VmConfig {
+            coverage: Some(Coverage::new()),
+            ..VmConfig::default()
+        };
+        let mut vm = VirtualMachine::new(Chunk::new()).with_config(config);
+        let (chunk, source) = match load_with_source(path, &mut vm.heap, compiler) {
+            Ok(loaded) => loaded,
+            Err(code) => {
+                status = status.and(Err(code));
+                continue;
+            }
+        };
+        vm.load(chunk);
+        if let Err(error) = vm.run() {
+            eprintln!("{}", error);
+            status = status.and(Err(EX_SOFTWARE));
+        }
+        if let Some(coverage) = &vm.config.coverage {
+            files.push(coverage.lines(path, &vm.chunk, source.as_deref()));
+        }
+    }
+
+    eprint!("{}", coverage::summary(&files));
+    if let Some(out) = out {
+        write_file(out, coverage::lcov(&files))?;
+    }
+    status
+}
+
+fn tokens(path: &str, json: bool) -> CliResult {
+    let source = read_source(path)?;
+    if json {
+        print!("{}", dump_tokens_json(&source));
+    } else {
+        print!("{}", dump_tokens(&source));
+    }
+
+    // Still a compile error, but only after the full listing is out
+    let diagnostics = scan_diagnostics(&source);
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        eprint!("{}", render_all(&diagnostics, &source, path));
+        Err(EX_DATAERR)
+    }
+}
+
+fn disasm(path: &str, compiler: &Compiler, registers: bool) -> CliResult {
+    let mut heap = Heap::new();
+    let chunk = load(path, &mut heap, compiler)?;
+    if registers {
+        print!("{}", translate(path, &chunk)?.disassemble(path, &heap));
+    } else {
+        print!("{}", chunk.disassemble(path, &heap));
+    }
+    Ok(())
+}
+
+fn compile(path: &str, out: &str, compiler: &Compiler) -> CliResult {
+    let mut heap = Heap::new();
+    let chunk = compile_source(path, &mut heap, compiler)?;
+    write_file(out, loxc::encode(&chunk, &heap))
+}
+
+fn check(path: &str, compiler: &Compiler) -> CliResult {
+    compile_source(path, &mut Heap::new(), compiler).map(|_| ())
+}
+
+// === Loading ===
+// Loaded chunks have been verified, so this only fails on a bug
+fn translate(path: &str, chunk: &Chunk) -> Result<RegisterChunk, u8> {
+    register::translate(chunk).map_err(|error| {
+        eprintln!("lox: could not translate '{}' for the register VM: {}", path, error);
+        EX_SOFTWARE
+    })
+}
+
+fn read(path: &str) -> Result<Vec<u8>, u8> {
+    fs::read(path).map_err(|error| {
+        eprintln!("lox: could not read '{}': {}", path, error);
+        EX_IOERR
+    })
+}
+
+fn write_file(path: &str, contents: impl AsRef<[u8]>) -> CliResult {
+    fs::write(path, contents).map_err(|error| {
+        eprintln!("lox: could not write '{}': {}", path, error);
+        EX_IOERR
+    })
+}
+
+fn read_source(path: &str) -> Result<String, u8> {
+    String::from_utf8(read(path)?).map_err(|_| {
+        eprintln!("lox: '{}' is not a UTF-8 source file", path);
+        EX_DATAERR
+    })
+}
+
+fn compile_source(path: &str, heap: &mut Heap, compiler: &Compiler) -> Result<Chunk, u8> {
+    let source = read_source(path)?;
+    compile_text(path, &source, heap, compiler)
+}
+
+fn compile_text(path: &str, source: &str, heap: &mut Heap, compiler: &Compiler) -> Result<Chunk, u8> {
+    compiler.compile(source, heap).map_err(|diagnostics| {
+        eprint!("{}", render_all(&diagnostics, source, path));
+        EX_DATAERR
+    })
+}
+
+// Compiled chunks are recognised by their magic number, anything else is source
+fn load(path: &str, heap: &mut Heap, compiler: &Compiler) -> Result<Chunk, u8> {
+    load_with_source(path, heap, compiler).map(|(chunk, _)| chunk)
+}
+
+// The source comes back too when there is one, i.e. not for a .loxc chunk
+fn load_with_source(
+    path: &str,
+    heap: &mut Heap,
+    compiler: &Compiler,
+) -> Result<(Chunk, Option<String>), u8> {
+    let bytes = read(path)?;
+    if !bytes.starts_with(MAGIC) {
+        let source = String::from_utf8(bytes).map_err(|_| {
+            eprintln!("lox: '{}' is neither a compiled chunk nor UTF-8 source", path);
+            EX_DATAERR
+        })?;
+        let chunk = compile_text(path, &source, heap, compiler)?;
+        return Ok((chunk, Some(source)));
+    }
+    let chunk = loxc::decode(&bytes, heap).map_err(|error| {
+        eprintln!("lox: invalid chunk '{}': {}", path, error);
+        EX_DATAERR
+    })?;
+    Ok((chunk, None))
+}
@@ -0,0 +1,256 @@
+use crate::virtual_machine::{Chunk, Op};
+
+// === Instruction Encoding ===
+// A chunk's code is a dense byte stream: each instruction is its opcode byte
+// followed by its operands, every operand a little-endian u16. OpPop is one
+// byte, OpConstant three and OpLessLocalConst five. Jump operands count bytes
+// from the end of the jump. The opcode numbers are also the ones .loxc files
+// use, so changing them needs a new file format version.
+
+// Largest operand an instruction can hold
+pub const OPERAND_MAX: usize = u16::MAX as usize;
+
+impl Op {
+    pub fn opcode(self) -> u8 {
+        match self {
+            Op::OpConstant(_) => 0,
+            Op::OpDefineGlobal(_) => 1,
+            Op::OpGetGlobal(_) => 2,
+            Op::OpSetGlobal(_) => 3,
+            Op::OpGetLocal(_) => 4,
+            Op::OpSetLocal(_) => 5,
+            Op::OpAdd => 6,
+            Op::OpSubtract => 7,
+            Op::OpMultiply => 8,
+            Op::OpDivide => 9,
+            Op::OpModulo => 10,
+            Op::OpNegate => 11,
+            Op::OpNot => 12,
+            Op::OpEqual => 13,
+            Op::OpGreater => 14,
+            Op::OpLess => 15,
+            Op::OpNil => 16,
+            Op::OpTrue => 17,
+            Op::OpFalse => 18,
+            Op::OpPrint => 19,
+            Op::OpPop => 20,
+            Op::OpReturn => 21,
+            Op::OpJump(_) => 22,
+            Op::OpJumpIfFalse(_) => 23,
+            Op::OpJumpIfTrue(_) => 24,
+            Op::OpLoop(_) => 25,
+            Op::OpAddConstant(_) => 26,
+            Op::OpGetLocals(..) => 27,
+            Op::OpLessLocalConst(..) => 28,
+        }
+    }
+
+    // Operands in the order they are encoded
+    fn operands(self) -> [Option<usize>; 2] {
+        match self {
+            Op::OpConstant(operand)
+            | Op::OpDefineGlobal(operand)
+            | Op::OpGetGlobal(operand)
+            | Op::OpSetGlobal(operand)
+            | Op::OpGetLocal(operand)
+            | Op::OpSetLocal(operand)
+            | Op::OpJump(operand)
+            | Op::OpJumpIfFalse(operand)
+            | Op::OpJumpIfTrue(operand)
+            | Op::OpLoop(operand)
+            | Op::OpAddConstant(operand) => [Some(operand), None],
+            Op::OpGetLocals(a, b) | Op::OpLessLocalConst(a, b) => [Some(a), Some(b)],
+            _ => [None, None],
+        }
+    }
+
+    // Bytes the instruction takes up, opcode included
+    #[inline]
+    pub fn size(self) -> usize {
+        match self {
+            Op::OpGetLocals(..) | Op::OpLessLocalConst(..) => 5,
+            Op::OpConstant(_)
+            | Op::OpDefineGlobal(_)
+            | Op::OpGetGlobal(_)
+            | Op::OpSetGlobal(_)
+            | Op::OpGetLocal(_)
+            | Op::OpSetLocal(_)
+            | Op::OpJump(_)
+            | Op::OpJumpIfFalse(_)
+            | Op::OpJumpIfTrue(_)
+            | Op::OpLoop(_)
+            | Op::OpAddConstant(_) => 3,
+            _ => 1,
+        }
+    }
+
+    // Appends the instruction's bytes. Operands must be at most OPERAND_MAX;
+    // the compiler reports anything larger as an error before it gets here.
+    pub fn encode(self, code: &mut Vec<u8>) {
+        code.push(self.opcode());
+        for operand in self.operands().into_iter().flatten() {
+            let operand = u16::try_from(operand).expect("operand too large to encode");
+            code.extend_from_slice(&operand.to_le_bytes());
+        }
+    }
+
+    // The instruction starting at `offset`, or None for an unknown opcode or
+    // one cut short by the end of the code. Always inlined so the VM's match
+    // on the result folds into this one instead of dispatching twice.
+    #[inline(always)]
+    pub fn decode(code: &[u8], offset: usize) -> Option<Op> {
+        let operand = |low: u8, high: u8| u16::from_le_bytes([low, high]) as usize;
+        // Each pattern checks the length the instruction needs just once
+        let op = match *code.get(offset..)? {
+            [0, a, b, ..] => Op::OpConstant(operand(a, b)),
+            [1, a, b, ..] => Op::OpDefineGlobal(operand(a, b)),
+            [2, a, b, ..] => Op::OpGetGlobal(operand(a, b)),
+            [3, a, b, ..] => Op::OpSetGlobal(operand(a, b)),
+            [4, a, b, ..] => Op::OpGetLocal(operand(a, b)),
+            [5, a, b, ..] => Op::OpSetLocal(operand(a, b)),
+            [6, ..] => Op::OpAdd,
+            [7, ..] => Op::OpSubtract,
+            [8, ..] => Op::OpMultiply,
+            [9, ..] => Op::OpDivide,
+            [10, ..] => Op::OpModulo,
+            [11, ..] => Op::OpNegate,
+            [12, ..] => Op::OpNot,
+            [13, ..] => Op::OpEqual,
+            [14, ..] => Op::OpGreater,
+            [15, ..] => Op::OpLess,
+            [16, ..] => Op::OpNil,
+            [17, ..] => Op::OpTrue,
+            [18, ..] => Op::OpFalse,
+            [19, ..] => Op::OpPrint,
+            [20, ..] => Op::OpPop,
+            [21, ..] => Op::OpReturn,
+            [22, a, b, ..] => Op::OpJump(operand(a, b)),
+            [23, a, b, ..] => Op::OpJumpIfFalse(operand(a, b)),
+            [24, a, b, ..] => Op::OpJumpIfTrue(operand(a, b)),
+            [25, a, b, ..] => Op::OpLoop(operand(a, b)),
+            [26, a, b, ..] => Op::OpAddConstant(operand(a, b)),
+            [27, a, b, c, d, ..] => Op::OpGetLocals(operand(a, b), operand(c, d)),
+            [28, a, b, c, d, ..] => Op::OpLessLocalConst(operand(a, b), operand(c, d)),
+            _ => return None,
+        };
+        Some(op)
+    }
+}
+
+// === Listings ===
+// For passes that insert, remove or reorder instructions, which is awkward
+// on bytes: one entry per instruction, each jump's operand replaced by the
+// index of the instruction it lands on (the list's length for the end of the
+// code). Backward jumps are OpJump too; `assemble` makes them OpLoop again.
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    pub code: Vec<Op>,
+    pub lines: Vec<usize>,
+}
+
+impl Chunk {
+    // Decodes every instruction up to the first byte that isn't one. A jump
+    // into the middle of an instruction gets usize::MAX as its target.
+    pub fn listing(&self) -> Listing {
+        let instructions: Vec<(usize, Op)> = self.instructions().collect();
+        let mut index = vec![usize::MAX; self.code.len() + 1];
+        for (i, &(offset, _)) in instructions.iter().enumerate() {
+            index[offset] = i;
+        }
+        index[self.code.len()] = instructions.len();
+
+        let mut listing = Listing::default();
+        for (offset, op) in instructions {
+            let target = op
+                .jump_target(offset)
+                .map(|target| index.get(target).copied().unwrap_or(usize::MAX));
+            listing.code.push(match (op, target) {
+                (Op::OpJumpIfFalse(_), Some(target)) => Op::OpJumpIfFalse(target),
+                (Op::OpJumpIfTrue(_), Some(target)) => Op::OpJumpIfTrue(target),
+                (_, Some(target)) => Op::OpJump(target),
+                (op, None) => op,
+            });
+            listing.lines.push(self.lines[offset]);
+        }
+        listing
+    }
+
+    // Replaces the code with the listing's, encoding its jumps as offsets
+    pub fn assemble(&mut self, listing: &Listing) {
+        let mut offsets = Vec::with_capacity(listing.code.len() + 1);
+        let mut end = 0;
+        for op in &listing.code {
+            offsets.push(end);
+            end += op.size();
+        }
+        offsets.push(end);
+
+        self.code.clear();
+        self.lines.clear();
+        for (i, (&op, &line)) in listing.code.iter().zip(&listing.lines).enumerate() {
+            let after = offsets[i + 1];
+            let op = match op {
+                Op::OpJump(target) if target <= i => Op::OpLoop(after - offsets[target]),
+                Op::OpJump(target) => Op::OpJump(offsets[target] - after),
+                Op::OpJumpIfFalse(target) => Op::OpJumpIfFalse(offsets[target] - after),
+                Op::OpJumpIfTrue(target) => Op::OpJumpIfTrue(offsets[target] - after),
+                op => op,
+            };
+            self.write(op, line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::object::Heap;
+
+    #[test]
+    fn test_encoding_round_trips() {
+        let ops = [
+            Op::OpNil,
+            Op::OpConstant(0),
+            Op::OpGetLocal(OPERAND_MAX),
+            Op::OpLoop(258),
+            Op::OpGetLocals(1, 2),
+            Op::OpLessLocalConst(3, 513),
+        ];
+        let mut code = Vec::new();
+        for op in ops {
+            op.encode(&mut code);
+        }
+        assert_eq!(code.len(), 1 + 3 + 3 + 3 + 5 + 5);
+        assert_eq!(code[7..10], [25, 2, 1]);
+
+        let mut offset = 0;
+        for op in ops {
+            let decoded = Op::decode(&code, offset).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", op));
+            offset += decoded.size();
+        }
+        assert!(Op::decode(&code, offset).is_none());
+        assert!(Op::decode(&code[..code.len() - 1], offset - 5).is_none());
+        assert!(Op::decode(&[200], 0).is_none());
+    }
+
+    #[test]
+    fn test_listing_round_trips() {
+        let source = "var a = 0; while (a < 3) { if (a == 1 or a == 2) print a; a = a + 1; }";
+        let chunk = Compiler::new().compile(source, &mut Heap::new()).unwrap();
+        let listing = chunk.listing();
+        assert_eq!(listing.code.len(), chunk.instructions().count());
+        // The loop back to the condition, as an index
+        let back = listing.code.iter().rev().find_map(|op| match op {
+            Op::OpJump(target) => Some(*target),
+            _ => None,
+        });
+        assert!(matches!(listing.code[back.unwrap()], Op::OpGetGlobal(_)));
+
+        let mut copy = chunk.clone();
+        copy.assemble(&listing);
+        assert_eq!(copy.code, chunk.code);
+        assert_eq!(copy.lines, chunk.lines);
+    }
+}
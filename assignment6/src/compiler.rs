@@ -1,43 +1,994 @@
-use crate::virtual_machine::{Chunk, Op, Value, VirtualMachine};
+use lox_diagnostics::{codes, render_all, Diagnostic};
 
+use crate::bytecode::OPERAND_MAX;
+use crate::object::Heap;
+use crate::peephole;
+use crate::scanner::{Scanner, Token, TokenType};
+use crate::virtual_machine::{binary, unary, Chunk, InterpretResult, Op, Value, VirtualMachine};
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Precedence {
+    PrecNone,
+    PrecAssignment, // =
+    PrecOr,         // or
+    PrecAnd,        // and
+    PrecEquality,   // == !=
+    PrecComparison, // < > <= >=
+    PrecTerm,       // + -
+    PrecFactor,     // * /
+    PrecUnary,      // ! -
+    PrecCall,       // . ()
+    PrecPrimary,
+}
+
+impl Precedence {
+    // The next-higher level, used for left-associative binary operators
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::PrecNone => Precedence::PrecAssignment,
+            Precedence::PrecAssignment => Precedence::PrecOr,
+            Precedence::PrecOr => Precedence::PrecAnd,
+            Precedence::PrecAnd => Precedence::PrecEquality,
+            Precedence::PrecEquality => Precedence::PrecComparison,
+            Precedence::PrecComparison => Precedence::PrecTerm,
+            Precedence::PrecTerm => Precedence::PrecFactor,
+            Precedence::PrecFactor => Precedence::PrecUnary,
+            Precedence::PrecUnary => Precedence::PrecCall,
+            Precedence::PrecCall | Precedence::PrecPrimary => Precedence::PrecPrimary,
+        }
+    }
+}
+
+type ParseFn<'heap> = fn(&mut Parser<'heap>, bool);
+
+// Pratt parser table entry for one token type
 #[derive(Clone)]
-pub struct ParseRule {
-    // Placeholder for potential parsing rules
+struct ParseRule<'heap> {
+    prefix: Option<ParseFn<'heap>>,
+    infix: Option<ParseFn<'heap>>,
+    precedence: Precedence,
 }
 
-impl ParseRule {
-    pub fn new() -> Self {
-        Self {}
+impl<'heap> ParseRule<'heap> {
+    fn new(
+        prefix: Option<ParseFn<'heap>>,
+        infix: Option<ParseFn<'heap>>,
+        precedence: Precedence,
+    ) -> Self {
+        Self {
+            prefix,
+            infix,
+            precedence,
+        }
     }
 }
 
-pub struct Compiler;
+fn get_rule<'heap>(token_type: &TokenType) -> ParseRule<'heap> {
+    use Precedence::*;
+    match token_type {
+        TokenType::TokenLeftParen => ParseRule::new(Some(Parser::grouping), None, PrecNone),
+        TokenType::TokenMinus => {
+            ParseRule::new(Some(Parser::unary), Some(Parser::binary), PrecTerm)
+        }
+        TokenType::TokenPlus => ParseRule::new(None, Some(Parser::binary), PrecTerm),
+        TokenType::TokenSlash | TokenType::TokenStar => {
+            ParseRule::new(None, Some(Parser::binary), PrecFactor)
+        }
+        TokenType::TokenNot => ParseRule::new(Some(Parser::unary), None, PrecNone),
+        TokenType::TokenNotEqual | TokenType::TokenEqualEqual => {
+            ParseRule::new(None, Some(Parser::binary), PrecEquality)
+        }
+        TokenType::TokenGreater
+        | TokenType::TokenGreaterEqual
+        | TokenType::TokenLess
+        | TokenType::TokenLessEqual => ParseRule::new(None, Some(Parser::binary), PrecComparison),
+        TokenType::TokenAnd => ParseRule::new(None, Some(Parser::and), PrecAnd),
+        TokenType::TokenOr => ParseRule::new(None, Some(Parser::or), PrecOr),
+        TokenType::TokenIdentifier => ParseRule::new(Some(Parser::variable), None, PrecNone),
+        TokenType::TokenString => ParseRule::new(Some(Parser::string), None, PrecNone),
+        TokenType::TokenNumber => ParseRule::new(Some(Parser::number), None, PrecNone),
+        TokenType::TokenTrue | TokenType::TokenFalse | TokenType::TokenNil => {
+            ParseRule::new(Some(Parser::literal), None, PrecNone)
+        }
+        _ => ParseRule::new(None, None, PrecNone),
+    }
+}
+
+// A local variable slot; depth is None until its initializer has run
+struct Local {
+    name: Vec<u8>,
+    depth: Option<usize>,
+}
+
+// Single-pass compiler state: scans on demand and emits straight into the chunk
+struct Parser<'heap> {
+    scanner: Scanner,
+    heap: &'heap mut Heap, // string constants are interned here
+    current: Token,
+    previous: Token,
+    chunk: Chunk,
+    diagnostics: Vec<Diagnostic>,
+    panic_mode: bool, // set after an error until the next statement boundary
+    locals: Vec<Local>,
+    scope_depth: usize,
+    repl: bool,
+    fold: bool,
+    block_start: usize, // latest offset a jump can land on; folding stays after it
+    starts: Vec<usize>, // offset of each instruction emitted so far
+}
+
+impl<'heap> Parser<'heap> {
+    fn new(source: &str, heap: &'heap mut Heap, compiler: &Compiler) -> Self {
+        // Placeholder until the first advance() scans a real token
+        let start = Token {
+            token_type: TokenType::TokenEof,
+            value: Vec::new(),
+            length: 0,
+            line: 1,
+            column: 1,
+            offset: 0,
+        };
+        Self {
+            scanner: Scanner::init_scanner(source),
+            heap,
+            current: start.clone(),
+            previous: start,
+            chunk: Chunk::new(),
+            diagnostics: Vec::new(),
+            panic_mode: false,
+            locals: Vec::new(),
+            scope_depth: 0,
+            repl: compiler.repl,
+            fold: compiler.fold,
+            block_start: 0,
+            starts: Vec::new(),
+        }
+    }
+
+    // === Token handling ===
+    fn advance(&mut self) {
+        self.previous = self.current.clone();
+        loop {
+            self.current = self.scanner.scan_token();
+            if self.current.token_type != TokenType::TokenError {
+                break;
+            }
+            let message = String::from_utf8_lossy(&self.current.value).into_owned();
+            self.error_at_current(codes::INVALID_TOKEN, &message);
+        }
+    }
+
+    fn consume(&mut self, token_type: TokenType, message: &str) {
+        if self.current.token_type == token_type {
+            self.advance();
+            return;
+        }
+        self.error_at_current(codes::EXPECTED_TOKEN, message);
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        self.current.token_type == token_type
+    }
+
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    // === Error reporting ===
+    // While in panic mode further errors are dropped; they are usually
+    // fallout from the first one.
+    fn report(&mut self, diagnostic: Diagnostic) {
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn error_at(&mut self, token: &Token, code: &'static str, message: &str) {
+        let mut diagnostic = Diagnostic::error(code, message, token.span());
+        if token.token_type == TokenType::TokenEof {
+            diagnostic = diagnostic.with_help("the input ended before the statement was complete");
+        }
+        self.report(diagnostic);
+    }
+
+    fn error(&mut self, code: &'static str, message: &str) {
+        let token = self.previous.clone();
+        self.error_at(&token, code, message);
+    }
+
+    fn error_at_current(&mut self, code: &'static str, message: &str) {
+        let token = self.current.clone();
+        self.error_at(&token, code, message);
+    }
+
+    // === Emitting ===
+    fn emit(&mut self, op: Op) {
+        if self.fold && self.fold_constant(op) {
+            return;
+        }
+        self.write(op, self.previous.line);
+    }
+
+    fn write(&mut self, op: Op, line: usize) {
+        self.starts.push(self.chunk.code.len());
+        self.chunk.write(op, line);
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let index = self.make_constant(value);
+        self.emit(Op::OpConstant(index));
+    }
+
+    // Operands are u16s, so past that many constants there's no index to use
+    fn make_constant(&mut self, value: Value) -> usize {
+        let index = self.chunk.add_constant(value);
+        if index > OPERAND_MAX {
+            self.error(codes::LIMIT_EXCEEDED, "Too many constants in one chunk.");
+            return 0;
+        }
+        index
+    }
+
+    // Emits a forward jump to be patched once its target is known
+    fn emit_jump(&mut self, jump: fn(usize) -> Op) -> usize {
+        self.emit(jump(0));
+        self.chunk.code.len() - jump(0).size()
+    }
+
+    fn patch_jump(&mut self, at: usize) {
+        let op = self.chunk.op_at(at);
+        let mut offset = self.chunk.code.len() - at - op.size();
+        if offset > OPERAND_MAX {
+            self.error(codes::LIMIT_EXCEEDED, "Too much code to jump over.");
+            offset = 0;
+        }
+        let patched = match op {
+            Op::OpJump(_) => Op::OpJump(offset),
+            Op::OpJumpIfFalse(_) => Op::OpJumpIfFalse(offset),
+            Op::OpJumpIfTrue(_) => Op::OpJumpIfTrue(offset),
+            op => unreachable!("patching {:?}, which is not a forward jump", op),
+        };
+        self.chunk.rewrite(at, patched);
+        self.block_start = self.chunk.code.len();
+    }
+
+    // The offset the next instruction will have, as a target for OpLoop
+    fn loop_target(&mut self) -> usize {
+        self.block_start = self.chunk.code.len();
+        self.block_start
+    }
+
+    fn emit_loop(&mut self, target: usize) {
+        let mut offset = self.chunk.code.len() + Op::OpLoop(0).size() - target;
+        if offset > OPERAND_MAX {
+            self.error(codes::LIMIT_EXCEEDED, "Loop body too large.");
+            offset = 0;
+        }
+        self.emit(Op::OpLoop(offset));
+    }
+
+    // === Constant folding ===
+    // Within a basic block an operator's operands are the instructions just
+    // before it. When those push literals, push the result instead. The VM's
+    // own `binary`/`unary` decide it, and anything they reject (a type error
+    // at runtime) is left for the VM to report.
+    fn fold_constant(&mut self, op: Op) -> bool {
+        let folded = match op {
+            Op::OpNegate | Op::OpNot => self.pushed_literal(1).and_then(|a| Some((1, unary(op, a)?))),
+            Op::OpAdd
+            | Op::OpSubtract
+            | Op::OpMultiply
+            | Op::OpDivide
+            | Op::OpModulo
+            | Op::OpEqual
+            | Op::OpGreater
+            | Op::OpLess => match (self.pushed_literal(2), self.pushed_literal(1)) {
+                (Some(a), Some(b)) => binary(op, a, b, self.heap).map(|value| (2, value)),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((operands, value)) = folded else {
+            return false;
+        };
+        // A jump landing between the operands would skip part of the expression
+        let first = self.starts[self.starts.len() - operands];
+        if first < self.block_start {
+            return false;
+        }
+
+        // The result keeps the line of the expression's first operand
+        let line = self.chunk.lines[first];
+        for _ in 0..operands {
+            let start = self.starts.pop().unwrap_or_default();
+            // Constants the operands just added are no longer needed
+            if let Op::OpConstant(index) = self.chunk.op_at(start)
+                && index + 1 == self.chunk.constants.len()
+            {
+                self.chunk.constants.pop();
+            }
+            self.chunk.code.truncate(start);
+            self.chunk.lines.truncate(start);
+        }
+        let push = match value {
+            Value::ValNil => Op::OpNil,
+            Value::ValBool(true) => Op::OpTrue,
+            Value::ValBool(false) => Op::OpFalse,
+            value => Op::OpConstant(self.make_constant(value)),
+        };
+        self.write(push, line);
+        true
+    }
+
+    // The value pushed by the instruction `back` places from the end, if it's a literal
+    fn pushed_literal(&self, back: usize) -> Option<Value> {
+        let offset = *self.starts.get(self.starts.len().checked_sub(back)?)?;
+        match self.chunk.op_at(offset) {
+            Op::OpConstant(index) => Some(self.chunk.constant(index)),
+            Op::OpNil => Some(Value::ValNil),
+            Op::OpTrue => Some(Value::ValBool(true)),
+            Op::OpFalse => Some(Value::ValBool(false)),
+            _ => None,
+        }
+    }
+
+    // === Statements ===
+    fn declaration(&mut self) {
+        if self.match_token(TokenType::TokenVar) {
+            self.var_declaration();
+        } else {
+            self.statement();
+        }
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+        if self.match_token(TokenType::TokenEqual) {
+            self.expression();
+        } else {
+            self.emit(Op::OpNil);
+        }
+        self.consume(TokenType::TokenSemicolon, "Expect ';' after variable declaration.");
+        self.define_variable(global);
+    }
+
+    fn statement(&mut self) {
+        if self.match_token(TokenType::TokenPrint) {
+            self.print_statement();
+        } else if self.match_token(TokenType::TokenIf) {
+            self.if_statement();
+        } else if self.match_token(TokenType::TokenWhile) {
+            self.while_statement();
+        } else if self.match_token(TokenType::TokenFor) {
+            self.for_statement();
+        } else if self.match_token(TokenType::TokenLeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn block(&mut self) {
+        while !self.check(TokenType::TokenRightBrace) && !self.check(TokenType::TokenEof) {
+            self.declaration();
+        }
+        self.consume(TokenType::TokenRightBrace, "Expect '}' after block.");
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::TokenSemicolon, "Expect ';' after value.");
+        self.emit(Op::OpPrint);
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(TokenType::TokenLeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenType::TokenRightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(Op::OpJumpIfFalse);
+        self.emit(Op::OpPop);
+        self.statement();
+        let else_jump = self.emit_jump(Op::OpJump);
+        self.patch_jump(then_jump);
+        self.emit(Op::OpPop);
+        if self.match_token(TokenType::TokenElse) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.loop_target();
+        self.consume(TokenType::TokenLeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenType::TokenRightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(Op::OpJumpIfFalse);
+        self.emit(Op::OpPop);
+        self.statement();
+        self.emit_loop(loop_start);
+        self.patch_jump(exit_jump);
+        self.emit(Op::OpPop);
+    }
+
+    // for (initializer; condition; increment) body. The increment comes
+    // before the body in the code, so the body jumps over it on the way in
+    // and loops back to it at the end.
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(TokenType::TokenLeftParen, "Expect '(' after 'for'.");
+        if self.match_token(TokenType::TokenSemicolon) {
+            // No initializer
+        } else if self.match_token(TokenType::TokenVar) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.loop_target();
+        let mut exit_jump = None;
+        if !self.match_token(TokenType::TokenSemicolon) {
+            self.expression();
+            self.consume(TokenType::TokenSemicolon, "Expect ';' after loop condition.");
+            exit_jump = Some(self.emit_jump(Op::OpJumpIfFalse));
+            self.emit(Op::OpPop);
+        }
+
+        if !self.match_token(TokenType::TokenRightParen) {
+            let body_jump = self.emit_jump(Op::OpJump);
+            let increment_start = self.loop_target();
+            self.expression();
+            self.emit(Op::OpPop);
+            self.consume(TokenType::TokenRightParen, "Expect ')' after for clauses.");
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit(Op::OpPop);
+        }
+        self.end_scope();
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        // The REPL echoes top-level expressions and lets the last one drop its ';'
+        let echo = self.repl && self.scope_depth == 0;
+        if !(echo && self.check(TokenType::TokenEof)) {
+            self.consume(TokenType::TokenSemicolon, "Expect ';' after expression.");
+        }
+        self.emit(if echo { Op::OpPrint } else { Op::OpPop });
+    }
+
+    // === Variables ===
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+        while self
+            .locals
+            .last()
+            .is_some_and(|local| local.depth.is_some_and(|depth| depth > self.scope_depth))
+        {
+            self.emit(Op::OpPop);
+            self.locals.pop();
+        }
+    }
+
+    // Consume a variable name. Globals return the constant holding the name.
+    fn parse_variable(&mut self, message: &str) -> usize {
+        self.consume(TokenType::TokenIdentifier, message);
+        if self.scope_depth > 0 {
+            self.declare_local();
+            return 0;
+        }
+        let name = self.previous.clone();
+        self.identifier_constant(&name)
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> usize {
+        let text = String::from_utf8_lossy(&name.value).into_owned();
+        let handle = self.heap.intern(&text);
+        self.make_constant(Value::ValObj(handle))
+    }
+
+    fn declare_local(&mut self) {
+        let name = self.previous.clone();
+        let duplicate = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= self.scope_depth))
+            .any(|local| local.name == name.value);
+        if duplicate {
+            self.error(
+                codes::DUPLICATE_VARIABLE,
+                "Already a variable with this name in this scope.",
+            );
+        }
+        // Slots are operands too
+        if self.locals.len() > OPERAND_MAX {
+            self.error(codes::LIMIT_EXCEEDED, "Too many local variables in function.");
+            return;
+        }
+        self.locals.push(Local {
+            name: name.value,
+            depth: None,
+        });
+    }
+
+    fn define_variable(&mut self, global: usize) {
+        if self.scope_depth > 0 {
+            // The value is already in the local's stack slot
+            if let Some(local) = self.locals.last_mut() {
+                local.depth = Some(self.scope_depth);
+            }
+            return;
+        }
+        self.emit(Op::OpDefineGlobal(global));
+    }
+
+    fn resolve_local(&mut self, name: &Token) -> Option<usize> {
+        let slot = self.locals.iter().rposition(|local| local.name == name.value)?;
+        if self.locals[slot].depth.is_none() {
+            self.error(
+                codes::SELF_REFERENCING_INITIALIZER,
+                "Can't read local variable in its own initializer.",
+            );
+        }
+        Some(slot)
+    }
+
+    fn named_variable(&mut self, name: Token, can_assign: bool) {
+        let (get, set) = match self.resolve_local(&name) {
+            Some(slot) => (Op::OpGetLocal(slot), Op::OpSetLocal(slot)),
+            None => {
+                let index = self.identifier_constant(&name);
+                (Op::OpGetGlobal(index), Op::OpSetGlobal(index))
+            }
+        };
+
+        if can_assign && self.match_token(TokenType::TokenEqual) {
+            self.expression();
+            self.emit(set);
+        } else {
+            self.emit(get);
+        }
+    }
+
+    // Skip tokens until something that looks like the start of a statement
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+        while !self.check(TokenType::TokenEof) {
+            if self.previous.token_type == TokenType::TokenSemicolon {
+                return;
+            }
+            match self.current.token_type {
+                TokenType::TokenClass
+                | TokenType::TokenFun
+                | TokenType::TokenVar
+                | TokenType::TokenFor
+                | TokenType::TokenIf
+                | TokenType::TokenWhile
+                | TokenType::TokenPrint
+                | TokenType::TokenReturn => return,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    // === Expressions ===
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::PrecAssignment);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let Some(prefix) = get_rule(&self.previous.token_type).prefix else {
+            self.error(codes::EXPECTED_EXPRESSION, "Expect expression.");
+            return;
+        };
+        let can_assign = precedence <= Precedence::PrecAssignment;
+        prefix(self, can_assign);
+
+        while precedence <= get_rule(&self.current.token_type).precedence {
+            self.advance();
+            if let Some(infix) = get_rule(&self.previous.token_type).infix {
+                infix(self, can_assign);
+            }
+        }
+
+        if can_assign && self.match_token(TokenType::TokenEqual) {
+            self.error(codes::INVALID_ASSIGNMENT_TARGET, "Invalid assignment target.");
+        }
+    }
+
+    fn number(&mut self, _can_assign: bool) {
+        let text = String::from_utf8_lossy(&self.previous.value);
+        match text.parse::<f64>() {
+            Ok(value) => self.emit_constant(Value::ValNumber(value)),
+            Err(_) => self.error(codes::INVALID_TOKEN, "Invalid number literal."),
+        }
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        let name = self.previous.clone();
+        self.named_variable(name, can_assign);
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        // Strip the surrounding quotes
+        let bytes = &self.previous.value[1..self.previous.value.len() - 1];
+        let text = String::from_utf8_lossy(bytes).into_owned();
+        let handle = self.heap.intern(&text);
+        self.emit_constant(Value::ValObj(handle));
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
+        match self.previous.token_type {
+            TokenType::TokenTrue => self.emit(Op::OpTrue),
+            TokenType::TokenFalse => self.emit(Op::OpFalse),
+            TokenType::TokenNil => self.emit(Op::OpNil),
+            _ => unreachable!("literal rule on non-literal token"),
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        let open = self.previous.clone();
+        self.expression();
+        if self.current.token_type == TokenType::TokenRightParen {
+            self.advance();
+            return;
+        }
+        let diagnostic = Diagnostic::error(
+            codes::EXPECTED_TOKEN,
+            "Expect ')' after expression.",
+            self.current.span(),
+        )
+        .with_help(format!(
+            "the group opened at {}:{} needs a closing `)`",
+            open.line, open.column
+        ));
+        self.report(diagnostic);
+    }
+
+    // The left operand decides: if it's falsey it is the result and the
+    // right operand is skipped
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(Op::OpJumpIfFalse);
+        self.emit(Op::OpPop);
+        self.parse_precedence(Precedence::PrecAnd);
+        self.patch_jump(end_jump);
+    }
+
+    // Likewise when the left operand is truthy
+    fn or(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(Op::OpJumpIfTrue);
+        self.emit(Op::OpPop);
+        self.parse_precedence(Precedence::PrecOr);
+        self.patch_jump(end_jump);
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
+        let operator = self.previous.token_type.clone();
+        self.parse_precedence(Precedence::PrecUnary);
+        match operator {
+            TokenType::TokenMinus => self.emit(Op::OpNegate),
+            TokenType::TokenNot => self.emit(Op::OpNot),
+            _ => unreachable!("unary rule on non-unary token"),
+        }
+    }
+
+    fn binary(&mut self, _can_assign: bool) {
+        let operator = self.previous.token_type.clone();
+        self.parse_precedence(get_rule(&operator).precedence.next());
+        match operator {
+            TokenType::TokenPlus => self.emit(Op::OpAdd),
+            TokenType::TokenMinus => self.emit(Op::OpSubtract),
+            TokenType::TokenStar => self.emit(Op::OpMultiply),
+            TokenType::TokenSlash => self.emit(Op::OpDivide),
+            TokenType::TokenEqualEqual => self.emit(Op::OpEqual),
+            TokenType::TokenNotEqual => {
+                self.emit(Op::OpEqual);
+                self.emit(Op::OpNot);
+            }
+            TokenType::TokenGreater => self.emit(Op::OpGreater),
+            TokenType::TokenGreaterEqual => {
+                self.emit(Op::OpLess);
+                self.emit(Op::OpNot);
+            }
+            TokenType::TokenLess => self.emit(Op::OpLess),
+            TokenType::TokenLessEqual => {
+                self.emit(Op::OpGreater);
+                self.emit(Op::OpNot);
+            }
+            _ => unreachable!("binary rule on non-binary token"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Compiler {
+    repl: bool,
+    fold: bool,
+    peephole: bool,
+    superinstructions: bool,
+}
 
 impl Compiler {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    // In REPL mode top-level expression statements print their value
+    pub fn for_repl() -> Self {
+        Self {
+            repl: true,
+            ..Self::default()
+        }
     }
 
-    pub fn compile(&self, source: &str) -> Chunk {
-        let mut chunk = Chunk::new();
+    // Evaluate operators on literal operands at compile time
+    pub fn with_folding(mut self, fold: bool) -> Self {
+        self.fold = fold;
+        self
+    }
 
-        // If the source looks like a number, add it as a constant
-        if let Ok(num) = source.trim().parse::<f64>() {
-            let index = chunk.add_constant(Value::ValNumber(num));
-            if index >= 0 {
-                chunk.write(Op::OpConstant);
-            }
+    // Run the peephole optimizer over the finished chunk
+    pub fn with_peephole(mut self, peephole: bool) -> Self {
+        self.peephole = peephole;
+        self
+    }
+
+    // Fuse common instruction sequences into superinstructions, last of all
+    pub fn with_superinstructions(mut self, superinstructions: bool) -> Self {
+        self.superinstructions = superinstructions;
+        self
+    }
+
+    // Compile a sequence of statements. Every syntax error found is returned
+    // as a diagnostic pointing into `source`. String constants are interned
+    // into `heap`, which must be the heap of the VM that runs the chunk.
+    pub fn compile(&self, source: &str, heap: &mut Heap) -> Result<Chunk, Vec<Diagnostic>> {
+        let mut parser = Parser::new(source, heap, self);
+        parser.advance();
+        while !parser.match_token(TokenType::TokenEof) {
+            parser.declaration();
         }
+        parser.emit(Op::OpReturn);
 
-        // Example: append an OpReturn so the VM knows to stop
-        chunk.write(Op::OpReturn);
-        chunk
+        if parser.diagnostics.is_empty() {
+            if self.peephole {
+                peephole::optimize(&mut parser.chunk);
+            }
+            if self.superinstructions {
+                peephole::fuse(&mut parser.chunk);
+            }
+            Ok(parser.chunk)
+        } else {
+            Err(parser.diagnostics)
+        }
     }
 }
 
-pub fn run_source(source: &str) {
+// Compile and run a whole program; `name` labels diagnostics
+pub fn run_source(source: &str, name: &str) -> InterpretResult {
     let compiler = Compiler::new();
-    let chunk = compiler.compile(source);
-    let mut vm = VirtualMachine::new(chunk);
-    vm.interpret();
+    let mut vm = VirtualMachine::new(Chunk::new());
+    match compiler.compile(source, &mut vm.heap) {
+        Ok(chunk) => {
+            vm.chunk = chunk;
+            vm.interpret()
+        }
+        Err(diagnostics) => {
+            eprint!("{}", render_all(&diagnostics, source, name));
+            InterpretResult::InterpretCompileError(diagnostics)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::output::Output;
+
+    fn compile(source: &str) -> Result<Chunk, Vec<Diagnostic>> {
+        Compiler::new().compile(source, &mut Heap::new())
+    }
+
+    fn compile_error(source: &str) -> Diagnostic {
+        let mut diagnostics = compile(source).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        diagnostics.remove(0)
+    }
+
+    // Run a program and return everything it printed
+    fn run(source: &str) -> String {
+        let mut vm = VirtualMachine::new(Chunk::new()).with_output(Output::Buffer(Vec::new()));
+        vm.chunk = Compiler::new().compile(source, &mut vm.heap).unwrap();
+        vm.run().unwrap();
+        String::from_utf8(vm.output.captured().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_compiles_precedence() {
+        assert_eq!(run("print 1 + 2 * 3; print (1 + 2) * 3; print 8 - 2 - 1;"), "7\n9\n5\n");
+    }
+
+    #[test]
+    fn test_comparison_and_not() {
+        assert_eq!(run("print !(1 >= 2) == true; print nil != false;"), "true\ntrue\n");
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(run("print \"lox\"; print \"a\" + \"b\" == \"ab\";"), "lox\ntrue\n");
+    }
+
+    #[test]
+    fn test_globals_and_locals() {
+        let source = "var a = 1; a = a + 1; { var b = a * 10; { var a = b; print a; } print b; } print a;";
+        assert_eq!(run(source), "20\n20\n2\n");
+    }
+
+    #[test]
+    fn test_variable_errors() {
+        assert_eq!(compile_error("{ var a = 1; var a = 2; }").code, codes::DUPLICATE_VARIABLE);
+        assert_eq!(compile_error("{ var a = a; }").code, codes::SELF_REFERENCING_INITIALIZER);
+        assert_eq!(compile_error("1 + 2 = 3;").code, codes::INVALID_ASSIGNMENT_TARGET);
+    }
+
+    #[test]
+    fn test_folds_literal_operators() {
+        let mut heap = Heap::new();
+        let folding = Compiler::new().with_folding(true);
+        let mut listing = |source: &str| {
+            let chunk = folding.compile(source, &mut heap).unwrap();
+            let text = chunk.disassemble("f", &heap);
+            text.lines().skip(1).map(|line| line[10..].trim_end().to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(listing("print 1 + 2 * 3;"), ["OpConstant          0 '7'", "OpPrint", "OpReturn"]);
+        assert_eq!(listing("print \"a\" + \"b\" == \"ab\";"), ["OpTrue", "OpPrint", "OpReturn"]);
+        assert_eq!(listing("print !(1 >= 2);"), ["OpTrue", "OpPrint", "OpReturn"]);
+        assert_eq!(listing("print -(1 / 0);"), ["OpConstant          0 '-inf'", "OpPrint", "OpReturn"]);
+
+        // Would fail at runtime, so the VM gets to report it
+        assert_eq!(listing("print -\"a\";"), ["OpConstant          0 'a'", "OpNegate", "OpPrint", "OpReturn"]);
+        assert_eq!(
+            listing("var a = 1; print a + 2 * 3 - 1;"),
+            [
+                "OpConstant          1 '1'",
+                "OpDefineGlobal      0 'a'",
+                "OpGetGlobal         2 'a'",
+                "OpConstant          3 '6'",
+                "OpAdd",
+                "OpConstant          4 '1'",
+                "OpSubtract",
+                "OpPrint",
+                "OpReturn",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_folding_keeps_runtime_behaviour() {
+        let programs = [
+            "print 1 + 2 * 3 - 4 / 8;",
+            "print 0 / 0 == 0 / 0; print 0 / 0 != 0 / 0; print -(0 / 0);",
+            "print 1 / 0 > 100000; print -1 / 0 < 0; print 0.1 + 0.2;",
+            "print !nil == !false; print \"x\" == \"x\"; print nil == false;",
+            "print \"con\" + \"cat\" + \"enate\";",
+            "{ var a = 2; print -a * (3 + 4); }",
+            "print 1 + true;",
+            "print -nil;",
+            "print \"a\" < \"b\";",
+            // A jump lands on the 1, so it must not be folded with the 2
+            "var x; print (x and 2) + 1; x = true; print (x and 2) + 1;",
+            "var y; print (y or 2) * 3; while (!y) y = 1 < 2; print y;",
+        ];
+        for source in programs {
+            let run = |fold| {
+                let mut vm = VirtualMachine::new(Chunk::new()).with_output(Output::Buffer(Vec::new()));
+                vm.chunk = Compiler::new().with_folding(fold).compile(source, &mut vm.heap).unwrap();
+                let error = vm.run().err().map(|error| error.message);
+                (String::from_utf8(vm.output.captured().unwrap().to_vec()).unwrap(), error)
+            };
+            assert_eq!(run(true), run(false), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_control_flow() {
+        assert_eq!(run("if (1 > 2) print 1; else print 2; if (nil) print 3;"), "2\n");
+        assert_eq!(run("var i = 0; while (i < 3) { print i; i = i + 1; }"), "0\n1\n2\n");
+        let source = "for (var i = 0; i < 3; i = i + 1) print i * 10; var i = 7; print i;";
+        assert_eq!(run(source), "0\n10\n20\n7\n");
+        assert_eq!(run("var n = 0; for (;n < 2;) n = n + 1; print n;"), "2\n");
+        let source = "print 1 and 2; print nil and 2; print nil or \"b\"; print 1 or 2;";
+        assert_eq!(run(source), "2\nnil\nb\n1\n");
+        assert_eq!(run("print false or nil and 1; print 1 < 2 and 2 < 3;"), "nil\ntrue\n");
+        assert_eq!(compile_error("if 1 print 2;").message, "Expect '(' after 'if'.");
+        let error = compile_error("for (var i = 0; i < 1; i = i + 1 print i;");
+        assert_eq!(error.message, "Expect ')' after for clauses.");
+    }
+
+    #[test]
+    fn test_repl_mode_echoes_expressions() {
+        let mut vm = VirtualMachine::new(Chunk::new()).with_output(Output::Buffer(Vec::new()));
+        vm.chunk = Compiler::for_repl().compile("var x = 2; x * 3; { x; } x + 1", &mut vm.heap).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.output.captured(), Some("6\n3\n".as_bytes()));
+    }
+
+    #[test]
+    fn test_scanner_error_has_location() {
+        let error = compile_error("1 +\n  2 @ 3;");
+        assert_eq!(error.code, codes::INVALID_TOKEN);
+        assert_eq!(error.message, "Unknown character.");
+        assert_eq!((error.span.line, error.span.column, error.span.start), (2, 5, 8));
+    }
+
+    #[test]
+    fn test_missing_operand_points_at_end() {
+        let source = "(1 +";
+        let error = compile_error(source);
+        assert_eq!(error.code, codes::EXPECTED_EXPRESSION);
+        assert_eq!(error.span.start, source.len());
+        assert!(error.help.is_some());
+    }
+
+    #[test]
+    fn test_unclosed_group_mentions_opening_paren() {
+        let error = compile_error("(1 + 2 3;");
+        assert_eq!(error.code, codes::EXPECTED_TOKEN);
+        assert_eq!(error.span.column, 8);
+        assert_eq!(error.help.as_deref(), Some("the group opened at 1:1 needs a closing `)`"));
+    }
+
+    #[test]
+    fn test_reports_every_statement_error() {
+        let source = "1 +;\n2 * 3;\n(4 5;\nvar 1;\n@ 6;\n";
+        let diagnostics = compile(source).unwrap_err();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.span.line, d.code, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, codes::EXPECTED_EXPRESSION, "Expect expression."),
+                (3, codes::EXPECTED_TOKEN, "Expect ')' after expression."),
+                (4, codes::EXPECTED_TOKEN, "Expect variable name."),
+                (5, codes::INVALID_TOKEN, "Unknown character."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cascading_errors_are_suppressed() {
+        // One bad statement yields one error, not one per leftover token
+        let diagnostics = compile("1 + + ) ) 2;\n3;").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+    }
 }
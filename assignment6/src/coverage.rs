@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::virtual_machine::Chunk;
+
+// How many times each bytecode offset executed
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    pub hits: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn hit(&mut self, offset: usize) {
+        if offset >= self.hits.len() {
+            self.hits.resize(offset + 1, 0);
+        }
+        self.hits[offset] += 1;
+    }
+
+    // Map offsets onto source lines through the chunk's line table. A line
+    // counts as reached when any of its instructions ran; lines past the end
+    // of `source` (the implicit return) are left out.
+    pub fn lines(&self, path: &str, chunk: &Chunk, source: Option<&str>) -> FileCoverage {
+        let last = source.map_or(usize::MAX, |source| source.lines().count());
+        let mut lines = BTreeMap::new();
+        for (offset, &line) in chunk.lines.iter().enumerate() {
+            if line == 0 || line > last {
+                continue;
+            }
+            let hits = self.hits.get(offset).copied().unwrap_or(0);
+            let entry = lines.entry(line).or_insert(0);
+            *entry = hits.max(*entry);
+        }
+        FileCoverage {
+            path: path.to_string(),
+            lines,
+        }
+    }
+}
+
+// Hits per line for one file, only for lines that have code
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCoverage {
+    pub path: String,
+    pub lines: BTreeMap<usize, u64>,
+}
+
+impl FileCoverage {
+    pub fn found(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn hit(&self) -> usize {
+        self.lines.values().filter(|&&hits| hits > 0).count()
+    }
+
+    // Runs of unreached lines, merged when only lines without code lie between
+    pub fn uncovered(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        let mut open = false;
+        for (&line, &hits) in &self.lines {
+            match ranges.last_mut() {
+                Some(range) if open && hits == 0 => range.1 = line,
+                _ if hits == 0 => ranges.push((line, line)),
+                _ => {}
+            }
+            open = hits == 0;
+        }
+        ranges
+    }
+}
+
+fn percent(hit: usize, found: usize) -> f64 {
+    if found == 0 {
+        100.0
+    } else {
+        hit as f64 * 100.0 / found as f64
+    }
+}
+
+// The lcov tracefile format genhtml and most CI coverage services read
+pub fn lcov(files: &[FileCoverage]) -> String {
+    let mut out = String::new();
+    for file in files {
+        let _ = writeln!(out, "TN:\nSF:{}", file.path);
+        for (line, hits) in &file.lines {
+            let _ = writeln!(out, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(out, "LF:{}\nLH:{}\nend_of_record", file.found(), file.hit());
+    }
+    out
+}
+
+pub fn summary(files: &[FileCoverage]) -> String {
+    let width = files.iter().map(|file| file.path.len()).chain([5]).max().unwrap_or(5);
+    let mut out = format!("{:<width$}  {:>9}  {:>6}  uncovered\n", "file", "lines", "cover", width = width);
+    for file in files {
+        let ranges: Vec<String> = file
+            .uncovered()
+            .iter()
+            .map(|&(start, end)| {
+                if start == end {
+                    start.to_string()
+                } else {
+                    format!("{}-{}", start, end)
+                }
+            })
+            .collect();
+        let row = format!(
+            "{:<width$}  {:>9}  {:>5.1}%  {}",
+            file.path,
+            format!("{}/{}", file.hit(), file.found()),
+            percent(file.hit(), file.found()),
+            ranges.join(", "),
+            width = width
+        );
+        let _ = writeln!(out, "{}", row.trim_end());
+    }
+    let hit = files.iter().map(FileCoverage::hit).sum();
+    let found = files.iter().map(FileCoverage::found).sum();
+    let _ = writeln!(
+        out,
+        "{:<width$}  {:>9}  {:>5.1}%",
+        "total",
+        format!("{}/{}", hit, found),
+        percent(hit, found),
+        width = width
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::output::Output;
+    use crate::trace::VmConfig;
+    use crate::virtual_machine::VirtualMachine;
+
+    fn covered(path: &str, source: &str) -> FileCoverage {
+        let config = VmConfig {
+            coverage: Some(Coverage::new()),
+            ..VmConfig::default()
+        };
+        let mut vm = VirtualMachine::new(Chunk::new())
+            .with_output(Output::Buffer(Vec::new()))
+            .with_config(config);
+        let chunk = Compiler::new().compile(source, &mut vm.heap).unwrap();
+        vm.load(chunk);
+        let _ = vm.run();
+        let coverage = vm.config.coverage.take().unwrap();
+        coverage.lines(path, &vm.chunk, Some(source))
+    }
+
+    #[test]
+    fn test_lines_after_an_error_are_uncovered() {
+        let source = "var a = 1;\n\nprint a;\nprint a + nil;\n\n// comment\nprint 2;\nprint 3;\n\nprint 4;\n";
+        let file = covered("t.lox", source);
+        let lines: Vec<(usize, u64)> = file.lines.iter().map(|(&line, &hits)| (line, hits)).collect();
+        assert_eq!(lines, [(1, 1), (3, 1), (4, 1), (7, 0), (8, 0), (10, 0)]);
+        assert_eq!((file.hit(), file.found()), (3, 6));
+        assert_eq!(file.uncovered(), [(7, 10)]);
+
+        let file = covered("u.lox", "print 1;\n{\n  var a = 2;\n}");
+        assert_eq!(file.uncovered(), []);
+        assert_eq!(file.lines.keys().copied().collect::<Vec<_>>(), [1, 3, 4]);
+    }
+
+    #[test]
+    fn test_lcov_and_summary() {
+        let mut lines = BTreeMap::new();
+        lines.extend([(1, 2), (2, 0), (3, 1), (5, 0), (6, 0)]);
+        let files = [
+            FileCoverage {
+                path: "tests/a.lox".to_string(),
+                lines,
+            },
+            FileCoverage {
+                path: "b.lox".to_string(),
+                lines: BTreeMap::from([(1, 1)]),
+            },
+        ];
+        assert_eq!(
+            lcov(&files[1..]),
+            "TN:\nSF:b.lox\nDA:1,1\nLF:1\nLH:1\nend_of_record\n"
+        );
+        assert_eq!(
+            summary(&files),
+            "file             lines   cover  uncovered\n\
+             tests/a.lox        2/5   40.0%  2, 5-6\n\
+             b.lox              1/1  100.0%\n\
+             total              3/6   50.0%\n"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt::Write;
+
+use lox_diagnostics::{codes, Diagnostic};
+
+use crate::object::Heap;
+use crate::scanner::{Scanner, TokenType};
+use crate::virtual_machine::{Chunk, Op};
+
+impl Chunk {
+    pub fn disassemble(&self, name: &str, heap: &Heap) -> String {
+        let mut out = format!("== {} ==\n", name);
+        for (offset, _) in self.instructions() {
+            out.push_str(&self.disassemble_instruction(offset, heap));
+            out.push('\n');
+        }
+        out
+    }
+
+    // One line per instruction: offset, source line ("|" when unchanged), op, operand
+    pub fn disassemble_instruction(&self, offset: usize, heap: &Heap) -> String {
+        let mut out = format!("{:04} ", offset);
+        if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
+            out.push_str("   | ");
+        } else {
+            let _ = write!(out, "{:4} ", self.lines[offset]);
+        }
+
+        let op = self.op_at(offset);
+        match op {
+            Op::OpConstant(index)
+            | Op::OpDefineGlobal(index)
+            | Op::OpGetGlobal(index)
+            | Op::OpSetGlobal(index)
+            | Op::OpAddConstant(index) => {
+                let name = op.name();
+                let value = self.constants[index].format(heap);
+                let _ = write!(out, "{:<16} {:4} '{}'", name, index, value);
+            }
+            Op::OpGetLocals(a, b) => {
+                let _ = write!(out, "{:<16} {:4} {:4}", "OpGetLocals", a, b);
+            }
+            Op::OpLessLocalConst(slot, index) => {
+                let value = self.constants[index].format(heap);
+                let _ = write!(out, "{:<16} {:4} {:4} '{}'", "OpLessLocalConst", slot, index, value);
+            }
+            Op::OpGetLocal(slot) => {
+                let _ = write!(out, "{:<16} {:4}", "OpGetLocal", slot);
+            }
+            Op::OpSetLocal(slot) => {
+                let _ = write!(out, "{:<16} {:4}", "OpSetLocal", slot);
+            }
+            op @ (Op::OpJump(jump)
+            | Op::OpJumpIfFalse(jump)
+            | Op::OpJumpIfTrue(jump)
+            | Op::OpLoop(jump)) => {
+                let target = op.jump_target(offset).unwrap_or_default();
+                let _ = write!(out, "{:<16} {:4} -> {:04}", op.name(), jump, target);
+            }
+            op => {
+                let _ = write!(out, "{:?}", op);
+            }
+        }
+        out
+    }
+}
+
+// Token listing in the same layout as the scanner assignment
+pub fn dump_tokens(source: &str) -> String {
+    let mut scanner = Scanner::init_scanner(source);
+    let mut out = String::new();
+    let mut line = 0;
+    loop {
+        let token = scanner.scan_token();
+        if token.line != line {
+            let _ = write!(out, "{:4} ", token.line);
+            line = token.line;
+        } else {
+            out.push_str("   | ");
+        }
+        let text = String::from_utf8_lossy(&token.value);
+        let _ = writeln!(out, "{:?} {}, {:?}", token.token_type, token.value.len(), text);
+        if token.token_type == TokenType::TokenEof {
+            break;
+        }
+    }
+    out
+}
+
+// The same tokens as a JSON array, one object per line, for scripts to consume
+pub fn dump_tokens_json(source: &str) -> String {
+    let mut scanner = Scanner::init_scanner(source);
+    let mut out = String::from("[\n");
+    loop {
+        let token = scanner.scan_token();
+        let lexeme = source.get(token.offset..token.offset + token.length).unwrap_or("");
+        let _ = write!(
+            out,
+            "  {{\"type\": \"{:?}\", \"lexeme\": {}, \"line\": {}, \"column\": {}",
+            token.token_type,
+            json_string(lexeme),
+            token.line,
+            token.column
+        );
+        if token.token_type == TokenType::TokenError {
+            let _ = write!(out, ", \"message\": {}", json_string(&String::from_utf8_lossy(&token.value)));
+        }
+        out.push('}');
+        if token.token_type == TokenType::TokenEof {
+            out.push_str("\n]\n");
+            break;
+        }
+        out.push_str(",\n");
+    }
+    out
+}
+
+// Scanner errors alone, for listings that should still fail on bad input
+pub fn scan_diagnostics(source: &str) -> Vec<Diagnostic> {
+    let mut scanner = Scanner::init_scanner(source);
+    let mut diagnostics = Vec::new();
+    loop {
+        let token = scanner.scan_token();
+        match token.token_type {
+            TokenType::TokenError => {
+                let message = String::from_utf8_lossy(&token.value);
+                diagnostics.push(Diagnostic::error(codes::INVALID_TOKEN, message, token.span()));
+            }
+            TokenType::TokenEof => return diagnostics,
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_json() {
+        let json = dump_tokens_json("print \"a\\b\";\n@");
+        assert_eq!(
+            json,
+            "[\n  {\"type\": \"TokenPrint\", \"lexeme\": \"print\", \"line\": 1, \"column\": 1},\n  \
+             {\"type\": \"TokenString\", \"lexeme\": \"\\\"a\\\\b\\\"\", \"line\": 1, \"column\": 7},\n  \
+             {\"type\": \"TokenSemicolon\", \"lexeme\": \";\", \"line\": 1, \"column\": 12},\n  \
+             {\"type\": \"TokenError\", \"lexeme\": \"@\", \"line\": 2, \"column\": 1, \"message\": \"Unknown character.\"},\n  \
+             {\"type\": \"TokenEof\", \"lexeme\": \"\", \"line\": 2, \"column\": 2}\n]\n"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+use crate::object::ObjRef;
+use crate::packed::Packed;
+use crate::virtual_machine::{Value, VirtualMachine};
+
+// Everything one instruction changed, enough to put the VM back as it was.
+// Strings it interned stay on the heap; nothing can reach them after an undo.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Delta {
+    ip: usize,
+    halted: bool,
+    output_len: usize,
+    pushed: usize,
+    popped: Vec<Packed>,                    // in the order they came off the stack
+    slots: Vec<(usize, Packed)>,            // stack slots overwritten in place
+    globals: Vec<(ObjRef, Option<Value>)>, // previous value of each global written
+}
+
+// Undo log for the most recent steps. `step` counts every instruction executed
+// since the chunk was loaded; the oldest deltas are dropped past `limit`.
+#[derive(Debug, Clone)]
+pub struct History {
+    deltas: VecDeque<Delta>,
+    limit: usize,
+    step: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            deltas: VecDeque::new(),
+            limit,
+            step: 0,
+        }
+    }
+
+    // Number of instructions executed so far
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    // Earliest step that can still be reached by stepping back
+    pub fn earliest(&self) -> usize {
+        self.step - self.deltas.len()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    fn push(&mut self, delta: Delta) {
+        self.step += 1;
+        if self.limit == 0 {
+            return;
+        }
+        if self.deltas.len() == self.limit {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+
+    fn pop(&mut self) -> Option<Delta> {
+        let delta = self.deltas.pop_back()?;
+        self.step -= 1;
+        Some(delta)
+    }
+}
+
+impl VirtualMachine {
+    // Start recording undo information for every step, keeping at most `limit`
+    pub fn enable_history(&mut self, limit: usize) {
+        self.history = Some(History::new(limit));
+    }
+
+    // Undo the most recent step. Returns false when there is nothing left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(delta) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+
+        let kept = self.stack.len() - delta.pushed;
+        self.stack.truncate(kept);
+        for &(slot, value) in delta.slots.iter().rev() {
+            self.stack[slot] = value;
+        }
+        self.stack.extend(delta.popped.iter().rev());
+        for &(name, value) in delta.globals.iter().rev() {
+            match value {
+                Some(value) => self.globals.insert(name, value),
+                None => self.globals.remove(&name),
+            };
+        }
+        self.ip = delta.ip;
+        self.halted = delta.halted;
+        self.output.truncate(delta.output_len);
+        true
+    }
+
+    pub(crate) fn begin_delta(&mut self) {
+        if self.history.is_some() {
+            self.delta = Some(Delta {
+                ip: self.ip,
+                halted: self.halted,
+                output_len: self.output.captured_len(),
+                pushed: self.stack.len(), // fixed up in end_delta
+                ..Delta::default()
+            });
+        }
+    }
+
+    pub(crate) fn end_delta(&mut self) {
+        if let (Some(mut delta), Some(history)) = (self.delta.take(), self.history.as_mut()) {
+            // Only pop() removes values, so whatever is above the untouched
+            // part of the stack was pushed by this step
+            let untouched = delta.pushed - delta.popped.len();
+            delta.pushed = self.stack.len() - untouched;
+            history.push(delta);
+        }
+    }
+
+    pub(crate) fn record_pop(&mut self, value: Packed) {
+        if let Some(delta) = &mut self.delta {
+            delta.popped.push(value);
+        }
+    }
+
+    pub(crate) fn record_slot(&mut self, slot: usize) {
+        if let Some(delta) = &mut self.delta {
+            delta.slots.push((slot, self.stack[slot]));
+        }
+    }
+
+    pub(crate) fn record_global(&mut self, name: ObjRef) {
+        if let Some(delta) = &mut self.delta {
+            delta.globals.push((name, self.globals.get(&name).copied()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::output::Output;
+    use crate::virtual_machine::{Chunk, VirtualMachine};
+
+    // Everything observable about the VM, formatted so it can be compared
+    fn snapshot(vm: &VirtualMachine) -> String {
+        let stack: Vec<String> = vm.stack.iter().map(|v| v.format(&vm.heap)).collect();
+        let mut globals: Vec<String> = vm
+            .globals
+            .iter()
+            .map(|(name, v)| format!("{}={}", vm.heap.as_string(*name).unwrap(), v.format(&vm.heap)))
+            .collect();
+        globals.sort();
+        format!(
+            "{} {} {:?} {:?} {:?}",
+            vm.ip,
+            vm.halted,
+            stack,
+            globals,
+            vm.output.captured()
+        )
+    }
+
+    fn machine(source: &str, limit: usize) -> VirtualMachine {
+        let mut vm = VirtualMachine::new(Chunk::new()).with_output(Output::Buffer(Vec::new()));
+        let chunk = Compiler::new().compile(source, &mut vm.heap).unwrap();
+        vm.load(chunk);
+        vm.enable_history(limit);
+        vm
+    }
+
+    #[test]
+    fn test_step_back_restores_every_state() {
+        let source = "var a = 1; a = a + 1; { var b = \"x\"; b = b + \"y\"; print b; } print a; a + nil;";
+        let mut vm = machine(source, 1000);
+        let mut states = vec![snapshot(&vm)];
+        while !vm.is_done() {
+            let _ = vm.step();
+            states.push(snapshot(&vm));
+        }
+        assert!(vm.output.captured().unwrap().starts_with(b"xy\n2\n"));
+
+        while vm.step_back() {
+            states.pop();
+            assert_eq!(snapshot(&vm), *states.last().unwrap());
+        }
+        assert_eq!(states.len(), 1);
+        assert_eq!(vm.history.as_ref().unwrap().step(), 0);
+    }
+
+    #[test]
+    fn test_history_limit_drops_oldest() {
+        let mut vm = machine("print 1; print 2; print 3;", 2);
+        vm.run().unwrap();
+        let history = vm.history.as_ref().unwrap();
+        assert_eq!((history.earliest(), history.step()), (5, 7));
+
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert!(!vm.step_back());
+        assert_eq!(vm.ip, 11);
+        assert_eq!(vm.output.captured(), Some("1\n2\n".as_bytes()));
+    }
+}
@@ -0,0 +1,647 @@
+use std::collections::HashMap;
+use std::ptr;
+
+use crate::packed::{bits, Packed};
+use crate::verifier::entry_depths;
+use crate::virtual_machine::{Chunk, Op, Value, VirtualMachine};
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+compile_error!("the jit feature needs Linux on x86-64");
+
+// === Baseline JIT ===
+// Hot loops are translated, one template per instruction, into x86-64 code
+// that works on the VM stack in place. There are no functions yet, so a loop
+// is the unit that gets compiled and the number of times its back edge is
+// taken stands in for a call count.
+//
+// The code knows the stack depth at every instruction (the verifier works it
+// out), so slot n is always at [rdi + 8n] and nothing tracks a stack pointer.
+// Arithmetic and comparisons check that their operands are numbers first. An
+// operand that isn't, an instruction with no template, or a jump out of the
+// loop returns the offset to carry on from, with the stack exactly as the
+// interpreter would have it there; the interpreter then does the rest,
+// including raising any runtime error.
+
+// Back edges taken before a loop is compiled, for `lox run`
+pub const DEFAULT_THRESHOLD: usize = 100;
+
+#[derive(Debug)]
+pub struct Jit {
+    threshold: usize,
+    loops: HashMap<usize, Loop>,        // by the offset of the loop header
+    depths: Option<Vec<Option<usize>>>, // of the loaded chunk, once a loop is hot
+}
+
+#[derive(Debug)]
+enum Loop {
+    Warming(usize),
+    Compiled(Code),
+    Rejected, // the chunk didn't verify or the header has no template
+}
+
+impl Jit {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            loops: HashMap::new(),
+            depths: None,
+        }
+    }
+
+    // Forget everything about the previous chunk
+    pub(crate) fn reset(&mut self) {
+        self.loops.clear();
+        self.depths = None;
+    }
+
+    // Number of loops turned into machine code so far
+    pub fn compiled(&self) -> usize {
+        self.loops.values().filter(|l| matches!(l, Loop::Compiled(_))).count()
+    }
+
+    // Counts one more trip round the loop starting at `header`, compiling it
+    // once it's hot. Returns the code to run from the header, if there is any.
+    fn hot(&mut self, chunk: &Chunk, header: usize, back_edge: usize) -> Option<&Code> {
+        if let Loop::Warming(count) = self.loops.entry(header).or_insert(Loop::Warming(0)) {
+            *count += 1;
+            if *count < self.threshold {
+                return None;
+            }
+            let depths = self.depths.get_or_insert_with(|| entry_depths(chunk).unwrap_or_default());
+            let code = compile(chunk, header, back_edge, depths);
+            self.loops.insert(header, code.map_or(Loop::Rejected, Loop::Compiled));
+        }
+        match &self.loops[&header] {
+            Loop::Compiled(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl VirtualMachine {
+    // Compile loops to machine code once their back edge has been taken
+    // `threshold` times
+    pub fn with_jit(mut self, threshold: usize) -> Self {
+        self.jit = Some(Jit::new(threshold));
+        self
+    }
+
+    pub fn jit(&self) -> Option<&Jit> {
+        self.jit.as_ref()
+    }
+
+    // Called by OpLoop after jumping back from `back_edge` to `ip`
+    pub(crate) fn loop_taken(&mut self, back_edge: usize) {
+        // Undo and instrumentation need to see every instruction
+        let config = &self.config;
+        let watched = config.trace.is_some()
+            || config.profile.is_some()
+            || config.sampler.is_some()
+            || config.coverage.is_some();
+        if watched || self.history.is_some() {
+            return;
+        }
+        let Some(jit) = &mut self.jit else {
+            return;
+        };
+        if let Some(code) = jit.hot(&self.chunk, self.ip, back_edge) {
+            self.ip = code.run(&mut self.stack);
+        }
+    }
+}
+
+// === Executable Code ===
+
+// Machine code in its own mapping, executable but no longer writable
+#[derive(Debug)]
+struct Code {
+    memory: *mut libc::c_void,
+    len: usize,
+    entry: usize,                 // where the loop header's template starts
+    max_depth: usize,             // stack slots the code may write
+    exits: HashMap<usize, usize>, // stack depth at each offset the code can return
+}
+
+impl Code {
+    fn new(bytes: &[u8], entry: usize, max_depth: usize, exits: HashMap<usize, usize>) -> Option<Self> {
+        let len = bytes.len();
+        // SAFETY: a fresh anonymous mapping, filled and then made executable
+        // before anything can call into it
+        unsafe {
+            let memory = libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if memory == libc::MAP_FAILED {
+                return None;
+            }
+            ptr::copy_nonoverlapping(bytes.as_ptr(), memory.cast::<u8>(), len);
+            let code = Code {
+                memory,
+                len,
+                entry,
+                max_depth,
+                exits,
+            };
+            if libc::mprotect(memory, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return None; // unmapped by drop
+            }
+            Some(code)
+        }
+    }
+
+    // Runs the loop on `stack`, which must be as deep as the header expects.
+    // Returns the offset the interpreter carries on from.
+    fn run(&self, stack: &mut Vec<Packed>) -> usize {
+        stack.reserve(self.max_depth.saturating_sub(stack.len()));
+        // SAFETY: the code only touches slots below max_depth, all within
+        // capacity, and every slot below the depth it exits at holds a value
+        // it or the interpreter wrote. Packed is a plain u64 here.
+        unsafe {
+            let entry = self.memory.cast::<u8>().add(self.entry);
+            let entry: extern "C" fn(*mut u64) -> usize = std::mem::transmute(entry);
+            let resume = entry(stack.as_mut_ptr().cast::<u64>());
+            stack.set_len(self.exits[&resume]);
+            resume
+        }
+    }
+}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        // SAFETY: mapped by `new` and not used after this
+        unsafe {
+            libc::munmap(self.memory, self.len);
+        }
+    }
+}
+
+// === Translation ===
+
+// The loop ending in the OpLoop at `back_edge`, entered at `header`, or None
+// when it can't usefully be compiled
+fn compile(chunk: &Chunk, header: usize, back_edge: usize, depths: &[Option<usize>]) -> Option<Code> {
+    if depths.get(header)?.is_none() || !supported(chunk, chunk.op_at(header)) {
+        return None;
+    }
+    // A for loop's body jumps back to the increment, which jumps back to the
+    // condition before it, so take in everything the loop's back edges reach
+    let mut start = header;
+    loop {
+        let reached = chunk
+            .instructions()
+            .filter(|&(offset, op)| (start..=back_edge).contains(&offset) && matches!(op, Op::OpLoop(_)))
+            .filter_map(|(offset, op)| op.jump_target(offset))
+            .min()
+            .unwrap_or(start);
+        if reached >= start {
+            break;
+        }
+        start = reached;
+    }
+
+    let mut asm = Assembler::default();
+    let mut max_depth = 0;
+    let body = chunk.instructions().skip_while(|&(offset, _)| offset < start);
+    for (offset, op) in body.take_while(|&(offset, _)| offset <= back_edge) {
+        // Unreachable code needs no translation
+        let Some(depth) = depths[offset] else {
+            continue;
+        };
+        max_depth = max_depth.max(depth + 2);
+        asm.labels.insert(offset, asm.code.len());
+        asm.instruction(chunk, offset, op, depth);
+    }
+    let entry = asm.labels[&header];
+    let (bytes, exits) = asm.finish(depths)?;
+    Code::new(&bytes, entry, max_depth, exits)
+}
+
+// Whether an instruction has a template. The fused ones only do when their
+// constant is a number.
+fn supported(chunk: &Chunk, op: Op) -> bool {
+    match op {
+        Op::OpAddConstant(index) | Op::OpLessLocalConst(_, index) => {
+            matches!(chunk.constant(index), Value::ValNumber(_))
+        }
+        Op::OpConstant(_)
+        | Op::OpNil
+        | Op::OpTrue
+        | Op::OpFalse
+        | Op::OpGetLocal(_)
+        | Op::OpSetLocal(_)
+        | Op::OpGetLocals(..)
+        | Op::OpPop
+        | Op::OpAdd
+        | Op::OpSubtract
+        | Op::OpMultiply
+        | Op::OpDivide
+        | Op::OpNegate
+        | Op::OpNot
+        | Op::OpEqual
+        | Op::OpGreater
+        | Op::OpLess
+        | Op::OpJump(_)
+        | Op::OpJumpIfFalse(_)
+        | Op::OpJumpIfTrue(_)
+        | Op::OpLoop(_) => true,
+        // Globals, printing and OpReturn are left to the interpreter, and
+        // OpModulo would need a call to fmod
+        _ => false,
+    }
+}
+
+// === x86-64 Encoding ===
+// Only what the templates need: rax and rcx hold operands, rdx is scratch,
+// xmm0 and xmm1 do the arithmetic and rdi points at stack slot 0.
+
+#[derive(Clone, Copy)]
+enum Reg {
+    Rax = 0,
+    Rcx = 1,
+    Rdx = 2,
+}
+
+const RDI: u8 = 7;
+
+// Opcodes of the 64-bit `op r/m, reg` forms
+const MOV: u8 = 0x89;
+const ADD: u8 = 0x01;
+const XOR: u8 = 0x31;
+const CMP: u8 = 0x39;
+
+// Jumps, each followed by a 32-bit displacement
+const JMP: &[u8] = &[0xe9];
+const JE: &[u8] = &[0x0f, 0x84];
+const JNE: &[u8] = &[0x0f, 0x85];
+
+// Condition codes for setcc
+const SETE: u8 = 0x94;
+const SETA: u8 = 0x97;
+const SETNP: u8 = 0x9b;
+
+// Where a jump goes, by bytecode offset
+#[derive(Clone, Copy)]
+enum Target {
+    Label(usize), // that instruction's template, or out of the loop if it isn't in it
+    Exit(usize),  // back to the interpreter, even if the instruction is in the loop
+}
+
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+    labels: HashMap<usize, usize>, // bytecode offset -> where its template starts
+    fixups: Vec<(usize, Target)>,  // displacement to fill in -> where it jumps
+}
+
+impl Assembler {
+    fn instruction(&mut self, chunk: &Chunk, offset: usize, op: Op, depth: usize) {
+        if !supported(chunk, op) {
+            self.jump(JMP, Target::Exit(offset));
+            return;
+        }
+        match op {
+            Op::OpConstant(index) => self.push_bits(depth, chunk.constants[index].to_bits()),
+            Op::OpNil => self.push_bits(depth, bits::NIL),
+            Op::OpTrue => self.push_bits(depth, bits::TRUE),
+            Op::OpFalse => self.push_bits(depth, bits::FALSE),
+            Op::OpGetLocal(slot) => self.copy(slot, depth),
+            Op::OpGetLocals(a, b) => {
+                self.copy(a, depth);
+                self.copy(b, depth + 1);
+            }
+            Op::OpSetLocal(slot) => self.copy(depth - 1, slot),
+            Op::OpPop => {}
+            Op::OpAdd | Op::OpSubtract | Op::OpMultiply | Op::OpDivide => {
+                self.number(Reg::Rax, depth - 2, offset);
+                self.number(Reg::Rcx, depth - 1, offset);
+                self.arithmetic(op);
+                self.store(depth - 2, Reg::Rax);
+            }
+            Op::OpAddConstant(index) => {
+                self.number(Reg::Rax, depth - 1, offset);
+                self.mov_imm(Reg::Rcx, chunk.constants[index].to_bits());
+                self.arithmetic(Op::OpAdd);
+                self.store(depth - 1, Reg::Rax);
+            }
+            Op::OpGreater | Op::OpLess => {
+                self.number(Reg::Rax, depth - 2, offset);
+                self.number(Reg::Rcx, depth - 1, offset);
+                self.compare(op);
+                self.boolean(depth - 2);
+            }
+            Op::OpLessLocalConst(slot, index) => {
+                self.number(Reg::Rax, slot, offset);
+                self.mov_imm(Reg::Rcx, chunk.constants[index].to_bits());
+                self.compare(Op::OpLess);
+                self.boolean(depth);
+            }
+            Op::OpEqual => self.equal(depth - 2),
+            Op::OpNegate => {
+                self.number(Reg::Rax, depth - 1, offset);
+                self.mov_imm(Reg::Rcx, bits::SIGN);
+                self.alu(XOR, Reg::Rax, Reg::Rcx);
+                self.store(depth - 1, Reg::Rax);
+            }
+            Op::OpNot => {
+                self.load(Reg::Rax, depth - 1);
+                self.falsey();
+                self.boolean(depth - 1);
+            }
+            Op::OpJump(_) | Op::OpLoop(_) => self.jump(JMP, Target::Label(op.jump_target(offset).unwrap())),
+            Op::OpJumpIfFalse(_) | Op::OpJumpIfTrue(_) => {
+                self.load(Reg::Rax, depth - 1);
+                self.falsey();
+                self.emit(&[0x84, 0xc9]); // test cl, cl
+                let taken = if matches!(op, Op::OpJumpIfFalse(_)) { JNE } else { JE };
+                self.jump(taken, Target::Label(op.jump_target(offset).unwrap()));
+            }
+            _ => unreachable!("{} has no template", op.name()),
+        }
+    }
+
+    // Resolves jumps, adding a return for each offset that leaves the loop.
+    // None if one of them is somewhere the stack depth isn't known.
+    fn finish(mut self, depths: &[Option<usize>]) -> Option<(Vec<u8>, HashMap<usize, usize>)> {
+        let mut stubs = HashMap::new();
+        let mut exits = HashMap::new();
+        for (at, target) in std::mem::take(&mut self.fixups) {
+            let label = match target {
+                Target::Label(offset) => self.labels.get(&offset).copied(),
+                Target::Exit(_) => None,
+            };
+            let (Target::Label(target) | Target::Exit(target)) = target;
+            let dest = match label {
+                Some(label) => label,
+                None => match stubs.get(&target) {
+                    Some(&stub) => stub,
+                    None => {
+                        exits.insert(target, (*depths.get(target)?)?);
+                        let stub = self.code.len();
+                        self.mov_imm(Reg::Rax, target as u64);
+                        self.emit(&[0xc3]); // ret
+                        stubs.insert(target, stub);
+                        stub
+                    }
+                },
+            };
+            self.patch(at, dest);
+        }
+        Some((self.code, exits))
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn jump(&mut self, opcode: &[u8], target: Target) {
+        self.emit(opcode);
+        self.fixups.push((self.code.len(), target));
+        self.emit(&[0; 4]);
+    }
+
+    // A jump within one template; `land` says where it goes
+    fn forward(&mut self, opcode: &[u8]) -> usize {
+        self.emit(opcode);
+        self.emit(&[0; 4]);
+        self.code.len() - 4
+    }
+
+    fn land(&mut self, at: usize) {
+        self.patch(at, self.code.len());
+    }
+
+    fn patch(&mut self, at: usize, dest: usize) {
+        let displacement = dest as i32 - (at + 4) as i32;
+        self.code[at..at + 4].copy_from_slice(&displacement.to_le_bytes());
+    }
+
+    // mov reg, [rdi + 8 * slot]
+    fn load(&mut self, reg: Reg, slot: usize) {
+        self.emit(&[0x48, 0x8b, 0x80 | (reg as u8) << 3 | RDI]);
+        self.emit(&((slot * 8) as i32).to_le_bytes());
+    }
+
+    // mov [rdi + 8 * slot], reg
+    fn store(&mut self, slot: usize, reg: Reg) {
+        self.emit(&[0x48, 0x89, 0x80 | (reg as u8) << 3 | RDI]);
+        self.emit(&((slot * 8) as i32).to_le_bytes());
+    }
+
+    // mov reg, imm64
+    fn mov_imm(&mut self, reg: Reg, value: u64) {
+        self.emit(&[0x48, 0xb8 + reg as u8]);
+        self.emit(&value.to_le_bytes());
+    }
+
+    // op dst, src
+    fn alu(&mut self, opcode: u8, dst: Reg, src: Reg) {
+        self.emit(&[0x48, opcode, 0xc0 | (src as u8) << 3 | dst as u8]);
+    }
+
+    fn push_bits(&mut self, slot: usize, value: u64) {
+        self.mov_imm(Reg::Rax, value);
+        self.store(slot, Reg::Rax);
+    }
+
+    fn copy(&mut self, from: usize, to: usize) {
+        self.load(Reg::Rax, from);
+        self.store(to, Reg::Rax);
+    }
+
+    // Sets ZF when `reg` is not a number, i.e. all the QNAN bits (50 to 62)
+    // are set
+    fn test_number(&mut self, reg: Reg) {
+        self.alu(MOV, Reg::Rdx, reg);
+        self.emit(&[0x48, 0xc1, 0xea, 50]); // shr rdx, 50
+        self.emit(&[0x81, 0xe2, 0xff, 0x1f, 0, 0]); // and edx, 0x1fff
+        self.emit(&[0x81, 0xfa, 0xff, 0x1f, 0, 0]); // cmp edx, 0x1fff
+    }
+
+    // Loads a slot, leaving for the interpreter at `offset` unless it's a number
+    fn number(&mut self, reg: Reg, slot: usize, offset: usize) {
+        self.load(reg, slot);
+        self.test_number(reg);
+        self.jump(JE, Target::Exit(offset));
+    }
+
+    // rax op= rcx, as doubles
+    fn arithmetic(&mut self, op: Op) {
+        let opcode = match op {
+            Op::OpAdd => 0x58,
+            Op::OpSubtract => 0x5c,
+            Op::OpMultiply => 0x59,
+            Op::OpDivide => 0x5e,
+            _ => unreachable!(),
+        };
+        self.emit(&[0x66, 0x48, 0x0f, 0x6e, 0xc0]); // movq xmm0, rax
+        self.emit(&[0x66, 0x48, 0x0f, 0x6e, 0xc9]); // movq xmm1, rcx
+        self.emit(&[0xf2, 0x0f, opcode, 0xc1]); // op xmm0, xmm1
+        self.emit(&[0x66, 0x48, 0x0f, 0x7e, 0xc0]); // movq rax, xmm0
+    }
+
+    // cl = rax > rcx or rax < rcx, as doubles. Unordered (NaN) sets CF and
+    // ZF, so seta is false for it as it should be.
+    fn compare(&mut self, op: Op) {
+        self.emit(&[0x66, 0x48, 0x0f, 0x6e, 0xc0]); // movq xmm0, rax
+        self.emit(&[0x66, 0x48, 0x0f, 0x6e, 0xc9]); // movq xmm1, rcx
+        match op {
+            Op::OpGreater => self.emit(&[0x66, 0x0f, 0x2e, 0xc1]), // ucomisd xmm0, xmm1
+            _ => self.emit(&[0x66, 0x0f, 0x2e, 0xc8]),             // ucomisd xmm1, xmm0
+        }
+        self.emit(&[0x0f, SETA, 0xc1]);
+    }
+
+    // Two numbers compare as doubles; anything else is equal only to the
+    // same bits, strings included since they're interned
+    fn equal(&mut self, slot: usize) {
+        self.load(Reg::Rax, slot);
+        self.load(Reg::Rcx, slot + 1);
+        self.test_number(Reg::Rax);
+        let first = self.forward(JE);
+        self.test_number(Reg::Rcx);
+        let second = self.forward(JE);
+        self.emit(&[0x66, 0x48, 0x0f, 0x6e, 0xc0]); // movq xmm0, rax
+        self.emit(&[0x66, 0x48, 0x0f, 0x6e, 0xc9]); // movq xmm1, rcx
+        self.emit(&[0x66, 0x0f, 0x2e, 0xc1]); // ucomisd xmm0, xmm1
+        self.emit(&[0x0f, SETE, 0xc1]);
+        self.emit(&[0x0f, SETNP, 0xc2]);
+        self.emit(&[0x20, 0xd1]); // and cl, dl
+        let done = self.forward(JMP);
+        self.land(first);
+        self.land(second);
+        self.alu(CMP, Reg::Rax, Reg::Rcx);
+        self.emit(&[0x0f, SETE, 0xc1]);
+        self.land(done);
+        self.boolean(slot);
+    }
+
+    // cl = whether rax is nil or false
+    fn falsey(&mut self) {
+        self.mov_imm(Reg::Rdx, bits::NIL);
+        self.alu(CMP, Reg::Rax, Reg::Rdx);
+        self.emit(&[0x0f, SETE, 0xc1]);
+        self.mov_imm(Reg::Rdx, bits::FALSE);
+        self.alu(CMP, Reg::Rax, Reg::Rdx);
+        self.emit(&[0x0f, SETE, 0xc2]);
+        self.emit(&[0x08, 0xd1]); // or cl, dl
+    }
+
+    // Stores cl as a Lox bool; true is false's bits plus one
+    fn boolean(&mut self, slot: usize) {
+        self.emit(&[0x0f, 0xb6, 0xc9]); // movzx ecx, cl
+        self.mov_imm(Reg::Rax, bits::FALSE);
+        self.alu(ADD, Reg::Rax, Reg::Rcx);
+        self.store(slot, Reg::Rax);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::output::Output;
+
+    fn machine(source: &str, level: usize, threshold: Option<usize>) -> VirtualMachine {
+        let mut vm = VirtualMachine::new(Chunk::new()).with_output(Output::Buffer(Vec::new()));
+        if let Some(threshold) = threshold {
+            vm = vm.with_jit(threshold);
+        }
+        let compiler = Compiler::new()
+            .with_folding(level >= 1)
+            .with_peephole(level >= 1)
+            .with_superinstructions(level >= 2);
+        let chunk = compiler.compile(source, &mut vm.heap).unwrap();
+        vm.load(chunk);
+        vm
+    }
+
+    // Output and error with and without the JIT, and how many loops it compiled
+    fn both(source: &str, level: usize) -> (String, String, usize) {
+        let mut results = Vec::new();
+        let mut compiled = 0;
+        for threshold in [None, Some(1)] {
+            let mut vm = machine(source, level, threshold);
+            let error = vm.run().err().map(|e| e.to_string()).unwrap_or_default();
+            let output = String::from_utf8_lossy(vm.output.captured().unwrap()).into_owned();
+            results.push(format!("{}{}", output, error));
+            compiled = vm.jit().map_or(0, Jit::compiled);
+        }
+        (results.remove(0), results.remove(0), compiled)
+    }
+
+    #[test]
+    fn test_hot_loops_match_the_interpreter() {
+        let programs = [
+            "{ var t = 0; for (var i = 0; i < 1000; i = i + 1) t = t + i * 2 - i / 4; print t; }",
+            "{ var n = 0; for (var i = 0; i < 30; i = i + 1) for (var j = 0; j < i; j = j + 1) n = n + j; print n; }",
+            "{ var x = 1; var i = 0; while (i < 10) { x = -x * 3; i = i + 1; } print x; print i; }",
+            "{ var z = 0; var i = 0; while (!(i > 5)) { if (i == 3 or i == 4) z = z + 1; i = i + 1; } print z; }",
+            "{ var a = 0 / 0; var b = 1; var i = 0; while (i < 3) { b = a == a; i = i + 1; } print b; }",
+            "{ var y = 1; var i = 0; while (i < 1100) { y = y * 2; i = i + 1; } print y; print -y; }",
+            "{ var s = nil; var i = 0; while (i < 4) { s = s == nil; i = i + 1; } print s; print !s; }",
+        ];
+        for level in 0..=2 {
+            for source in programs {
+                let (interpreted, jitted, compiled) = both(source, level);
+                assert_eq!(jitted, interpreted, "-O{} {}", level, source);
+                assert!(compiled > 0, "-O{} {}", level, source);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compiled_loops_run_to_the_end() {
+        // The body and the increment both jump back, and neither leaves the
+        // compiled code until the condition fails
+        let source = "{ var t = 0; for (var i = 0; i < 1000; i = i + 1) t = t + i; print t; }";
+        for level in 0..=2 {
+            let mut vm = machine(source, level, Some(1));
+            let mut steps = 0;
+            while !vm.is_done() {
+                vm.step().unwrap();
+                steps += 1;
+            }
+            assert!(steps < 50, "-O{} took {} steps", level, steps);
+            assert_eq!(vm.output.captured(), Some("499500\n".as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_guards_fall_back_to_the_interpreter() {
+        // String concatenation, a runtime error and a print inside the loop
+        let programs = [
+            "{ var s = \"\"; var i = 0; while (i < 3) { s = s + \"ab\"; i = i + 1; } print s; }",
+            "{ var i = 0; while (i < 5) { if (i == 3) i = i + nil; i = i + 1; } }",
+            "{ for (var i = 0; i < 3; i = i + 1) print i * i; }",
+            "var g = 0; { for (var i = 0; i < 3; i = i + 1) g = g + i; } print g;",
+        ];
+        for source in programs {
+            let (interpreted, jitted, _) = both(source, 2);
+            assert_eq!(jitted, interpreted, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_threshold_and_instrumentation() {
+        let source = "{ var t = 0; for (var i = 0; i < 10; i = i + 1) t = t + i; print t; }";
+        let mut vm = machine(source, 1, Some(20));
+        vm.run().unwrap();
+        assert_eq!(vm.jit().unwrap().compiled(), 0);
+
+        let mut vm = machine(source, 1, Some(5));
+        vm.run().unwrap();
+        assert_eq!(vm.jit().unwrap().compiled(), 1);
+        assert_eq!(vm.output.captured(), Some("45\n".as_bytes()));
+
+        // Stepping back needs every instruction to go through the interpreter
+        let mut vm = machine(source, 1, Some(1));
+        vm.enable_history(100);
+        vm.run().unwrap();
+        assert_eq!(vm.jit().unwrap().compiled(), 0);
+    }
+}
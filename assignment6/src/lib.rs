@@ -1,4 +1,26 @@
 pub mod virtual_machine;
+pub mod bytecode;
 pub mod compiler;
+pub mod scanner;
+pub mod object;
+pub mod output;
+pub mod debug;
+pub mod repl;
+pub mod verifier;
+pub mod loxc;
+pub mod history;
+pub mod trace;
+pub mod profile;
+pub mod coverage;
+pub mod peephole;
+pub mod packed;
+pub mod register;
+#[cfg(feature = "jit")]
+pub mod jit;
 
-pub use virtual_machine::{VirtualMachine, InterpretResult};
+pub use coverage::Coverage;
+pub use output::Output;
+pub use profile::{Profile, Sampler};
+pub use repl::{Repl, ReplStatus};
+pub use trace::{Trace, TraceFormat, VmConfig};
+pub use virtual_machine::{Frame, InterpretResult, RuntimeError, RuntimeErrorKind, VirtualMachine};
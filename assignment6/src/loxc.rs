@@ -0,0 +1,194 @@
+use std::fmt;
+
+use crate::object::{Heap, Obj};
+use crate::verifier::{verify, VerifyError};
+use crate::virtual_machine::{Chunk, Value};
+
+// Compiled chunk file layout (all integers little-endian):
+//   "LOXC" version:u8
+//   constant_count:u32, then per constant a tag byte and its payload
+//   code_length:u32, then the code bytes as bytecode.rs encodes them
+//   then a line:u32 for each code byte
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u8 = 2;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    InvalidConstantTag(u8),
+    InvalidString,
+    Invalid(VerifyError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "not a compiled Lox file"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {} (expected {})", version, VERSION)
+            }
+            LoadError::Truncated => write!(f, "file ends in the middle of the chunk"),
+            LoadError::InvalidConstantTag(tag) => write!(f, "invalid constant tag {}", tag),
+            LoadError::InvalidString => write!(f, "string constant is not valid UTF-8"),
+            LoadError::Invalid(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+pub fn encode(chunk: &Chunk, heap: &Heap) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+
+    out.extend_from_slice(&(chunk.constants.len() as u32).to_le_bytes());
+    for constant in &chunk.constants {
+        match constant.unpack() {
+            Value::ValNil => out.push(TAG_NIL),
+            Value::ValBool(false) => out.push(TAG_FALSE),
+            Value::ValBool(true) => out.push(TAG_TRUE),
+            Value::ValNumber(n) => {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::ValObj(handle) => match heap.get(handle) {
+                Obj::ObjString(text) => {
+                    out.push(TAG_STRING);
+                    out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                    out.extend_from_slice(text.as_bytes());
+                }
+            },
+        }
+    }
+
+    out.extend_from_slice(&(chunk.code.len() as u32).to_le_bytes());
+    out.extend_from_slice(&chunk.code);
+    for &line in &chunk.lines {
+        out.extend_from_slice(&(line as u32).to_le_bytes());
+    }
+    out
+}
+
+// Reads a chunk back, interning its strings into `heap`, and verifies it
+pub fn decode(bytes: &[u8], heap: &mut Heap) -> Result<Chunk, LoadError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err(LoadError::BadMagic);
+    }
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+
+    let mut chunk = Chunk::new();
+    for _ in 0..reader.u32()? {
+        let value = match reader.byte()? {
+            TAG_NIL => Value::ValNil,
+            TAG_FALSE => Value::ValBool(false),
+            TAG_TRUE => Value::ValBool(true),
+            TAG_NUMBER => Value::ValNumber(f64::from_le_bytes(reader.array()?)),
+            TAG_STRING => {
+                let length = reader.u32()? as usize;
+                let text = std::str::from_utf8(reader.take(length)?)
+                    .map_err(|_| LoadError::InvalidString)?;
+                Value::ValObj(heap.intern(text))
+            }
+            tag => return Err(LoadError::InvalidConstantTag(tag)),
+        };
+        chunk.add_constant(value);
+    }
+
+    // Whether the bytes are valid instructions is the verifier's call
+    let length = reader.u32()? as usize;
+    chunk.code = reader.take(length)?.to_vec();
+    for _ in 0..length {
+        chunk.lines.push(reader.u32()? as usize);
+    }
+
+    verify(&chunk).map_err(LoadError::Invalid)?;
+    Ok(chunk)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.position.checked_add(count).ok_or(LoadError::Truncated)?;
+        let slice = self.bytes.get(self.position..end).ok_or(LoadError::Truncated)?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::virtual_machine::Op;
+
+    #[test]
+    fn test_round_trip() {
+        let source = "var greeting = \"hi\";\n{ var n = -1.5; print n < 2 == true; }\nprint greeting + \"!\";";
+        let mut heap = Heap::new();
+        let chunk = Compiler::new().compile(source, &mut heap).unwrap();
+        let bytes = encode(&chunk, &heap);
+
+        let mut fresh = Heap::new();
+        let loaded = decode(&bytes, &mut fresh).unwrap();
+        assert_eq!(loaded.lines, chunk.lines);
+        assert_eq!(loaded.disassemble("c", &fresh), chunk.disassemble("c", &heap));
+
+        // Superinstructions carry two operands
+        let source = "{ var a = 1; for (var i = 0; i < 3; i = i + 1) a = a + i; print a; }";
+        let compiler = Compiler::new().with_peephole(true).with_superinstructions(true);
+        let chunk = compiler.compile(source, &mut heap).unwrap();
+        let loaded = decode(&encode(&chunk, &heap), &mut fresh).unwrap();
+        assert!(chunk.instructions().any(|(_, op)| matches!(op, Op::OpLessLocalConst(..))));
+        assert_eq!(loaded.disassemble("c", &fresh), chunk.disassemble("c", &heap));
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let mut heap = Heap::new();
+        let chunk = Compiler::new().compile("print 1;", &mut heap).unwrap();
+        let bytes = encode(&chunk, &heap);
+
+        assert_eq!(decode(b"LOX", &mut heap).unwrap_err(), LoadError::Truncated);
+        assert_eq!(decode(b"ELF\x7f\x01", &mut heap).unwrap_err(), LoadError::BadMagic);
+        assert_eq!(decode(&bytes[..bytes.len() - 1], &mut heap).unwrap_err(), LoadError::Truncated);
+
+        // Code without its trailing OpReturn, or with a byte that isn't an opcode
+        let mut damaged = chunk.clone();
+        damaged.code.pop();
+        damaged.lines.pop();
+        let error = decode(&encode(&damaged, &heap), &mut heap).unwrap_err();
+        assert_eq!(error.to_string(), format!("{:04} execution falls off end of chunk", damaged.code.len()));
+        damaged.write(Op::OpReturn, 1);
+        damaged.code[0] = 99;
+        let error = decode(&encode(&damaged, &heap), &mut heap).unwrap_err();
+        assert_eq!(error.to_string(), "0000 unknown opcode 99");
+    }
+}
@@ -1,10 +1,17 @@
-mod virtual_machine;
-mod compiler;
+use std::env;
+use std::fs;
 
-use compiler::run_source;
+use assignment6::compiler::run_source;
+use assignment6::Repl;
 
 fn main() {
-    // Example: print the number 3.14
-    run_source("3.14");
+    let args: Vec<String> = env::args().collect();
+    match args.len() {
+        1 => Repl::default().run_interactive(),
+        2 => {
+            let source = fs::read_to_string(&args[1]).expect("Failed to read source file");
+            run_source(&source, &args[1]);
+        }
+        _ => eprintln!("Usage: assignment6 [script]"),
+    }
 }
-
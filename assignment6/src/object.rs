@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+// Handle to an object on the VM heap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub u32);
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Obj {
+    ObjString(String),
+}
+
+// Objects live until the heap is dropped. Strings are interned, so two
+// string values are equal exactly when their handles are.
+#[derive(Debug, Clone, Default)]
+pub struct Heap {
+    objects: Vec<Obj>,
+    strings: HashMap<String, ObjRef>,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            strings: HashMap::new(),
+        }
+    }
+
+    pub fn intern(&mut self, text: &str) -> ObjRef {
+        if let Some(&handle) = self.strings.get(text) {
+            return handle;
+        }
+        let handle = ObjRef(self.objects.len() as u32);
+        self.objects.push(Obj::ObjString(text.to_string()));
+        self.strings.insert(text.to_string(), handle);
+        handle
+    }
+
+    pub fn get(&self, handle: ObjRef) -> &Obj {
+        &self.objects[handle.0 as usize]
+    }
+
+    pub fn as_string(&self, handle: ObjRef) -> Option<&str> {
+        match self.get(handle) {
+            Obj::ObjString(text) => Some(text),
+        }
+    }
+
+    pub fn objects(&self) -> &[Obj] {
+        &self.objects
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::io::Write;
+
+// Where the VM sends program output (`print` and the final OpReturn value)
+#[derive(Default)]
+pub enum Output {
+    #[default]
+    Stdout,
+    Buffer(Vec<u8>),
+    Writer(Box<dyn Write>),
+    Callback(Box<dyn FnMut(&str)>),
+}
+
+impl Output {
+    pub fn write_line(&mut self, text: &str) {
+        match self {
+            Output::Stdout => println!("{}", text),
+            Output::Buffer(buffer) => {
+                buffer.extend_from_slice(text.as_bytes());
+                buffer.push(b'\n');
+            }
+            // A closed pipe shouldn't abort the program being run
+            Output::Writer(writer) => {
+                let _ = writeln!(writer, "{}", text);
+            }
+            Output::Callback(callback) => callback(text),
+        }
+    }
+
+    // Bytes captured so far; other sinks can't be measured and report 0
+    pub fn captured_len(&self) -> usize {
+        self.captured().map_or(0, <[u8]>::len)
+    }
+
+    // Drop captured output past `len`, e.g. when a debugger steps back
+    pub fn truncate(&mut self, len: usize) {
+        if let Output::Buffer(buffer) = self {
+            buffer.truncate(len);
+        }
+    }
+
+    // Everything written so far, when capturing into a buffer
+    pub fn captured(&self) -> Option<&[u8]> {
+        match self {
+            Output::Buffer(buffer) => Some(buffer),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Output::Stdout => write!(f, "Stdout"),
+            Output::Buffer(buffer) => write!(f, "Buffer({} bytes)", buffer.len()),
+            Output::Writer(_) => write!(f, "Writer"),
+            Output::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
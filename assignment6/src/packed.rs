@@ -0,0 +1,164 @@
+use std::fmt;
+
+use crate::object::Heap;
+#[cfg(feature = "nan-boxing")]
+use crate::object::ObjRef;
+use crate::virtual_machine::Value;
+
+// === Packed Values ===
+// How a value is stored on the VM stack and in a chunk's constant pool. By
+// default that is the Value enum itself (16 bytes). With the `nan-boxing`
+// feature every value fits in one u64 instead: numbers are their own bits,
+// and everything else hides in the payload of a quiet NaN, which no
+// arithmetic result uses once NaNs are made canonical.
+//
+//   number   any f64 whose bits don't have all of QNAN set
+//   nil      QNAN | 1
+//   false    QNAN | 2
+//   true     QNAN | 3
+//   object   SIGN | QNAN | handle
+//
+// Code outside the stack and pool works with Value; `pack` and `unpack` move
+// between the two. The JIT reads and writes the stack as raw u64s, hence
+// the transparent layout.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Packed(Repr);
+
+#[cfg(not(feature = "nan-boxing"))]
+type Repr = Value;
+
+#[cfg(feature = "nan-boxing")]
+type Repr = u64;
+
+#[cfg(feature = "nan-boxing")]
+pub(crate) mod bits {
+    pub const SIGN: u64 = 0x8000_0000_0000_0000;
+    pub const QNAN: u64 = 0x7ffc_0000_0000_0000;
+    pub const NIL: u64 = QNAN | 1;
+    pub const FALSE: u64 = QNAN | 2;
+    pub const TRUE: u64 = QNAN | 3;
+}
+
+impl Packed {
+    #[cfg(not(feature = "nan-boxing"))]
+    pub fn pack(value: Value) -> Self {
+        Packed(value)
+    }
+
+    #[cfg(not(feature = "nan-boxing"))]
+    pub fn unpack(self) -> Value {
+        self.0
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    pub fn pack(value: Value) -> Self {
+        Packed(match value {
+            // Any NaN could collide with a tag, so all of them become the one
+            // the FPU produces
+            Value::ValNumber(n) if n.is_nan() => f64::NAN.to_bits(),
+            Value::ValNumber(n) => n.to_bits(),
+            Value::ValNil => bits::NIL,
+            Value::ValBool(false) => bits::FALSE,
+            Value::ValBool(true) => bits::TRUE,
+            Value::ValObj(handle) => bits::SIGN | bits::QNAN | u64::from(handle.0),
+        })
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    pub fn unpack(self) -> Value {
+        match self.0 {
+            raw if raw & bits::QNAN != bits::QNAN => Value::ValNumber(f64::from_bits(raw)),
+            bits::NIL => Value::ValNil,
+            bits::FALSE => Value::ValBool(false),
+            bits::TRUE => Value::ValBool(true),
+            raw => Value::ValObj(ObjRef(raw as u32)),
+        }
+    }
+
+    // The raw NaN-boxed bits, for code generated by the JIT
+    #[cfg(feature = "jit")]
+    pub(crate) fn to_bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn format(self, heap: &Heap) -> String {
+        self.unpack().format(heap)
+    }
+}
+
+impl From<Value> for Packed {
+    fn from(value: Value) -> Self {
+        Packed::pack(value)
+    }
+}
+
+impl From<Packed> for Value {
+    fn from(packed: Packed) -> Self {
+        packed.unpack()
+    }
+}
+
+// Compared as values, so NaN is unequal to itself and 0 equals -0 in both
+// representations
+impl PartialEq for Packed {
+    fn eq(&self, other: &Packed) -> bool {
+        self.unpack() == other.unpack()
+    }
+}
+
+impl PartialEq<Value> for Packed {
+    fn eq(&self, other: &Value) -> bool {
+        self.unpack() == *other
+    }
+}
+
+impl fmt::Debug for Packed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.unpack().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::ObjRef;
+
+    #[test]
+    fn test_values_survive_packing() {
+        let values = [
+            Value::ValNil,
+            Value::ValBool(false),
+            Value::ValBool(true),
+            Value::ValNumber(0.0),
+            Value::ValNumber(-0.0),
+            Value::ValNumber(-1.5),
+            Value::ValNumber(f64::INFINITY),
+            Value::ValNumber(f64::NEG_INFINITY),
+            Value::ValNumber(f64::MIN_POSITIVE),
+            Value::ValNumber(f64::MAX),
+            Value::ValObj(ObjRef(0)),
+            Value::ValObj(ObjRef(u32::MAX)),
+        ];
+        for value in values {
+            let unpacked = Packed::pack(value).unpack();
+            assert_eq!(unpacked, value);
+            if let (Value::ValNumber(a), Value::ValNumber(b)) = (unpacked, value) {
+                assert_eq!(a.to_bits(), b.to_bits());
+            }
+        }
+
+        // NaNs with a payload that looks like a tag still come back as numbers
+        for bits in [0x7ffc_0000_0000_0001, 0xfffc_0000_0000_0003, 0x7ff8_0000_0000_0000] {
+            let nan = Value::ValNumber(f64::from_bits(bits));
+            assert!(matches!(Packed::pack(nan).unpack(), Value::ValNumber(n) if n.is_nan()));
+            assert_ne!(Packed::pack(nan), Packed::pack(nan));
+        }
+    }
+
+    #[test]
+    fn test_packed_size() {
+        let expected = if cfg!(feature = "nan-boxing") { 8 } else { size_of::<Value>() };
+        assert_eq!(size_of::<Packed>(), expected);
+    }
+}
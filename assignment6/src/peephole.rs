@@ -0,0 +1,436 @@
+use crate::bytecode::Listing;
+use crate::packed::Packed;
+use crate::virtual_machine::{is_falsey, Chunk, Op, Value};
+
+// === Peephole optimizer ===
+// Rewrites short instruction patterns in a compiled chunk:
+//   OpNot OpJumpIfFalse       -> OpJumpIfTrue, when both ways on pop the condition
+//   OpTrue OpJumpIfFalse      -> OpTrue, and other jumps on a literal condition
+//   x OpConstant 0 OpSubtract -> x, when x is known to be a number (likewise * 1, / 1)
+//   push OpPop                -> nothing, for pushes that can't fail
+//   jump to a jump            -> jump straight to the final target
+//   jump to the next offset   -> nothing
+//   unreachable code          -> dropped
+// `+ 0` is left alone: -0 + 0 is 0, and a string operand must still fail.
+//
+// Both passes work on the chunk's listing, where jumps hold the index of the
+// instruction they land on and "offset" means an index into the listing.
+pub fn optimize(chunk: &mut Chunk) {
+    let Listing { mut code, mut lines } = chunk.listing();
+    // One change can expose another, e.g. dropping a pop leaves a jump to the next offset
+    while round(&mut code, &mut lines, &chunk.constants) {}
+    chunk.assemble(&Listing { code, lines });
+}
+
+// === Superinstructions ===
+// Replaces common sequences with one instruction that does the same work,
+// saving a dispatch per instruction fused:
+//   OpConstant k, OpAdd               -> OpAddConstant k
+//   OpGetLocal a, OpGetLocal b        -> OpGetLocals a b
+//   OpGetLocal s, OpConstant k, OpLess -> OpLessLocalConst s k
+// Nothing is fused across a jump target. Run it after `optimize`, whose
+// patterns only know the plain instructions.
+pub fn fuse(chunk: &mut Chunk) {
+    let Listing { mut code, mut lines } = chunk.listing();
+    let is_target = jump_targets(&code);
+    let mut removed = vec![false; code.len()];
+    // The instruction `n` after `offset`, unless a jump lands on it
+    let follows = |code: &[Op], offset: usize, n: usize| {
+        code.get(offset + n).copied().filter(|_| !is_target[offset + n])
+    };
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let next = [1, 2, 3].map(|n| follows(&code, offset, n));
+        let fused = match (code[offset], next) {
+            (Op::OpGetLocal(slot), [Some(Op::OpConstant(index)), Some(Op::OpLess), _]) => {
+                Some((Op::OpLessLocalConst(slot, index), 3))
+            }
+            // Unless the second read starts a comparison, which saves more
+            (Op::OpGetLocal(a), [Some(Op::OpGetLocal(b)), third, fourth])
+                if !matches!((third, fourth), (Some(Op::OpConstant(_)), Some(Op::OpLess))) =>
+            {
+                Some((Op::OpGetLocals(a, b), 2))
+            }
+            (Op::OpConstant(index), [Some(Op::OpAdd), ..]) => Some((Op::OpAddConstant(index), 2)),
+            _ => None,
+        };
+        let Some((op, length)) = fused else {
+            offset += 1;
+            continue;
+        };
+        code[offset] = op;
+        // Runtime errors come from the last part, so report its line
+        lines[offset] = lines[offset + length - 1];
+        removed[offset + 1..offset + length].fill(true);
+        offset += length;
+    }
+
+    compact(&mut code, &mut lines, &removed);
+    chunk.assemble(&Listing { code, lines });
+}
+
+fn target(op: Op) -> Option<usize> {
+    match op {
+        Op::OpJump(target) | Op::OpJumpIfFalse(target) | Op::OpJumpIfTrue(target) => Some(target),
+        _ => None,
+    }
+}
+
+// Every rewrite once over the code; returns whether anything changed
+fn round(code: &mut Vec<Op>, lines: &mut Vec<usize>, constants: &[Packed]) -> bool {
+    let mut changed = thread_jumps(code);
+    let is_target = jump_targets(code);
+    let mut removed = unreachable(code);
+    let at = |code: &[Op], offset: usize| code.get(offset).copied();
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let matched = match (code[offset], at(code, offset + 1), at(code, offset + 2)) {
+            // A conditional jump doesn't pop, so going to the next offset does nothing
+            (Op::OpJump(target) | Op::OpJumpIfFalse(target) | Op::OpJumpIfTrue(target), _, _)
+                if target == offset + 1 =>
+            {
+                removed[offset] = true;
+                1
+            }
+            // Jumping on x rather than !x leaves x on the stack, which is fine
+            // when it's popped straight away on both paths
+            (Op::OpNot, Some(jump @ (Op::OpJumpIfFalse(target) | Op::OpJumpIfTrue(target))), Some(Op::OpPop))
+                if !is_target[offset + 1] && matches!(at(code, target), Some(Op::OpPop)) =>
+            {
+                removed[offset] = true;
+                code[offset + 1] = match jump {
+                    Op::OpJumpIfFalse(_) => Op::OpJumpIfTrue(target),
+                    _ => Op::OpJumpIfFalse(target),
+                };
+                2
+            }
+            // The condition is known, so the jump either always or never happens
+            (literal, Some(jump @ (Op::OpJumpIfFalse(target) | Op::OpJumpIfTrue(target))), _)
+                if !is_target[offset + 1] && truthiness(literal, constants).is_some() =>
+            {
+                let jumps_on = matches!(jump, Op::OpJumpIfTrue(_));
+                if truthiness(literal, constants) == Some(jumps_on) {
+                    code[offset + 1] = Op::OpJump(target);
+                } else {
+                    removed[offset + 1] = true;
+                }
+                2
+            }
+            (push, Some(Op::OpPop), _) if pushes_without_failing(push) && !is_target[offset + 1] => {
+                removed[offset] = true;
+                removed[offset + 1] = true;
+                2
+            }
+            (number, Some(Op::OpConstant(index)), Some(op))
+                if yields_number(number)
+                    && is_identity(op, constants[index].unpack())
+                    && !is_target[offset + 1]
+                    && !is_target[offset + 2] =>
+            {
+                removed[offset + 1] = true;
+                removed[offset + 2] = true;
+                3
+            }
+            _ => 1,
+        };
+        offset += matched;
+    }
+
+    if removed.contains(&true) {
+        compact(code, lines, &removed);
+        changed = true;
+    }
+    changed
+}
+
+// Whether a jump lands on each offset, including the one past the end
+fn jump_targets(code: &[Op]) -> Vec<bool> {
+    let mut is_target = vec![false; code.len() + 1];
+    for &op in code {
+        if let Some(target) = target(op) {
+            is_target[target] = true;
+        }
+    }
+    is_target
+}
+
+// Points each jump past any jumps it lands on
+fn thread_jumps(code: &mut [Op]) -> bool {
+    let mut changed = false;
+    for offset in 0..code.len() {
+        let Some(first) = target(code[offset]) else {
+            continue;
+        };
+        let conditional = !matches!(code[offset], Op::OpJump(_));
+        let mut target = first;
+        // Bounded so a cycle of jumps can't hang the compiler
+        for _ in 0..code.len() {
+            let next = match (code[offset], code.get(target).copied()) {
+                (_, Some(Op::OpJump(next))) => next,
+                // The condition is still on the stack and still decides the same way
+                (Op::OpJumpIfFalse(_), Some(Op::OpJumpIfFalse(next)))
+                | (Op::OpJumpIfTrue(_), Some(Op::OpJumpIfTrue(next))) => next,
+                // ...or the other way, so the second jump falls through
+                (Op::OpJumpIfFalse(_), Some(Op::OpJumpIfTrue(_)))
+                | (Op::OpJumpIfTrue(_), Some(Op::OpJumpIfFalse(_))) => target + 1,
+                _ => break,
+            };
+            // Only OpLoop goes backwards, and it has no conditional form
+            if next == target || (conditional && next <= offset) {
+                break;
+            }
+            target = next;
+        }
+        if target != first {
+            code[offset] = match code[offset] {
+                Op::OpJumpIfFalse(_) => Op::OpJumpIfFalse(target),
+                Op::OpJumpIfTrue(_) => Op::OpJumpIfTrue(target),
+                _ => Op::OpJump(target),
+            };
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Instructions no path from the start reaches
+fn unreachable(code: &[Op]) -> Vec<bool> {
+    let mut unreached = vec![true; code.len()];
+    let mut pending = vec![0];
+    while let Some(offset) = pending.pop() {
+        if offset >= code.len() || !unreached[offset] {
+            continue;
+        }
+        unreached[offset] = false;
+        let op = code[offset];
+        pending.extend(target(op));
+        if !matches!(op, Op::OpReturn | Op::OpJump(_)) {
+            pending.push(offset + 1);
+        }
+    }
+    unreached
+}
+
+// Drops removed instructions and their lines. A jump to a removed
+// instruction goes to the next one kept, which every rewrite allows for.
+fn compact(code: &mut Vec<Op>, lines: &mut Vec<usize>, removed: &[bool]) {
+    let mut new_offset = Vec::with_capacity(code.len() + 1);
+    let mut kept = 0;
+    for &gone in removed {
+        new_offset.push(kept);
+        kept += usize::from(!gone);
+    }
+    new_offset.push(kept);
+
+    let mut offset = 0;
+    code.retain(|_| {
+        offset += 1;
+        !removed[offset - 1]
+    });
+    let mut offset = 0;
+    lines.retain(|_| {
+        offset += 1;
+        !removed[offset - 1]
+    });
+    for op in code.iter_mut() {
+        *op = match *op {
+            Op::OpJump(target) => Op::OpJump(new_offset[target]),
+            Op::OpJumpIfFalse(target) => Op::OpJumpIfFalse(new_offset[target]),
+            Op::OpJumpIfTrue(target) => Op::OpJumpIfTrue(new_offset[target]),
+            op => op,
+        };
+    }
+}
+
+fn pushes_without_failing(op: Op) -> bool {
+    matches!(op, Op::OpConstant(_) | Op::OpNil | Op::OpTrue | Op::OpFalse | Op::OpGetLocal(_))
+}
+
+// Whether a literal push leaves a truthy value; None for anything else
+fn truthiness(op: Op, constants: &[Packed]) -> Option<bool> {
+    let value = match op {
+        Op::OpNil => Value::ValNil,
+        Op::OpTrue => Value::ValBool(true),
+        Op::OpFalse => Value::ValBool(false),
+        Op::OpConstant(index) => constants[index].unpack(),
+        _ => return None,
+    };
+    Some(!is_falsey(value))
+}
+
+// Ops that either fail or leave a number
+fn yields_number(op: Op) -> bool {
+    matches!(op, Op::OpSubtract | Op::OpMultiply | Op::OpDivide | Op::OpModulo | Op::OpNegate)
+}
+
+// x - 0, x * 1 and x / 1 are x for every number, NaN and -0 included
+fn is_identity(op: Op, operand: Value) -> bool {
+    let Value::ValNumber(n) = operand else {
+        return false;
+    };
+    match op {
+        Op::OpSubtract => n == 0.0 && n.is_sign_positive(),
+        Op::OpMultiply | Op::OpDivide => n == 1.0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::object::Heap;
+    use crate::output::Output;
+    use crate::verifier::verify;
+    use crate::virtual_machine::VirtualMachine;
+
+    fn listing(chunk: &Chunk, heap: &Heap) -> Vec<String> {
+        let text = chunk.disassemble("p", heap);
+        text.lines().skip(1).map(|line| line[10..].trim_end().to_string()).collect()
+    }
+
+    #[test]
+    fn test_rewrites() {
+        let mut heap = Heap::new();
+        let optimized = |source: &str, heap: &mut Heap| {
+            let mut chunk = Compiler::new().compile(source, heap).unwrap();
+            optimize(&mut chunk);
+            assert!(verify(&chunk).is_ok(), "{}", source);
+            chunk
+        };
+
+        let chunk = optimized("{ var a = 1; if (!a) print a; }", &mut heap);
+        assert_eq!(
+            listing(&chunk, &heap),
+            [
+                "OpConstant          0 '1'",
+                "OpGetLocal          0",
+                "OpJumpIfTrue        8 -> 0017",
+                "OpPop",
+                "OpGetLocal          0",
+                "OpPrint",
+                "OpJump              1 -> 0018",
+                "OpPop",
+                "OpPop",
+                "OpReturn",
+            ]
+        );
+
+        // The inner if's jump over its (missing) else lands on the outer one's
+        let chunk = optimized("var a; if (a) if (a) print 1;", &mut heap);
+        let text = listing(&chunk, &heap);
+        assert_eq!(text[10], "OpJump              5 -> 0030");
+        assert_eq!(text[12], "OpJump              1 -> 0030");
+
+        // The expression statement's value is never used
+        let chunk = optimized("{ var a = 1; a; print a * 1 - 0; print a - 0; }", &mut heap);
+        assert_eq!(
+            listing(&chunk, &heap),
+            [
+                "OpConstant          0 '1'",
+                "OpGetLocal          0",
+                "OpConstant          1 '1'",
+                "OpMultiply",
+                "OpPrint",
+                "OpGetLocal          0",
+                "OpConstant          3 '0'",
+                "OpSubtract",
+                "OpPrint",
+                "OpPop",
+                "OpReturn",
+            ]
+        );
+
+        // `and` into `or`: a falsey left operand skips the right one and then
+        // can't take the `or` jump, so it goes straight to the pop after it
+        let chunk = optimized("var a; print a and 1 or 2;", &mut heap);
+        let text = listing(&chunk, &heap);
+        assert_eq!(text[3], "OpJumpIfFalse       7 -> 0017");
+        assert_eq!(text[7], "OpPop");
+
+        // Nothing after an endless loop can run
+        let chunk = optimized("while (true) print 1; print 2;", &mut heap);
+        assert_eq!(listing(&chunk, &heap).last().unwrap(), "OpLoop              7 -> 0000");
+    }
+
+    #[test]
+    fn test_lines_follow_their_instructions() {
+        let mut heap = Heap::new();
+        let mut chunk = Compiler::new().compile("var a = 2;\n-a;\nprint a * 1\n  / 1;\n", &mut heap).unwrap();
+        optimize(&mut chunk);
+        assert_eq!(chunk.code.len(), chunk.lines.len());
+        let lines = chunk.instructions().map(|(offset, _)| chunk.lines[offset]);
+        let numbered: Vec<(usize, String)> = lines.zip(listing(&chunk, &heap)).collect();
+        assert_eq!(numbered[2], (2, "OpGetGlobal         2 'a'".to_string()));
+        assert_eq!(numbered[5].0, 3);
+        assert_eq!(numbered[7], (3, "OpMultiply".to_string()));
+        assert_eq!(numbered[8], (4, "OpPrint".to_string()));
+    }
+
+    // Runs a program, returning its output, error and how many instructions it dispatched
+    fn dispatches(source: &str, compiler: &Compiler) -> (String, Option<(String, usize)>, usize) {
+        let mut vm = VirtualMachine::new(Chunk::new()).with_output(Output::Buffer(Vec::new()));
+        let chunk = compiler.compile(source, &mut vm.heap).unwrap();
+        assert!(verify(&chunk).is_ok(), "{}", source);
+        vm.load(chunk);
+        let mut count = 0;
+        let mut error = None;
+        while !vm.is_done() {
+            count += 1;
+            if let Err(failure) = vm.step() {
+                error = Some((failure.message, failure.line));
+            }
+        }
+        (String::from_utf8(vm.output.captured().unwrap().to_vec()).unwrap(), error, count)
+    }
+
+    #[test]
+    fn test_fuses_superinstructions() {
+        let mut heap = Heap::new();
+        let source = "{ var a = 1; for (var i = 0; i < 10; i = i + 1) a = a + i; print a; }";
+        let mut chunk = Compiler::new().with_peephole(true).compile(source, &mut heap).unwrap();
+        fuse(&mut chunk);
+        let text = listing(&chunk, &heap);
+        assert!(text.contains(&"OpLessLocalConst    1    2 '10'".to_string()), "{:#?}", text);
+        assert!(text.contains(&"OpAddConstant       3 '1'".to_string()), "{:#?}", text);
+        assert!(text.contains(&"OpGetLocals         0    1".to_string()), "{:#?}", text);
+        assert_eq!(chunk.code.len(), chunk.lines.len());
+
+        let plain = Compiler::new().with_folding(true).with_peephole(true);
+        let fused = Compiler::new().with_folding(true).with_peephole(true).with_superinstructions(true);
+        let (output, error, before) = dispatches(source, &plain);
+        let (fused_output, fused_error, after) = dispatches(source, &fused);
+        assert_eq!((output.as_str(), error), ("46\n", None));
+        assert_eq!((fused_output.as_str(), fused_error), ("46\n", None));
+        assert_eq!((before, after), (192, 150));
+    }
+
+    #[test]
+    fn test_optimized_programs_behave_the_same() {
+        let programs = [
+            "for (var i = 0; i < 5; i = i + 1) { if (!(i > 2)) print i; else print -i; }",
+            "var a = 0; while (!(a >= 3)) { a = a + 1; } print a;",
+            "print nil and 1 or 2; print 1 and nil or false; print !nil or 3; print !(1 and 0);",
+            "var s = \"x\"; print s - 0;",
+            "{ var n = 0 / 0; print n * 1; print -0 * 1; print -0 - 0; print -0 + 0; }",
+            "var x = 1; if (x) { x; } else { 2; } print x;",
+            "for (;;) { print 1; if (true) print -nil; }",
+            "{ var a = 1; var b = \"s\"; print a < 2; print a + a; print b + \"t\"; print a + b; }",
+            "{ var a = \"x\"; print a < 1; }",
+            "{ var a = nil; print a + 1; }",
+            "{ var a = 1; var b = 2; print a < 2 == b < 2; print a + 1 + 2; }",
+        ];
+        let plain = Compiler::new();
+        let optimized = Compiler::new().with_peephole(true);
+        let fused = Compiler::new().with_peephole(true).with_superinstructions(true);
+        for source in programs {
+            let (output, error, _) = dispatches(source, &plain);
+            for compiler in [&optimized, &fused] {
+                let (optimized_output, optimized_error, _) = dispatches(source, compiler);
+                assert_eq!((&optimized_output, &optimized_error), (&output, &error), "{}", source);
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use std::time::{Duration, Instant};
+
+use crate::virtual_machine::{RuntimeError, VirtualMachine};
+
+// Executions of something and the wall time spent in them
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counter {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+// What a profiled run spent its time on, by opcode, source line and function.
+// Only the instruction itself is timed, not the stepping around it.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub ops: HashMap<&'static str, Counter>,
+    pub lines: HashMap<usize, Counter>,
+    pub functions: HashMap<String, Counter>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn total(&self) -> Counter {
+        self.ops.values().fold(Counter::default(), |total, counter| Counter {
+            count: total.count + counter.count,
+            time: total.time + counter.time,
+        })
+    }
+
+    // Hottest first in each table
+    pub fn report(&self) -> String {
+        let total = self.total();
+        let mut out = format!(
+            "== profile: {} instructions in {:.3} ms ==\n",
+            total.count,
+            total.time.as_secs_f64() * 1000.0
+        );
+        let tables = [
+            ("opcode", sorted(&self.ops)),
+            ("line", sorted(&self.lines)),
+            ("function", sorted(&self.functions)),
+        ];
+        for (title, rows) in tables {
+            let _ = writeln!(
+                out,
+                "\n{:<16} {:>10} {:>12} {:>7} {:>10}",
+                title, "count", "total ms", "time %", "avg ns"
+            );
+            for (name, counter) in rows {
+                let share = match total.time.as_nanos() {
+                    0 => 0.0,
+                    all => counter.time.as_nanos() as f64 * 100.0 / all as f64,
+                };
+                let _ = writeln!(
+                    out,
+                    "{:<16} {:>10} {:>12.3} {:>6.1}% {:>10}",
+                    name,
+                    counter.count,
+                    counter.time.as_secs_f64() * 1000.0,
+                    share,
+                    counter.time.as_nanos() / counter.count.max(1) as u128
+                );
+            }
+        }
+        out
+    }
+
+    // One row per opcode, line and function, in the same order as the report
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("kind,name,count,total_ns\n");
+        let tables = [
+            ("opcode", sorted(&self.ops)),
+            ("line", sorted(&self.lines)),
+            ("function", sorted(&self.functions)),
+        ];
+        for (kind, rows) in tables {
+            for (name, counter) in rows {
+                let _ = writeln!(out, "{},{},{},{}", kind, name, counter.count, counter.time.as_nanos());
+            }
+        }
+        out
+    }
+}
+
+// Most time first, then most executions, then by name so ties stay put
+fn sorted<K: Display>(counters: &HashMap<K, Counter>) -> Vec<(String, Counter)> {
+    let mut rows: Vec<(String, Counter)> = counters
+        .iter()
+        .map(|(key, counter)| (key.to_string(), *counter))
+        .collect();
+    rows.sort_by(|(a_name, a), (b_name, b)| {
+        b.time
+            .cmp(&a.time)
+            .then(b.count.cmp(&a.count))
+            .then_with(|| a_name.cmp(b_name))
+    });
+    rows
+}
+
+// Instructions between samples when none is given
+pub const DEFAULT_SAMPLE_INTERVAL: u64 = 1000;
+
+// Counts how often each call stack is seen, looking every `interval`
+// instructions. Cheap enough to leave on for long runs, unlike `Profile`.
+#[derive(Debug, Clone)]
+pub struct Sampler {
+    interval: u64,
+    until_next: u64,
+    pub stacks: HashMap<String, u64>, // folded stack -> samples
+}
+
+impl Sampler {
+    pub fn new(interval: u64) -> Self {
+        let interval = interval.max(1);
+        Self {
+            interval,
+            until_next: interval,
+            stacks: HashMap::new(),
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    fn due(&mut self) -> bool {
+        self.until_next -= 1;
+        if self.until_next > 0 {
+            return false;
+        }
+        self.until_next = self.interval;
+        true
+    }
+
+    // Brendan Gregg's folded format, one `outer;inner count` line per
+    // distinct stack, as flamegraph.pl and inferno read it
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<(&String, &u64)> = self.stacks.iter().collect();
+        stacks.sort();
+        stacks
+            .into_iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect()
+    }
+}
+
+impl VirtualMachine {
+    // Called before each dispatch while sampling is on
+    pub(crate) fn sample(&mut self) {
+        if !self.config.sampler.as_mut().is_some_and(Sampler::due) {
+            return;
+        }
+        let frames: Vec<String> = self
+            .call_stack()
+            .iter()
+            .rev()
+            .map(|frame| format!("{}:{}", frame.function, frame.line))
+            .collect();
+        if let Some(sampler) = &mut self.config.sampler {
+            *sampler.stacks.entry(frames.join(";")).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn execute_profiled(&mut self) -> Result<(), RuntimeError> {
+        let op = self.chunk.op_at(self.ip);
+        let line = self.chunk.lines[self.ip];
+        let start = Instant::now();
+        let result = self.execute();
+        let time = start.elapsed();
+
+        if let Some(profile) = &mut self.config.profile {
+            profile.ops.entry(op.name()).or_default().add(time);
+            profile.lines.entry(line).or_default().add(time);
+            // The script is the only function until calls exist
+            profile.functions.entry("script".to_string()).or_default().add(time);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::output::Output;
+    use crate::trace::VmConfig;
+    use crate::virtual_machine::Chunk;
+
+    fn profiled(source: &str) -> Profile {
+        let config = VmConfig {
+            profile: Some(Profile::new()),
+            ..VmConfig::default()
+        };
+        let mut vm = VirtualMachine::new(Chunk::new())
+            .with_output(Output::Buffer(Vec::new()))
+            .with_config(config);
+        let chunk = Compiler::new().compile(source, &mut vm.heap).unwrap();
+        vm.load(chunk);
+        let _ = vm.run();
+        vm.config.profile.take().unwrap()
+    }
+
+    #[test]
+    fn test_counts_by_opcode_line_and_function() {
+        let profile = profiled("var a = 1;\na = a + a + a;\nprint a;\n");
+        assert_eq!(profile.ops["OpGetGlobal"].count, 4);
+        assert_eq!(profile.ops["OpAdd"].count, 2);
+        assert_eq!(profile.lines[&1].count, 2);
+        assert_eq!(profile.lines[&2].count, 7);
+        assert_eq!(profile.functions["script"].count, profile.total().count);
+        assert_eq!(profile.total().count, 12);
+
+        // A failing instruction still counts
+        let profile = profiled("print -nil;");
+        assert_eq!(profile.ops["OpNegate"].count, 1);
+        assert!(!profile.ops.contains_key("OpPrint"));
+    }
+
+    #[test]
+    fn test_sampler_folds_call_stacks() {
+        let run = |interval| {
+            let config = VmConfig {
+                sampler: Some(Sampler::new(interval)),
+                ..VmConfig::default()
+            };
+            let mut vm = VirtualMachine::new(Chunk::new())
+                .with_output(Output::Buffer(Vec::new()))
+                .with_config(config);
+            let chunk = Compiler::new().compile("var a = 1;\na = a + a;\nprint a;\n", &mut vm.heap).unwrap();
+            vm.load(chunk);
+            vm.run().unwrap();
+            vm.config.sampler.take().unwrap().folded()
+        };
+        assert_eq!(run(1), "script:1 2\nscript:2 5\nscript:3 2\nscript:4 1\n");
+        assert_eq!(run(3), "script:2 2\nscript:3 1\n"); // instructions 3, 6 and 9 of 10
+        assert_eq!(run(0), run(1));
+        assert_eq!(run(11), "");
+    }
+
+    #[test]
+    fn test_report_and_csv_are_sorted() {
+        let mut profile = Profile::new();
+        profile.ops.insert("OpAdd", Counter { count: 2, time: Duration::from_micros(30) });
+        profile.ops.insert("OpPop", Counter { count: 5, time: Duration::from_micros(10) });
+        profile.lines.insert(7, Counter { count: 7, time: Duration::from_micros(40) });
+        profile.functions.insert("script".to_string(), Counter { count: 7, time: Duration::from_micros(40) });
+
+        let report = profile.report();
+        assert!(report.starts_with("== profile: 7 instructions in 0.040 ms ==\n"));
+        assert!(report.contains("OpAdd                     2        0.030   75.0%      15000\n"));
+        assert!(report.find("OpAdd").unwrap() < report.find("OpPop").unwrap());
+
+        assert_eq!(
+            profile.to_csv(),
+            "kind,name,count,total_ns\n\
+             opcode,OpAdd,2,30000\n\
+             opcode,OpPop,5,10000\n\
+             line,7,7,40000\n\
+             function,script,7,40000\n"
+        );
+    }
+}
@@ -0,0 +1,662 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::object::{Heap, ObjRef};
+use crate::output::Output;
+use crate::packed::Packed;
+use crate::verifier::{entry_depths, VerifyError};
+use crate::virtual_machine::{
+    binary, is_falsey, operand_message, unary, Chunk, Frame, Op, RuntimeError, RuntimeErrorKind, Value,
+};
+
+// === Register Code ===
+// An alternative backend to the stack machine. Each instruction names the
+// registers it reads and writes, so `a = a + 1` on a local is one ADD rather
+// than four stack operations. Register n holds what stack slot n would,
+// locals included. An operand that is only read may be a constant instead of
+// a register.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Reg(usize),
+    Const(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    Move(usize, Operand), // dst <- src
+    Add(usize, Operand, Operand),
+    Subtract(usize, Operand, Operand),
+    Multiply(usize, Operand, Operand),
+    Divide(usize, Operand, Operand),
+    Modulo(usize, Operand, Operand),
+    Equal(usize, Operand, Operand),
+    Greater(usize, Operand, Operand),
+    Less(usize, Operand, Operand),
+    Negate(usize, Operand),
+    Not(usize, Operand),
+    // Global names are string constants, as in the stack code
+    DefineGlobal(usize, Operand),
+    GetGlobal(usize, usize), // dst <- the global named by the constant
+    SetGlobal(usize, Operand),
+    Print(Operand),
+    // Jump targets are instruction indices
+    Jump(usize),
+    JumpIfFalse(Operand, usize),
+    JumpIfTrue(Operand, usize),
+    Return(Option<Operand>), // prints the operand, like OpReturn with a non-empty stack
+}
+
+impl Instr {
+    pub fn name(self) -> &'static str {
+        match self {
+            Instr::Move(..) => "MOVE",
+            Instr::Add(..) => "ADD",
+            Instr::Subtract(..) => "SUBTRACT",
+            Instr::Multiply(..) => "MULTIPLY",
+            Instr::Divide(..) => "DIVIDE",
+            Instr::Modulo(..) => "MODULO",
+            Instr::Equal(..) => "EQUAL",
+            Instr::Greater(..) => "GREATER",
+            Instr::Less(..) => "LESS",
+            Instr::Negate(..) => "NEGATE",
+            Instr::Not(..) => "NOT",
+            Instr::DefineGlobal(..) => "DEFINEGLOBAL",
+            Instr::GetGlobal(..) => "GETGLOBAL",
+            Instr::SetGlobal(..) => "SETGLOBAL",
+            Instr::Print(_) => "PRINT",
+            Instr::Jump(_) => "JUMP",
+            Instr::JumpIfFalse(..) => "JUMPIFFALSE",
+            Instr::JumpIfTrue(..) => "JUMPIFTRUE",
+            Instr::Return(_) => "RETURN",
+        }
+    }
+
+    // The register the instruction writes, if any
+    fn dst(self) -> Option<usize> {
+        match self {
+            Instr::Move(dst, _)
+            | Instr::Add(dst, ..)
+            | Instr::Subtract(dst, ..)
+            | Instr::Multiply(dst, ..)
+            | Instr::Divide(dst, ..)
+            | Instr::Modulo(dst, ..)
+            | Instr::Equal(dst, ..)
+            | Instr::Greater(dst, ..)
+            | Instr::Less(dst, ..)
+            | Instr::Negate(dst, _)
+            | Instr::Not(dst, _)
+            | Instr::GetGlobal(dst, _) => Some(dst),
+            _ => None,
+        }
+    }
+
+    // The same instruction writing `dst` instead
+    fn with_dst(self, dst: usize) -> Instr {
+        match self {
+            Instr::Move(_, src) => Instr::Move(dst, src),
+            Instr::Add(_, a, b) => Instr::Add(dst, a, b),
+            Instr::Subtract(_, a, b) => Instr::Subtract(dst, a, b),
+            Instr::Multiply(_, a, b) => Instr::Multiply(dst, a, b),
+            Instr::Divide(_, a, b) => Instr::Divide(dst, a, b),
+            Instr::Modulo(_, a, b) => Instr::Modulo(dst, a, b),
+            Instr::Equal(_, a, b) => Instr::Equal(dst, a, b),
+            Instr::Greater(_, a, b) => Instr::Greater(dst, a, b),
+            Instr::Less(_, a, b) => Instr::Less(dst, a, b),
+            Instr::Negate(_, src) => Instr::Negate(dst, src),
+            Instr::Not(_, src) => Instr::Not(dst, src),
+            Instr::GetGlobal(_, name) => Instr::GetGlobal(dst, name),
+            other => other,
+        }
+    }
+}
+
+// `registers` is how many registers the code needs
+#[derive(Debug, Clone, Default)]
+pub struct RegisterChunk {
+    pub code: Vec<Instr>,
+    pub lines: Vec<usize>,
+    pub constants: Vec<Packed>,
+    pub registers: usize,
+}
+
+impl RegisterChunk {
+    pub fn disassemble(&self, name: &str, heap: &Heap) -> String {
+        let mut out = format!("== {} ==\n", name);
+        for index in 0..self.code.len() {
+            out.push_str(&self.disassemble_instruction(index, heap));
+            out.push('\n');
+        }
+        out
+    }
+
+    // Same layout as Chunk::disassemble_instruction. Registers show as r0,
+    // r1, ..., constants as their quoted value.
+    pub fn disassemble_instruction(&self, index: usize, heap: &Heap) -> String {
+        let mut out = format!("{:04} ", index);
+        if index > 0 && self.lines[index] == self.lines[index - 1] {
+            out.push_str("   | ");
+        } else {
+            let _ = write!(out, "{:4} ", self.lines[index]);
+        }
+
+        let operand = |operand: Operand| match operand {
+            Operand::Reg(register) => format!("r{}", register),
+            Operand::Const(index) => format!("'{}'", self.constants[index].format(heap)),
+        };
+        let register = |register: usize| operand(Operand::Reg(register));
+        let name = |index: usize| operand(Operand::Const(index));
+        let instr = self.code[index];
+        let operands = match instr {
+            Instr::Move(dst, src) | Instr::Negate(dst, src) | Instr::Not(dst, src) => {
+                format!("{}, {}", register(dst), operand(src))
+            }
+            Instr::Add(dst, a, b)
+            | Instr::Subtract(dst, a, b)
+            | Instr::Multiply(dst, a, b)
+            | Instr::Divide(dst, a, b)
+            | Instr::Modulo(dst, a, b)
+            | Instr::Equal(dst, a, b)
+            | Instr::Greater(dst, a, b)
+            | Instr::Less(dst, a, b) => format!("{}, {}, {}", register(dst), operand(a), operand(b)),
+            Instr::DefineGlobal(global, src) | Instr::SetGlobal(global, src) => {
+                format!("{}, {}", name(global), operand(src))
+            }
+            Instr::GetGlobal(dst, global) => format!("{}, {}", register(dst), name(global)),
+            Instr::Print(src) | Instr::Return(Some(src)) => operand(src),
+            Instr::Return(None) => String::new(),
+            Instr::Jump(target) => format!("-> {:04}", target),
+            Instr::JumpIfFalse(condition, target) | Instr::JumpIfTrue(condition, target) => {
+                format!("{} -> {:04}", operand(condition), target)
+            }
+        };
+        let _ = write!(out, "{:<16} {}", instr.name(), operands);
+        out.trim_end().to_string()
+    }
+}
+
+// === Translation ===
+// Walks the stack code once, tracking where each stack slot's value is: in
+// its own register, or still just a constant or another register (a local
+// read and not yet changed). Values are only moved into their slot's
+// register where control flow joins, so both paths agree, or when the
+// register they live in is about to be overwritten.
+pub fn translate(chunk: &Chunk) -> Result<RegisterChunk, VerifyError> {
+    let depths = entry_depths(chunk)?;
+    let mut targets = vec![false; chunk.code.len() + 1];
+    for (offset, op) in chunk.instructions() {
+        if depths[offset].is_some()
+            && let Some(target) = op.jump_target(offset)
+        {
+            targets[target] = true;
+        }
+    }
+
+    let mut translator = Translator {
+        out: RegisterChunk {
+            constants: chunk.constants.clone(),
+            ..RegisterChunk::default()
+        },
+        stack: Vec::new(),
+        block_start: 0,
+        line: 1,
+        literals: HashMap::new(),
+        starts: vec![0; chunk.code.len() + 1],
+        jumps: Vec::new(),
+    };
+    let mut falls_through = true;
+    for (offset, op) in chunk.instructions() {
+        let Some(depth) = depths[offset] else {
+            falls_through = false;
+            continue;
+        };
+        if targets[offset] {
+            translator.join(depth, falls_through);
+        }
+        translator.starts[offset] = translator.out.code.len();
+        translator.line = chunk.lines[offset];
+        translator.translate(op, offset);
+        falls_through = !matches!(op, Op::OpReturn | Op::OpJump(_) | Op::OpLoop(_));
+    }
+    translator.starts[chunk.code.len()] = translator.out.code.len();
+
+    let Translator {
+        mut out, starts, jumps, ..
+    } = translator;
+    for (index, target) in jumps {
+        out.code[index] = match out.code[index] {
+            Instr::JumpIfFalse(condition, _) => Instr::JumpIfFalse(condition, starts[target]),
+            Instr::JumpIfTrue(condition, _) => Instr::JumpIfTrue(condition, starts[target]),
+            _ => Instr::Jump(starts[target]),
+        };
+    }
+    Ok(out)
+}
+
+struct Translator {
+    out: RegisterChunk,
+    stack: Vec<Operand>,         // where each stack slot's value is now
+    block_start: usize,          // first instruction since the last join
+    line: usize,                 // of the stack instruction being translated
+    literals: HashMap<u8, usize>, // constant added for nil, true or false, by opcode
+    starts: Vec<usize>,          // instruction each stack offset became
+    jumps: Vec<(usize, usize)>,  // jumps to patch, with their stack code targets
+}
+
+impl Translator {
+    fn translate(&mut self, op: Op, offset: usize) {
+        match op {
+            Op::OpConstant(index) => self.stack.push(Operand::Const(index)),
+            Op::OpNil => self.literal(op, Value::ValNil),
+            Op::OpTrue => self.literal(op, Value::ValBool(true)),
+            Op::OpFalse => self.literal(op, Value::ValBool(false)),
+            Op::OpGetLocal(slot) => self.stack.push(self.stack[slot]),
+            Op::OpSetLocal(slot) => self.set_local(slot),
+            Op::OpGetGlobal(name) => {
+                let dst = self.stack.len();
+                self.emit(Instr::GetGlobal(dst, name));
+                self.stack.push(Operand::Reg(dst));
+            }
+            Op::OpSetGlobal(name) => {
+                let value = self.top();
+                self.emit(Instr::SetGlobal(name, value));
+            }
+            Op::OpDefineGlobal(name) => {
+                let value = self.pop();
+                self.emit(Instr::DefineGlobal(name, value));
+            }
+            Op::OpPrint => {
+                let value = self.pop();
+                self.emit(Instr::Print(value));
+            }
+            Op::OpPop => {
+                self.pop();
+            }
+            Op::OpAdd
+            | Op::OpSubtract
+            | Op::OpMultiply
+            | Op::OpDivide
+            | Op::OpModulo
+            | Op::OpEqual
+            | Op::OpGreater
+            | Op::OpLess => {
+                let b = self.pop();
+                let a = self.pop();
+                let dst = self.stack.len();
+                self.emit(match op {
+                    Op::OpAdd => Instr::Add(dst, a, b),
+                    Op::OpSubtract => Instr::Subtract(dst, a, b),
+                    Op::OpMultiply => Instr::Multiply(dst, a, b),
+                    Op::OpDivide => Instr::Divide(dst, a, b),
+                    Op::OpModulo => Instr::Modulo(dst, a, b),
+                    Op::OpEqual => Instr::Equal(dst, a, b),
+                    Op::OpGreater => Instr::Greater(dst, a, b),
+                    _ => Instr::Less(dst, a, b),
+                });
+                self.stack.push(Operand::Reg(dst));
+            }
+            Op::OpNegate | Op::OpNot => {
+                let value = self.pop();
+                let dst = self.stack.len();
+                self.emit(match op {
+                    Op::OpNegate => Instr::Negate(dst, value),
+                    _ => Instr::Not(dst, value),
+                });
+                self.stack.push(Operand::Reg(dst));
+            }
+            Op::OpReturn => {
+                let value = self.stack.last().copied();
+                self.emit(Instr::Return(value));
+            }
+            Op::OpJump(_) | Op::OpLoop(_) | Op::OpJumpIfFalse(_) | Op::OpJumpIfTrue(_) => {
+                // Whichever way it goes, the code there expects every slot in its register
+                self.flush();
+                let target = op.jump_target(offset).expect("verified jump");
+                self.jumps.push((self.out.code.len(), target));
+                self.emit(match op {
+                    Op::OpJumpIfFalse(_) => Instr::JumpIfFalse(self.top(), 0),
+                    Op::OpJumpIfTrue(_) => Instr::JumpIfTrue(self.top(), 0),
+                    _ => Instr::Jump(0),
+                });
+            }
+            // Superinstructions come apart again; the register code has no use for them
+            Op::OpAddConstant(index) => {
+                self.translate(Op::OpConstant(index), offset);
+                self.translate(Op::OpAdd, offset);
+            }
+            Op::OpGetLocals(a, b) => {
+                self.translate(Op::OpGetLocal(a), offset);
+                self.translate(Op::OpGetLocal(b), offset);
+            }
+            Op::OpLessLocalConst(slot, index) => {
+                self.translate(Op::OpGetLocal(slot), offset);
+                self.translate(Op::OpConstant(index), offset);
+                self.translate(Op::OpLess, offset);
+            }
+        }
+    }
+
+    fn emit(&mut self, instr: Instr) {
+        if let Some(dst) = instr.dst() {
+            self.out.registers = self.out.registers.max(dst + 1);
+        }
+        self.out.code.push(instr);
+        self.out.lines.push(self.line);
+    }
+
+    fn top(&self) -> Operand {
+        *self.stack.last().expect("verified stack depth")
+    }
+
+    fn pop(&mut self) -> Operand {
+        self.stack.pop().expect("verified stack depth")
+    }
+
+    // nil, true and false become constants so they can be operands too
+    fn literal(&mut self, op: Op, value: Value) {
+        let constants = &mut self.out.constants;
+        let index = *self.literals.entry(op.opcode()).or_insert_with(|| {
+            constants.push(Packed::pack(value));
+            constants.len() - 1
+        });
+        self.stack.push(Operand::Const(index));
+    }
+
+    fn set_local(&mut self, slot: usize) {
+        // Values read from the local before this keep the old one
+        let mut moved = false;
+        for depth in slot + 1..self.stack.len() {
+            if self.stack[depth] == Operand::Reg(slot) {
+                self.emit(Instr::Move(depth, Operand::Reg(slot)));
+                self.stack[depth] = Operand::Reg(depth);
+                moved = true;
+            }
+        }
+
+        // The value was just computed into its temporary: compute it into
+        // the local instead, as long as nothing can jump in between
+        let top = self.stack.len() - 1;
+        let last = self.out.code.len().checked_sub(1);
+        let value = self.top();
+        match last {
+            Some(last)
+                if !moved
+                    && last >= self.block_start
+                    && value == Operand::Reg(top)
+                    && self.out.code[last].dst() == Some(top) =>
+            {
+                self.out.code[last] = self.out.code[last].with_dst(slot);
+                self.stack[top] = Operand::Reg(slot);
+            }
+            _ => self.emit(Instr::Move(slot, value)),
+        }
+        self.stack[slot] = Operand::Reg(slot);
+    }
+
+    // Moves every slot's value into its own register
+    fn flush(&mut self) {
+        for depth in 0..self.stack.len() {
+            let value = self.stack[depth];
+            if value != Operand::Reg(depth) {
+                self.emit(Instr::Move(depth, value));
+                self.stack[depth] = Operand::Reg(depth);
+            }
+        }
+    }
+
+    // A jump lands here. Code falling through has to leave things as the
+    // jumps do; after that every slot is in its register either way.
+    fn join(&mut self, depth: usize, falls_through: bool) {
+        if falls_through {
+            self.flush();
+        }
+        self.stack = (0..depth).map(Operand::Reg).collect();
+        self.block_start = self.out.code.len();
+    }
+}
+
+// === Register VM ===
+#[derive(Debug)]
+pub struct RegisterVm {
+    pub chunk: RegisterChunk,
+    pub pc: usize,
+    pub registers: Vec<Packed>,
+    pub heap: Heap,
+    pub globals: HashMap<ObjRef, Value>,
+    pub output: Output,
+    pub halted: bool,
+}
+
+impl RegisterVm {
+    pub fn new(chunk: RegisterChunk) -> Self {
+        Self {
+            registers: vec![Packed::pack(Value::ValNil); chunk.registers],
+            chunk,
+            pc: 0,
+            heap: Heap::new(),
+            globals: HashMap::new(),
+            output: Output::Stdout,
+            halted: false,
+        }
+    }
+
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.halted || self.pc >= self.chunk.code.len()
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        while !self.is_done() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        if self.is_done() {
+            return Ok(());
+        }
+        let instr = self.chunk.code[self.pc];
+        self.pc += 1;
+
+        match instr {
+            Instr::Move(dst, src) => {
+                let value = self.packed(src);
+                self.registers[dst] = value;
+            }
+            Instr::Add(dst, a, b) => self.binary(Op::OpAdd, dst, a, b)?,
+            Instr::Subtract(dst, a, b) => self.binary(Op::OpSubtract, dst, a, b)?,
+            Instr::Multiply(dst, a, b) => self.binary(Op::OpMultiply, dst, a, b)?,
+            Instr::Divide(dst, a, b) => self.binary(Op::OpDivide, dst, a, b)?,
+            Instr::Modulo(dst, a, b) => self.binary(Op::OpModulo, dst, a, b)?,
+            Instr::Equal(dst, a, b) => self.binary(Op::OpEqual, dst, a, b)?,
+            Instr::Greater(dst, a, b) => self.binary(Op::OpGreater, dst, a, b)?,
+            Instr::Less(dst, a, b) => self.binary(Op::OpLess, dst, a, b)?,
+            Instr::Negate(dst, src) => self.unary(Op::OpNegate, dst, src)?,
+            Instr::Not(dst, src) => self.unary(Op::OpNot, dst, src)?,
+            Instr::DefineGlobal(name, src) => {
+                let value = self.read(src);
+                self.globals.insert(self.global_name(name), value);
+            }
+            Instr::GetGlobal(dst, name) => {
+                let name = self.global_name(name);
+                match self.globals.get(&name) {
+                    Some(&value) => self.write(dst, value),
+                    None => return Err(self.undefined_variable(name)),
+                }
+            }
+            Instr::SetGlobal(name, src) => {
+                let name = self.global_name(name);
+                if !self.globals.contains_key(&name) {
+                    return Err(self.undefined_variable(name));
+                }
+                let value = self.read(src);
+                self.globals.insert(name, value);
+            }
+            Instr::Print(src) => {
+                let text = self.read(src).format(&self.heap);
+                self.output.write_line(&text);
+            }
+            Instr::Jump(target) => self.pc = target,
+            Instr::JumpIfFalse(condition, target) => {
+                if is_falsey(self.read(condition)) {
+                    self.pc = target;
+                }
+            }
+            Instr::JumpIfTrue(condition, target) => {
+                if !is_falsey(self.read(condition)) {
+                    self.pc = target;
+                }
+            }
+            Instr::Return(value) => {
+                if let Some(value) = value {
+                    let text = self.read(value).format(&self.heap);
+                    self.output.write_line(&text);
+                }
+                self.halted = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn packed(&self, operand: Operand) -> Packed {
+        match operand {
+            Operand::Reg(register) => self.registers[register],
+            Operand::Const(index) => self.chunk.constants[index],
+        }
+    }
+
+    fn read(&self, operand: Operand) -> Value {
+        self.packed(operand).unpack()
+    }
+
+    fn write(&mut self, dst: usize, value: Value) {
+        self.registers[dst] = Packed::pack(value);
+    }
+
+    fn binary(&mut self, op: Op, dst: usize, a: Operand, b: Operand) -> Result<(), RuntimeError> {
+        let (a, b) = (self.read(a), self.read(b));
+        match binary(op, a, b, &mut self.heap) {
+            Some(value) => {
+                self.write(dst, value);
+                Ok(())
+            }
+            None => Err(self.runtime_error(RuntimeErrorKind::TypeError, operand_message(op))),
+        }
+    }
+
+    fn unary(&mut self, op: Op, dst: usize, src: Operand) -> Result<(), RuntimeError> {
+        match unary(op, self.read(src)) {
+            Some(value) => {
+                self.write(dst, value);
+                Ok(())
+            }
+            None => Err(self.runtime_error(RuntimeErrorKind::TypeError, operand_message(op))),
+        }
+    }
+
+    fn global_name(&self, index: usize) -> ObjRef {
+        match self.chunk.constants[index].unpack() {
+            Value::ValObj(handle) => handle,
+            other => panic!("global name must be a string constant, got {:?}", other),
+        }
+    }
+
+    fn undefined_variable(&mut self, name: ObjRef) -> RuntimeError {
+        let message = format!("Undefined variable '{}'.", self.heap.as_string(name).unwrap_or("?"));
+        self.runtime_error(RuntimeErrorKind::UndefinedVariable, &message)
+    }
+
+    // Errors report the same way the stack VM's do
+    fn runtime_error(&mut self, kind: RuntimeErrorKind, message: &str) -> RuntimeError {
+        let line = self.chunk.lines.get(self.pc - 1).copied().unwrap_or(0);
+        self.halted = true;
+        RuntimeError {
+            message: message.to_string(),
+            kind,
+            line,
+            trace: vec![Frame {
+                function: "script".to_string(),
+                line,
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn translated(source: &str, heap: &mut Heap) -> RegisterChunk {
+        let chunk = Compiler::new().compile(source, heap).unwrap();
+        translate(&chunk).unwrap()
+    }
+
+    fn listing(chunk: &RegisterChunk, heap: &Heap) -> Vec<String> {
+        (0..chunk.code.len())
+            .map(|index| chunk.disassemble_instruction(index, heap)[10..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_locals_become_registers() {
+        let mut heap = Heap::new();
+        let chunk = translated("{ var a = 1; var b = 2; a = a + b; print a * 2; }", &mut heap);
+        assert_eq!(
+            listing(&chunk, &heap),
+            [
+                "ADD              r0, '1', '2'",
+                "MULTIPLY         r2, r0, '2'",
+                "PRINT            r2",
+                "RETURN",
+            ]
+        );
+        assert_eq!(chunk.registers, 3);
+    }
+
+    #[test]
+    fn test_reads_keep_values_a_local_had() {
+        let mut heap = Heap::new();
+        // `a` is read, then changed before the read value is used
+        let chunk = translated("{ var a = 1; a = 3; print a + (a = 2); }", &mut heap);
+        assert_eq!(
+            listing(&chunk, &heap),
+            [
+                "MOVE             r0, '3'",
+                "MOVE             r1, r0",
+                "MOVE             r0, '2'",
+                "ADD              r1, r1, '2'",
+                "PRINT            r1",
+                "RETURN",
+            ]
+        );
+
+        let mut vm = RegisterVm::new(chunk).with_output(Output::Buffer(Vec::new()));
+        vm.heap = heap;
+        vm.run().unwrap();
+        assert_eq!(vm.output.captured(), Some("5\n".as_bytes()));
+    }
+
+    #[test]
+    fn test_values_meet_in_registers_at_joins() {
+        let mut heap = Heap::new();
+        let source = "{ var i = 0; while (i < 3) i = i + 1; print i; }";
+        let chunk = translated(source, &mut heap);
+        assert_eq!(
+            listing(&chunk, &heap),
+            [
+                "MOVE             r0, '0'",
+                "LESS             r1, r0, '3'",
+                "JUMPIFFALSE      r1 -> 0005",
+                "ADD              r0, r0, '1'",
+                "JUMP             -> 0001",
+                "PRINT            r0",
+                "RETURN",
+            ]
+        );
+    }
+}
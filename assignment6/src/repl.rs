@@ -0,0 +1,244 @@
+use lox_diagnostics::render_all;
+use lox_repl::LineHandler;
+
+use crate::compiler::Compiler;
+use crate::debug::dump_tokens;
+use crate::output::Output;
+use crate::scanner::{Scanner, TokenType};
+use crate::virtual_machine::{Chunk, VirtualMachine};
+
+pub const PROMPT: &str = "> ";
+pub const CONTINUATION_PROMPT: &str = "... ";
+
+const HELP: &str = "\
+Enter Lox statements; a bare expression prints its value.
+Input continues while brackets are open. A blank line submits it anyway.
+  :help           show this message
+  :disasm [code]  disassemble code, or the last chunk that ran
+  :tokens <code>  show the tokens the scanner produces
+  :reset          forget all globals";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplStatus {
+    Done,
+    NeedMore,
+}
+
+// Line-at-a-time driver. The VM (and so its globals and heap) lives across
+// lines; errors go to a separate sink so they can be shown apart from output.
+pub struct Repl {
+    vm: VirtualMachine,
+    buffer: String,
+    last_chunk: Option<Chunk>,
+    errors: Output,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new(Output::Stdout, Output::Writer(Box::new(std::io::stderr())))
+    }
+}
+
+impl Repl {
+    pub fn new(output: Output, errors: Output) -> Self {
+        Self {
+            vm: VirtualMachine::new(Chunk::new()).with_output(output),
+            buffer: String::new(),
+            last_chunk: None,
+            errors,
+        }
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        }
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.vm.output
+    }
+
+    pub fn errors(&self) -> &Output {
+        &self.errors
+    }
+
+    // Reads lines from the terminal, with editing and history, until end of input
+    pub fn run_interactive(&mut self) {
+        lox_repl::run(self);
+    }
+
+    pub fn feed(&mut self, line: &str) -> ReplStatus {
+        if self.buffer.is_empty() {
+            if line.trim().is_empty() {
+                return ReplStatus::Done;
+            }
+            if let Some(command) = line.trim().strip_prefix(':') {
+                self.command(command);
+                return ReplStatus::Done;
+            }
+        }
+
+        let submit = line.trim().is_empty();
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        if !submit && !is_complete(&self.buffer) {
+            return ReplStatus::NeedMore;
+        }
+
+        let source = std::mem::take(&mut self.buffer);
+        self.execute(&source);
+        ReplStatus::Done
+    }
+
+    fn execute(&mut self, source: &str) {
+        match Compiler::for_repl().compile(source, &mut self.vm.heap) {
+            Ok(chunk) => {
+                self.vm.load(chunk.clone());
+                self.last_chunk = Some(chunk);
+                if let Err(error) = self.vm.run() {
+                    self.errors.write_line(&error.to_string());
+                }
+            }
+            Err(diagnostics) => {
+                let rendered = render_all(&diagnostics, source, "<repl>");
+                self.errors.write_line(rendered.trim_end());
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str) {
+        let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+        let argument = argument.trim();
+        match name {
+            "help" => self.vm.output.write_line(HELP),
+            "disasm" => self.disasm(argument),
+            "tokens" => {
+                let tokens = dump_tokens(argument);
+                self.vm.output.write_line(tokens.trim_end());
+            }
+            "reset" => {
+                let output = std::mem::take(&mut self.vm.output);
+                self.vm = VirtualMachine::new(Chunk::new()).with_output(output);
+                self.last_chunk = None;
+            }
+            _ => self
+                .errors
+                .write_line(&format!("Unknown command ':{}'. Try :help.", name)),
+        }
+    }
+
+    // Compiles without running so the listing can be inspected first
+    fn disasm(&mut self, source: &str) {
+        let chunk = if source.is_empty() {
+            match &self.last_chunk {
+                Some(chunk) => chunk.clone(),
+                None => {
+                    self.errors.write_line("Nothing has run yet.");
+                    return;
+                }
+            }
+        } else {
+            match Compiler::for_repl().compile(source, &mut self.vm.heap) {
+                Ok(chunk) => chunk,
+                Err(diagnostics) => {
+                    let rendered = render_all(&diagnostics, source, "<repl>");
+                    self.errors.write_line(rendered.trim_end());
+                    return;
+                }
+            }
+        };
+        let listing = chunk.disassemble("repl", &self.vm.heap);
+        self.vm.output.write_line(listing.trim_end());
+    }
+}
+
+impl LineHandler for Repl {
+    fn prompt(&self) -> &'static str {
+        Repl::prompt(self)
+    }
+
+    fn feed(&mut self, line: &str) {
+        Repl::feed(self, line);
+    }
+}
+
+// Input is complete once every bracket is closed and no string is left open
+fn is_complete(source: &str) -> bool {
+    let mut scanner = Scanner::init_scanner(source);
+    let mut depth: i32 = 0;
+    loop {
+        let token = scanner.scan_token();
+        match token.token_type {
+            TokenType::TokenLeftParen | TokenType::TokenLeftBrace => depth += 1,
+            TokenType::TokenRightParen | TokenType::TokenRightBrace => depth -= 1,
+            TokenType::TokenError if token.value == b"Unterminated string." => return false,
+            TokenType::TokenEof => return depth <= 0,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl() -> Repl {
+        Repl::new(Output::Buffer(Vec::new()), Output::Buffer(Vec::new()))
+    }
+
+    fn text(output: &Output) -> String {
+        String::from_utf8_lossy(output.captured().unwrap()).into_owned()
+    }
+
+    #[test]
+    fn test_globals_persist_and_expressions_echo() {
+        let mut repl = repl();
+        repl.feed("var a = 40;");
+        repl.feed("a + 2");
+        repl.feed("print a;");
+        assert_eq!(text(repl.output()), "42\n40\n");
+        assert_eq!(text(repl.errors()), "");
+    }
+
+    #[test]
+    fn test_unbalanced_input_continues() {
+        let mut repl = repl();
+        assert_eq!(repl.feed("{"), ReplStatus::NeedMore);
+        assert_eq!(repl.prompt(), CONTINUATION_PROMPT);
+        assert_eq!(repl.feed("  print (1 +"), ReplStatus::NeedMore);
+        assert_eq!(repl.feed("2);"), ReplStatus::NeedMore);
+        assert_eq!(repl.feed("}"), ReplStatus::Done);
+        assert_eq!(repl.prompt(), PROMPT);
+        assert_eq!(text(repl.output()), "3\n");
+    }
+
+    #[test]
+    fn test_errors_do_not_lose_state() {
+        let mut repl = repl();
+        repl.feed("var a = 1;");
+        repl.feed("a + nil");
+        repl.feed("print ;");
+        repl.feed("a");
+        assert_eq!(text(repl.output()), "1\n");
+        let errors = text(repl.errors());
+        assert!(errors.contains("Operands must be two numbers or two strings."));
+        assert!(errors.contains("error[E0002]: Expect expression."));
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let mut repl = repl();
+        repl.feed("var a = 1;");
+        repl.feed(":disasm");
+        repl.feed(":tokens a");
+        repl.feed(":reset");
+        repl.feed("a");
+        let output = text(repl.output());
+        assert!(output.contains("OpDefineGlobal      0 'a'"));
+        assert!(output.contains("TokenIdentifier 1, \"a\""));
+        assert!(text(repl.errors()).contains("Undefined variable 'a'."));
+    }
+}
@@ -1,6 +1,8 @@
 // src/scanner.rs
 // A simple scanner for a small subset of Lox tokens required by the assignments.
 
+use lox_diagnostics::Span;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum TokenType {
     // Single-character tokens
@@ -56,9 +58,18 @@ pub enum TokenType {
 #[derive(Clone, Debug)]
 pub struct Token {
     pub token_type: TokenType,
-    pub value: Vec<u8>, // raw bytes of lexeme
-    pub length: usize,
+    pub value: Vec<u8>, // raw bytes of lexeme, or the message for TokenError
+    pub length: usize,  // length of the lexeme in the source
     pub line: usize,
+    pub column: usize, // 1-based, in characters
+    pub offset: usize, // byte offset of the lexeme in the source
+}
+
+impl Token {
+    // Location of the token in the source, for diagnostics
+    pub fn span(&self) -> Span {
+        Span::new(self.offset, self.offset + self.length, self.line, self.column)
+    }
 }
 
 pub struct Scanner {
@@ -66,6 +77,9 @@ pub struct Scanner {
     start: usize,
     current: usize,
     pub line: usize,
+    line_start: usize, // byte offset where the current line begins
+    start_line: usize,
+    start_column: usize,
 }
 
 impl Scanner {
@@ -75,6 +89,9 @@ impl Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
@@ -121,7 +138,9 @@ impl Scanner {
             token_type,
             value: slice.to_vec(),
             length: slice.len(),
-            line: self.line,
+            line: self.start_line,
+            column: self.start_column,
+            offset: self.start,
         }
     }
 
@@ -129,17 +148,35 @@ impl Scanner {
         Token {
             token_type: TokenType::TokenError,
             value: message.as_bytes().to_vec(),
-            length: message.len(),
-            line: self.line,
+            length: self.current - self.start,
+            line: self.start_line,
+            column: self.start_column,
+            offset: self.start,
         }
     }
 
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    // Remember where the next token starts
+    fn mark_start(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        // Count characters rather than bytes so columns match what editors show
+        self.start_column = 1 + self.source[self.line_start..self.start]
+            .iter()
+            .filter(|&&b| b & 0xC0 != 0x80)
+            .count();
+    }
+
     fn skip_whitespace_and_comments(&mut self) {
         loop {
             let c = self.peek();
             match c {
                 b' ' | b'\r' | b'\t' => { self.advance(); }
-                b'\n' => { self.line += 1; self.advance(); }
+                b'\n' => { self.advance(); self.newline(); }
                 b'/' => {
                     if self.peek_next() == b'/' {
                         // comment to end of line
@@ -160,10 +197,10 @@ impl Scanner {
 
     fn string(&mut self) -> Token {
         while self.peek() != b'"' && !self.is_at_end() {
-            if self.peek() == b'\n' {
-                self.line += 1;
-            }
             self.advance();
+            if self.source[self.current - 1] == b'\n' {
+                self.newline();
+            }
         }
         if self.is_at_end() {
             return self.error_token("Unterminated string.");
@@ -219,7 +256,7 @@ impl Scanner {
 
     pub fn scan_token(&mut self) -> Token {
         self.skip_whitespace_and_comments();
-        self.start = self.current;
+        self.mark_start();
 
         if self.is_at_end() {
             return self.make_token(TokenType::TokenEof);
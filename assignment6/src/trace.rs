@@ -0,0 +1,169 @@
+use std::fmt::Write;
+
+use crate::debug::json_string;
+use crate::object::Heap;
+use crate::coverage::Coverage;
+use crate::output::Output;
+use crate::profile::{Profile, Sampler};
+use crate::virtual_machine::{Op, Value, VirtualMachine};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TraceFormat {
+    // The stack, then the instruction as `disassemble` shows it, like clox
+    #[default]
+    Text,
+    // One JSON object per instruction
+    Json,
+}
+
+// Where a trace goes and how it's written
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub format: TraceFormat,
+    pub sink: Output,
+}
+
+impl Trace {
+    pub fn text(sink: Output) -> Self {
+        Self {
+            format: TraceFormat::Text,
+            sink,
+        }
+    }
+
+    pub fn json(sink: Output) -> Self {
+        Self {
+            format: TraceFormat::Json,
+            sink,
+        }
+    }
+}
+
+// Options that change how the VM runs rather than what it runs
+#[derive(Debug, Default)]
+pub struct VmConfig {
+    pub trace: Option<Trace>,     // report every instruction before it executes
+    pub profile: Option<Profile>, // filled in as the program runs
+    pub sampler: Option<Sampler>, // call stacks every so many instructions
+    pub coverage: Option<Coverage>, // which instructions ran
+}
+
+impl VirtualMachine {
+    pub fn with_config(mut self, config: VmConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // Called before each dispatch while tracing is on
+    pub(crate) fn trace_instruction(&mut self) {
+        let Some(trace) = &mut self.config.trace else {
+            return;
+        };
+        match trace.format {
+            TraceFormat::Text => {
+                let mut stack = String::from("          ");
+                for value in &self.stack {
+                    let _ = write!(stack, "[ {} ]", value.format(&self.heap));
+                }
+                trace.sink.write_line(&stack);
+                trace.sink.write_line(&self.chunk.disassemble_instruction(self.ip, &self.heap));
+            }
+            TraceFormat::Json => {
+                let op = self.chunk.op_at(self.ip);
+                let mut line = format!(
+                    "{{\"offset\":{},\"line\":{},\"op\":{}",
+                    self.ip,
+                    self.chunk.lines[self.ip],
+                    json_string(op.name())
+                );
+                match op {
+                    Op::OpConstant(index)
+                    | Op::OpDefineGlobal(index)
+                    | Op::OpGetGlobal(index)
+                    | Op::OpSetGlobal(index)
+                    | Op::OpAddConstant(index) => {
+                        let constant = json_value(&self.chunk.constant(index), &self.heap);
+                        let _ = write!(line, ",\"operand\":{},\"constant\":{}", index, constant);
+                    }
+                    Op::OpGetLocal(operand)
+                    | Op::OpSetLocal(operand)
+                    | Op::OpJump(operand)
+                    | Op::OpJumpIfFalse(operand)
+                    | Op::OpJumpIfTrue(operand)
+                    | Op::OpLoop(operand) => {
+                        let _ = write!(line, ",\"operand\":{}", operand);
+                    }
+                    Op::OpGetLocals(a, b) => {
+                        let _ = write!(line, ",\"operand\":[{},{}]", a, b);
+                    }
+                    Op::OpLessLocalConst(slot, index) => {
+                        let constant = json_value(&self.chunk.constant(index), &self.heap);
+                        let _ = write!(line, ",\"operand\":[{},{}],\"constant\":{}", slot, index, constant);
+                    }
+                    _ => {}
+                }
+                let stack: Vec<String> =
+                    self.stack.iter().map(|value| json_value(&value.unpack(), &self.heap)).collect();
+                let _ = write!(line, ",\"stack\":[{}]}}", stack.join(","));
+                trace.sink.write_line(&line);
+            }
+        }
+    }
+}
+
+// JSON has no NaN or infinity, so those become strings
+fn json_value(value: &Value, heap: &Heap) -> String {
+    match value {
+        Value::ValNumber(n) if n.is_finite() => n.to_string(),
+        Value::ValNumber(n) => json_string(&n.to_string()),
+        Value::ValBool(b) => b.to_string(),
+        Value::ValNil => "null".to_string(),
+        Value::ValObj(_) => json_string(&value.format(heap)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::virtual_machine::Chunk;
+
+    fn traced(source: &str, trace: Trace) -> String {
+        let config = VmConfig {
+            trace: Some(trace),
+            ..VmConfig::default()
+        };
+        let mut vm = VirtualMachine::new(Chunk::new())
+            .with_output(Output::Buffer(Vec::new()))
+            .with_config(config);
+        let chunk = Compiler::new().compile(source, &mut vm.heap).unwrap();
+        vm.load(chunk);
+        let _ = vm.run();
+        let sink = &vm.config.trace.as_ref().unwrap().sink;
+        String::from_utf8(sink.captured().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_text_trace_shows_stack_then_instruction() {
+        let trace = traced("print 1 + 2;", Trace::text(Output::Buffer(Vec::new())));
+        let expected = "          \n\
+                        0000    1 OpConstant          0 '1'\n          [ 1 ]\n\
+                        0003    | OpConstant          1 '2'\n          [ 1 ][ 2 ]\n\
+                        0006    | OpAdd\n          [ 3 ]\n\
+                        0007    | OpPrint\n          \n\
+                        0008    | OpReturn\n";
+        assert_eq!(trace, expected);
+    }
+
+    #[test]
+    fn test_json_trace() {
+        let source = "var s = \"a\nb\";\n{ var n = 0/0; n; }";
+        let trace = traced(source, Trace::json(Output::Buffer(Vec::new())));
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines[0], r#"{"offset":0,"line":1,"op":"OpConstant","operand":1,"constant":"a\nb","stack":[]}"#);
+        assert_eq!(lines[1], r#"{"offset":3,"line":2,"op":"OpDefineGlobal","operand":0,"constant":"s","stack":["a\nb"]}"#);
+        assert_eq!(lines[6], r#"{"offset":16,"line":3,"op":"OpPop","stack":["NaN","NaN"]}"#);
+        assert!(lines[5].contains(r#""op":"OpGetLocal","operand":0,"#));
+        assert!(lines.iter().all(|line| line.starts_with('{') && line.ends_with('}')));
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+
+use crate::virtual_machine::{Chunk, Op, Value};
+
+// Largest stack depth a verified chunk may reach
+pub const STACK_MAX: usize = 256;
+
+// === Verification Errors ===
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    UnknownOpcode { byte: u8 },
+    TruncatedInstruction,
+    ConstantOutOfRange { index: usize, count: usize },
+    NameNotString { index: usize },
+    LocalOutOfRange { slot: usize, depth: usize },
+    StackUnderflow { depth: usize, needed: usize },
+    StackOverflow { depth: usize },
+    JumpOutOfRange { target: usize, count: usize },
+    JumpIntoInstruction { target: usize },
+    StackMismatch { depth: usize, expected: usize },
+    MissingLine,
+    MissingReturn,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub offset: usize,
+    pub kind: VerifyErrorKind,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04} ", self.offset)?;
+        match &self.kind {
+            VerifyErrorKind::UnknownOpcode { byte } => write!(f, "unknown opcode {}", byte),
+            VerifyErrorKind::TruncatedInstruction => write!(f, "instruction runs past end of chunk"),
+            VerifyErrorKind::ConstantOutOfRange { index, count } => {
+                write!(f, "constant index {} out of range ({} constants)", index, count)
+            }
+            VerifyErrorKind::NameNotString { index } => {
+                write!(f, "variable name constant {} is not a string", index)
+            }
+            VerifyErrorKind::LocalOutOfRange { slot, depth } => {
+                write!(f, "local slot {} out of range (depth {})", slot, depth)
+            }
+            VerifyErrorKind::StackUnderflow { depth, needed } => {
+                write!(f, "stack underflow (depth {}, needs {})", depth, needed)
+            }
+            VerifyErrorKind::StackOverflow { depth } => {
+                write!(f, "stack overflow (depth {}, max {})", depth, STACK_MAX)
+            }
+            VerifyErrorKind::JumpOutOfRange { target, count } => {
+                write!(f, "jump target {} out of range ({} bytes)", target, count)
+            }
+            VerifyErrorKind::JumpIntoInstruction { target } => {
+                write!(f, "jump target {} is inside an instruction", target)
+            }
+            VerifyErrorKind::StackMismatch { depth, expected } => {
+                write!(f, "stack depth {} here but {} on another path", depth, expected)
+            }
+            VerifyErrorKind::MissingLine => write!(f, "instruction has no line number"),
+            VerifyErrorKind::MissingReturn => write!(f, "execution falls off end of chunk"),
+        }
+    }
+}
+
+// === Verifier ===
+// Checks a chunk that didn't come from our compiler (e.g. a .loxc file) so the
+// VM can decode every instruction it reaches and index constants and stack
+// slots without bounds failures. First every byte must belong to a valid
+// instruction. Then it follows every path from the start, so each reachable
+// instruction must be entered with the same stack depth whichever way
+// control gets there. Returns the maximum stack depth reached, or the first
+// problem found.
+pub fn verify(chunk: &Chunk) -> Result<usize, VerifyError> {
+    analyze(chunk).map(|(_, max_depth)| max_depth)
+}
+
+// The stack depth on entry to the instruction at each offset, None for
+// operand bytes and unreachable code. Stack slot n is always at depth n here,
+// which is what lets the register translator give each slot a register.
+pub fn entry_depths(chunk: &Chunk) -> Result<Vec<Option<usize>>, VerifyError> {
+    analyze(chunk).map(|(depths, _)| depths)
+}
+
+fn analyze(chunk: &Chunk) -> Result<(Vec<Option<usize>>, usize), VerifyError> {
+    let count = chunk.code.len();
+    let mut is_start = vec![false; count];
+    let mut offset = 0;
+    while offset < count {
+        let Some(op) = Op::decode(&chunk.code, offset) else {
+            // An opcode that decodes once given room for its operands was cut short
+            let byte = chunk.code[offset];
+            let kind = match Op::decode(&[byte, 0, 0, 0, 0], 0) {
+                Some(_) => VerifyErrorKind::TruncatedInstruction,
+                None => VerifyErrorKind::UnknownOpcode { byte },
+            };
+            return Err(VerifyError { offset, kind });
+        };
+        is_start[offset] = true;
+        offset += op.size();
+    }
+
+    let mut entry_depth: Vec<Option<usize>> = vec![None; count];
+    let mut pending = vec![(0, 0)]; // (offset, depth) still to check
+    let mut max_depth = 0;
+
+    while let Some((offset, depth)) = pending.pop() {
+        let error = |kind| VerifyError { offset, kind };
+        if offset >= count {
+            return Err(error(VerifyErrorKind::MissingReturn));
+        }
+        let op = chunk.op_at(offset);
+        match entry_depth[offset] {
+            Some(expected) if expected == depth => continue,
+            Some(expected) => return Err(error(VerifyErrorKind::StackMismatch { depth, expected })),
+            None => entry_depth[offset] = Some(depth),
+        }
+        if offset >= chunk.lines.len() {
+            return Err(error(VerifyErrorKind::MissingLine));
+        }
+
+        match op {
+            Op::OpConstant(index) | Op::OpAddConstant(index) => check_constant(chunk, index).map_err(error)?,
+            Op::OpDefineGlobal(index) | Op::OpGetGlobal(index) | Op::OpSetGlobal(index) => {
+                check_constant(chunk, index).map_err(error)?;
+                if !matches!(chunk.constant(index), Value::ValObj(_)) {
+                    return Err(error(VerifyErrorKind::NameNotString { index }));
+                }
+            }
+            Op::OpGetLocal(slot) | Op::OpSetLocal(slot) if slot >= depth => {
+                return Err(error(VerifyErrorKind::LocalOutOfRange { slot, depth }));
+            }
+            Op::OpLessLocalConst(slot, index) => {
+                if slot >= depth {
+                    return Err(error(VerifyErrorKind::LocalOutOfRange { slot, depth }));
+                }
+                check_constant(chunk, index).map_err(error)?;
+            }
+            // The second read happens with the first value already pushed
+            Op::OpGetLocals(a, b) => {
+                if a >= depth {
+                    return Err(error(VerifyErrorKind::LocalOutOfRange { slot: a, depth }));
+                }
+                if b > depth {
+                    return Err(error(VerifyErrorKind::LocalOutOfRange { slot: b, depth: depth + 1 }));
+                }
+            }
+            _ => {}
+        }
+
+        let (pops, pushes) = stack_effect(op);
+        if depth < pops {
+            return Err(error(VerifyErrorKind::StackUnderflow { depth, needed: pops }));
+        }
+        let depth = depth - pops + pushes;
+        if depth > STACK_MAX {
+            return Err(error(VerifyErrorKind::StackOverflow { depth }));
+        }
+        max_depth = max_depth.max(depth);
+
+        // Pushed last so the fall-through path is checked first, keeping
+        // errors in offset order for straight-line code
+        if let Some(target) = op.jump_target(offset) {
+            if target >= count {
+                return Err(error(VerifyErrorKind::JumpOutOfRange { target, count }));
+            }
+            if !is_start[target] {
+                return Err(error(VerifyErrorKind::JumpIntoInstruction { target }));
+            }
+            pending.push((target, depth));
+        }
+        if !matches!(op, Op::OpReturn | Op::OpJump(_) | Op::OpLoop(_)) {
+            pending.push((offset + op.size(), depth));
+        }
+    }
+
+    Ok((entry_depth, max_depth))
+}
+
+fn check_constant(chunk: &Chunk, index: usize) -> Result<(), VerifyErrorKind> {
+    if index < chunk.constants.len() {
+        Ok(())
+    } else {
+        Err(VerifyErrorKind::ConstantOutOfRange {
+            index,
+            count: chunk.constants.len(),
+        })
+    }
+}
+
+// Number of values popped and pushed by an instruction
+fn stack_effect(op: Op) -> (usize, usize) {
+    match op {
+        // Conditional jumps leave the condition for the code after them to pop
+        Op::OpReturn
+        | Op::OpJump(_)
+        | Op::OpJumpIfFalse(_)
+        | Op::OpJumpIfTrue(_)
+        | Op::OpLoop(_) => (0, 0),
+        Op::OpConstant(_)
+        | Op::OpNil
+        | Op::OpTrue
+        | Op::OpFalse
+        | Op::OpGetGlobal(_)
+        | Op::OpGetLocal(_)
+        | Op::OpLessLocalConst(..) => (0, 1),
+        Op::OpGetLocals(..) => (0, 2),
+        Op::OpAddConstant(_) => (1, 1),
+        Op::OpPrint | Op::OpPop | Op::OpDefineGlobal(_) => (1, 0),
+        Op::OpNegate | Op::OpNot | Op::OpSetGlobal(_) | Op::OpSetLocal(_) => (1, 1),
+        Op::OpAdd
+        | Op::OpSubtract
+        | Op::OpMultiply
+        | Op::OpDivide
+        | Op::OpModulo
+        | Op::OpEqual
+        | Op::OpGreater
+        | Op::OpLess => (2, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::object::Heap;
+
+    #[test]
+    fn test_compiled_chunks_verify() {
+        let source = "var a = 1; { var b = a; b = b + 2; print b; } print a;";
+        let chunk = Compiler::new().compile(source, &mut Heap::new()).unwrap();
+        assert_eq!(verify(&chunk), Ok(3));
+    }
+
+    #[test]
+    fn test_rejects_bad_operands() {
+        // A number constant, then `middle`, then a return
+        let chunk_with = |middle: Op| {
+            let mut chunk = Chunk::new();
+            let number = chunk.add_constant(Value::ValNumber(1.0));
+            chunk.write(Op::OpConstant(number), 1);
+            chunk.write(middle, 1);
+            chunk.write(Op::OpReturn, 1);
+            chunk
+        };
+        assert_eq!(
+            verify(&chunk_with(Op::OpGetLocal(1))).unwrap_err(),
+            VerifyError {
+                offset: 3,
+                kind: VerifyErrorKind::LocalOutOfRange { slot: 1, depth: 1 }
+            }
+        );
+        assert_eq!(
+            verify(&chunk_with(Op::OpGetGlobal(0))).unwrap_err().kind,
+            VerifyErrorKind::NameNotString { index: 0 }
+        );
+        assert_eq!(
+            verify(&chunk_with(Op::OpAdd)).unwrap_err().to_string(),
+            "0003 stack underflow (depth 1, needs 2)"
+        );
+        assert_eq!(
+            verify(&chunk_with(Op::OpJump(5))).unwrap_err().to_string(),
+            "0003 jump target 11 out of range (7 bytes)"
+        );
+        assert_eq!(
+            verify(&chunk_with(Op::OpLoop(5))).unwrap_err().to_string(),
+            "0003 jump target 1 is inside an instruction"
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_bytes() {
+        let mut chunk = Chunk::new();
+        chunk.write(Op::OpNil, 1);
+        chunk.write(Op::OpReturn, 1);
+        chunk.code[1] = 99;
+        assert_eq!(verify(&chunk).unwrap_err().to_string(), "0001 unknown opcode 99");
+
+        // An OpConstant missing the second byte of its operand
+        chunk.code = vec![Op::OpConstant(0).opcode(), 0];
+        chunk.lines = vec![1; 2];
+        assert_eq!(verify(&chunk).unwrap_err().kind, VerifyErrorKind::TruncatedInstruction);
+    }
+
+    #[test]
+    fn test_follows_jumps() {
+        let source = "var a = 0; while (a < 3) { var b = a; if (b and !b or b > 1) print b; a = a + 1; }";
+        let chunk = Compiler::new().compile(source, &mut Heap::new()).unwrap();
+        assert_eq!(verify(&chunk), Ok(3));
+        let depths = entry_depths(&chunk).unwrap();
+        assert_eq!(depths[..4], [Some(0), None, None, Some(1)]);
+
+        // Skipping a push leaves the join point with two different depths
+        let mut chunk = Chunk::new();
+        chunk.write(Op::OpTrue, 1);
+        chunk.write(Op::OpJumpIfFalse(1), 1);
+        chunk.write(Op::OpNil, 1);
+        chunk.write(Op::OpReturn, 1);
+        assert_eq!(
+            verify(&chunk).unwrap_err(),
+            VerifyError {
+                offset: 5,
+                kind: VerifyErrorKind::StackMismatch { depth: 1, expected: 2 }
+            }
+        );
+
+        // A loop that never ends needs no return
+        let mut chunk = Chunk::new();
+        chunk.write(Op::OpNil, 1);
+        chunk.write(Op::OpPop, 1);
+        chunk.write(Op::OpLoop(5), 1);
+        assert_eq!(verify(&chunk), Ok(1));
+    }
+}
@@ -1,13 +1,41 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use lox_diagnostics::Diagnostic;
+
+use crate::history::{Delta, History};
+use crate::object::{Heap, Obj, ObjRef};
+use crate::output::Output;
+use crate::packed::Packed;
+use crate::trace::VmConfig;
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     ValBool(bool),
     ValNumber(f64),
     ValNil,
+    ValObj(ObjRef),
+}
+
+impl Value {
+    // How `print` shows a value: 3 rather than 3.0, strings without quotes
+    pub fn format(&self, heap: &Heap) -> String {
+        match self {
+            Value::ValBool(b) => b.to_string(),
+            Value::ValNumber(n) => n.to_string(),
+            Value::ValNil => "nil".to_string(),
+            Value::ValObj(handle) => match heap.get(*handle) {
+                Obj::ObjString(text) => text.clone(),
+            },
+        }
+    }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy)]
 pub enum Op {
-    OpConstant,
+    OpConstant(usize),
     OpAdd,
     OpSubtract,
     OpMultiply,
@@ -16,46 +44,189 @@ pub enum Op {
     OpReturn,
     OpModulo,
     OpPrint,
-    OpDefineGlobal,
+    OpDefineGlobal(usize),
+    OpGetGlobal(usize),
+    OpSetGlobal(usize),
+    OpGetLocal(usize),
+    OpSetLocal(usize),
+    OpNil,
+    OpTrue,
+    OpFalse,
+    OpNot,
+    OpEqual,
+    OpGreater,
+    OpLess,
+    OpPop,
+    // Jump operands count bytes from the end of the jump
+    OpJump(usize),
+    OpJumpIfFalse(usize), // leaves the condition on the stack
+    OpJumpIfTrue(usize),  // likewise
+    OpLoop(usize),        // jumps backwards
+    // Superinstructions the optimizer fuses from common sequences
+    OpAddConstant(usize),           // OpConstant k, OpAdd
+    OpGetLocals(usize, usize),      // OpGetLocal a, OpGetLocal b
+    OpLessLocalConst(usize, usize), // OpGetLocal slot, OpConstant k, OpLess
+}
+
+impl Op {
+    // The variant name without its operand, as listings show it
+    pub fn name(self) -> &'static str {
+        match self {
+            Op::OpConstant(_) => "OpConstant",
+            Op::OpAdd => "OpAdd",
+            Op::OpSubtract => "OpSubtract",
+            Op::OpMultiply => "OpMultiply",
+            Op::OpDivide => "OpDivide",
+            Op::OpNegate => "OpNegate",
+            Op::OpReturn => "OpReturn",
+            Op::OpModulo => "OpModulo",
+            Op::OpPrint => "OpPrint",
+            Op::OpDefineGlobal(_) => "OpDefineGlobal",
+            Op::OpGetGlobal(_) => "OpGetGlobal",
+            Op::OpSetGlobal(_) => "OpSetGlobal",
+            Op::OpGetLocal(_) => "OpGetLocal",
+            Op::OpSetLocal(_) => "OpSetLocal",
+            Op::OpNil => "OpNil",
+            Op::OpTrue => "OpTrue",
+            Op::OpFalse => "OpFalse",
+            Op::OpNot => "OpNot",
+            Op::OpEqual => "OpEqual",
+            Op::OpGreater => "OpGreater",
+            Op::OpLess => "OpLess",
+            Op::OpPop => "OpPop",
+            Op::OpJump(_) => "OpJump",
+            Op::OpJumpIfFalse(_) => "OpJumpIfFalse",
+            Op::OpJumpIfTrue(_) => "OpJumpIfTrue",
+            Op::OpLoop(_) => "OpLoop",
+            Op::OpAddConstant(_) => "OpAddConstant",
+            Op::OpGetLocals(..) => "OpGetLocals",
+            Op::OpLessLocalConst(..) => "OpLessLocalConst",
+        }
+    }
+
+    // Where a jump at `offset` goes, None for anything else. A loop back past
+    // the start gives usize::MAX, which is out of range like any bad target.
+    pub fn jump_target(self, offset: usize) -> Option<usize> {
+        let after = offset + self.size();
+        match self {
+            Op::OpJump(jump) | Op::OpJumpIfFalse(jump) | Op::OpJumpIfTrue(jump) => Some(after + jump),
+            Op::OpLoop(jump) => Some(after.checked_sub(jump).unwrap_or(usize::MAX)),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+// `code` is encoded as bytecode.rs describes; `lines` has an entry for every
+// byte of it, operands included
+#[derive(Debug, Clone, Default)]
 pub struct Chunk {
-    pub code: Vec<Op>,
-    pub constants: Vec<Value>,
+    pub code: Vec<u8>,
+    pub lines: Vec<usize>,
+    pub constants: Vec<Packed>,
 }
 
 impl Chunk {
     pub fn new() -> Self {
         Self {
             code: Vec::new(),
+            lines: Vec::new(),
             constants: Vec::new(),
         }
     }
 
-    pub fn write(&mut self, op: Op) {
-        self.code.push(op);
+    pub fn write(&mut self, op: Op, line: usize) {
+        op.encode(&mut self.code);
+        self.lines.resize(self.code.len(), line);
+    }
+
+    // The instruction starting at `offset`. Compiled and verified chunks
+    // decode everywhere execution can reach, so anything else is a bug.
+    #[inline(always)]
+    pub fn op_at(&self, offset: usize) -> Op {
+        Op::decode(&self.code, offset).unwrap_or_else(|| panic!("no instruction at offset {}", offset))
+    }
+
+    // Each instruction with its offset, up to the first byte that isn't one
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, Op)> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            let op = Op::decode(&self.code, offset)?;
+            offset += op.size();
+            Some((offset - op.size(), op))
+        })
+    }
+
+    // Overwrites the instruction at `offset` with one of the same size
+    pub fn rewrite(&mut self, offset: usize, op: Op) {
+        let mut bytes = Vec::new();
+        op.encode(&mut bytes);
+        assert_eq!(bytes.len(), self.op_at(offset).size(), "rewrite must keep the instruction size");
+        self.code[offset..offset + bytes.len()].copy_from_slice(&bytes);
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
-        self.constants.push(value);
+        self.constants.push(Packed::pack(value));
         self.constants.len() - 1
     }
+
+    pub fn constant(&self, index: usize) -> Value {
+        self.constants[index].unpack()
+    }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InterpretResult {
     InterpretOk,
-    InterpretCompileError,
+    InterpretCompileError(Vec<Diagnostic>),
     InterpretRuntimeError,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeErrorKind {
+    TypeError,
+    StackUnderflow,
+    UndefinedVariable,
+}
+
+// One entry of a stack trace, innermost call first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub message: String,
+    pub kind: RuntimeErrorKind,
+    pub line: usize,
+    pub trace: Vec<Frame>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in &self.trace {
+            write!(f, "\n[line {}] in {}", frame.line, frame.function)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct VirtualMachine {
     pub chunk: Chunk,
     pub ip: usize,
-    pub stack: Vec<Value>,
+    pub stack: Vec<Packed>,
+    pub heap: Heap,
+    pub globals: HashMap<ObjRef, Value>,
+    pub output: Output,
+    pub halted: bool, // set by OpReturn or a runtime error
+    pub history: Option<History>,
+    pub(crate) delta: Option<Delta>, // undo record for the step in progress
+    pub config: VmConfig,
+    #[cfg(feature = "jit")]
+    pub(crate) jit: Option<crate::jit::Jit>, // set by with_jit
 }
 
 impl VirtualMachine {
@@ -64,60 +235,388 @@ impl VirtualMachine {
             chunk,
             ip: 0,
             stack: Vec::new(),
+            heap: Heap::new(),
+            globals: HashMap::new(),
+            output: Output::Stdout,
+            halted: false,
+            history: None,
+            delta: None,
+            config: VmConfig::default(),
+            #[cfg(feature = "jit")]
+            jit: None,
         }
     }
 
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    // Swap in a new chunk, keeping the heap and globals (used by the REPL)
+    pub fn load(&mut self, chunk: Chunk) {
+        self.chunk = chunk;
+        self.ip = 0;
+        self.stack.clear();
+        self.halted = false;
+        if let Some(history) = &mut self.history {
+            *history = History::new(history.limit());
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.reset();
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.halted || self.ip >= self.chunk.code.len()
+    }
+
     pub fn interpret(&mut self) -> InterpretResult {
-        while self.ip < self.chunk.code.len() {
-            let op = self.chunk.code[self.ip];
-            self.ip += 1;
-
-            match op {
-                Op::OpConstant => {
-                    if let Some(constant) = self.chunk.constants.get(0) {
-                        self.stack.push(*constant);
-                    }
+        match self.run() {
+            Ok(()) => InterpretResult::InterpretOk,
+            Err(error) => {
+                eprintln!("{}", error);
+                InterpretResult::InterpretRuntimeError
+            }
+        }
+    }
+
+    // Runs until OpReturn or the end of the chunk. On error the stack is reset
+    // and execution stops at the failing instruction.
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        while !self.is_done() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // Executes the single instruction at `ip`, for debuggers
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        if self.is_done() {
+            return Ok(());
+        }
+        if self.config.trace.is_some() {
+            self.trace_instruction();
+        }
+        if self.config.sampler.is_some() {
+            self.sample();
+        }
+        if let Some(coverage) = &mut self.config.coverage {
+            coverage.hit(self.ip);
+        }
+        self.begin_delta();
+        let result = if self.config.profile.is_some() {
+            self.execute_profiled()
+        } else {
+            self.execute()
+        };
+        self.end_delta();
+        result
+    }
+
+    pub(crate) fn execute(&mut self) -> Result<(), RuntimeError> {
+        let op = self.chunk.op_at(self.ip);
+        self.ip += op.size();
+
+        match op {
+            Op::OpConstant(index) => {
+                let constant = self.chunk.constants[index];
+                self.stack.push(constant);
+            }
+            Op::OpAdd
+            | Op::OpSubtract
+            | Op::OpMultiply
+            | Op::OpDivide
+            | Op::OpModulo
+            | Op::OpEqual
+            | Op::OpGreater
+            | Op::OpLess => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                match binary(op, a, b, &mut self.heap) {
+                    Some(value) => self.push(value),
+                    None => return Err(self.operand_error(op)),
                 }
-                Op::OpAdd => self.binary_op(|a, b| a + b),
-                Op::OpSubtract => self.binary_op(|a, b| a - b),
-                Op::OpMultiply => self.binary_op(|a, b| a * b),
-                Op::OpDivide => self.binary_op(|a, b| a / b),
-                Op::OpModulo => self.binary_op(|a, b| a % b),
-                Op::OpNegate => {
-                    if let Some(Value::ValNumber(v)) = self.stack.pop() {
-                        self.stack.push(Value::ValNumber(-v));
-                    }
+            }
+            Op::OpNegate | Op::OpNot => {
+                let value = self.pop()?;
+                match unary(op, value) {
+                    Some(value) => self.push(value),
+                    None => return Err(self.operand_error(op)),
                 }
-                Op::OpPrint => {
-                    if let Some(value) = self.stack.last() {
-                        println!("{:?}", value);
-                    }
+            }
+            Op::OpPrint => {
+                let value = self.pop()?;
+                let text = value.format(&self.heap);
+                self.output.write_line(&text);
+            }
+            Op::OpReturn => {
+                if let Some(value) = self.stack.last() {
+                    let text = value.format(&self.heap);
+                    self.output.write_line(&text);
                 }
-                Op::OpReturn => {
-                    if let Some(v) = self.stack.last() {
-                        match v {
-                            Value::ValNumber(num) => println!("{}", num),
-                            Value::ValBool(b) => println!("{}", b),
-                            Value::ValNil => println!("nil"),
-                        }
-                    }
-                    return InterpretResult::InterpretOk;
+                self.halted = true;
+            }
+            Op::OpDefineGlobal(index) => {
+                let name = self.global_name(index);
+                let value = self.pop()?;
+                self.record_global(name);
+                self.globals.insert(name, value);
+            }
+            Op::OpGetGlobal(index) => {
+                let name = self.global_name(index);
+                match self.globals.get(&name) {
+                    Some(&value) => self.push(value),
+                    None => return Err(self.undefined_variable(name)),
+                }
+            }
+            Op::OpSetGlobal(index) => {
+                // Assignment is an expression, so the value stays on the stack
+                let name = self.global_name(index);
+                let value = self.peek()?;
+                if !self.globals.contains_key(&name) {
+                    return Err(self.undefined_variable(name));
+                }
+                self.record_global(name);
+                self.globals.insert(name, value);
+            }
+            Op::OpGetLocal(slot) => {
+                let value = self.stack[slot];
+                self.stack.push(value);
+            }
+            Op::OpSetLocal(slot) => {
+                let value = self.peek()?;
+                self.record_slot(slot);
+                self.stack[slot] = Packed::pack(value);
+            }
+            Op::OpNil => self.push(Value::ValNil),
+            Op::OpTrue => self.push(Value::ValBool(true)),
+            Op::OpFalse => self.push(Value::ValBool(false)),
+            Op::OpPop => {
+                self.pop()?;
+            }
+            Op::OpJump(offset) => self.ip += offset,
+            Op::OpJumpIfFalse(offset) => {
+                if is_falsey(self.peek()?) {
+                    self.ip += offset;
+                }
+            }
+            Op::OpJumpIfTrue(offset) => {
+                if !is_falsey(self.peek()?) {
+                    self.ip += offset;
+                }
+            }
+            Op::OpLoop(offset) => {
+                self.ip -= offset;
+                #[cfg(feature = "jit")]
+                self.loop_taken(self.ip + offset - op.size());
+            }
+            Op::OpAddConstant(index) => {
+                let a = self.pop()?;
+                let b = self.chunk.constant(index);
+                match binary(Op::OpAdd, a, b, &mut self.heap) {
+                    Some(value) => self.push(value),
+                    None => return Err(self.operand_error(Op::OpAdd)),
+                }
+            }
+            Op::OpGetLocals(a, b) => {
+                let value = self.stack[a];
+                self.stack.push(value);
+                let value = self.stack[b];
+                self.stack.push(value);
+            }
+            Op::OpLessLocalConst(slot, index) => {
+                let a = self.stack[slot].unpack();
+                let b = self.chunk.constant(index);
+                match binary(Op::OpLess, a, b, &mut self.heap) {
+                    Some(value) => self.push(value),
+                    None => return Err(self.operand_error(Op::OpLess)),
                 }
-                Op::OpDefineGlobal => {} // placeholder for future variable definition
             }
         }
 
-        InterpretResult::InterpretOk
+        Ok(())
+    }
+
+    fn operand_error(&mut self, op: Op) -> RuntimeError {
+        self.runtime_error(RuntimeErrorKind::TypeError, operand_message(op))
+    }
+
+    fn global_name(&self, index: usize) -> ObjRef {
+        match self.chunk.constant(index) {
+            Value::ValObj(handle) => handle,
+            other => panic!("global name must be a string constant, got {:?}", other),
+        }
+    }
+
+    fn undefined_variable(&mut self, name: ObjRef) -> RuntimeError {
+        let message = format!("Undefined variable '{}'.", self.heap.as_string(name).unwrap_or("?"));
+        self.runtime_error(RuntimeErrorKind::UndefinedVariable, &message)
+    }
+
+    fn push(&mut self, value: Value) {
+        self.stack.push(Packed::pack(value));
+    }
+
+    fn peek(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.last() {
+            Some(value) => Ok(value.unpack()),
+            None => Err(self.runtime_error(RuntimeErrorKind::StackUnderflow, "Stack underflow.")),
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, RuntimeError> {
+        match self.stack.pop() {
+            Some(value) => {
+                self.record_pop(value);
+                Ok(value.unpack())
+            }
+            None => Err(self.runtime_error(RuntimeErrorKind::StackUnderflow, "Stack underflow.")),
+        }
+    }
+
+    // Builds the error for the instruction that just executed and resets the stack
+    fn runtime_error(&mut self, kind: RuntimeErrorKind, message: &str) -> RuntimeError {
+        let line = self.chunk.lines.get(self.ip - 1).copied().unwrap_or(0);
+        while let Some(value) = self.stack.pop() {
+            self.record_pop(value);
+        }
+        self.halted = true;
+        RuntimeError {
+            message: message.to_string(),
+            kind,
+            line,
+            trace: self.frames_at(line),
+        }
+    }
+
+    // Calls in progress, innermost first, each at the line it has reached
+    pub fn call_stack(&self) -> Vec<Frame> {
+        let offset = if self.is_done() { self.ip.saturating_sub(1) } else { self.ip };
+        self.frames_at(self.chunk.lines.get(offset).copied().unwrap_or(0))
+    }
+
+    // The script is the only frame until functions exist
+    fn frames_at(&self, line: usize) -> Vec<Frame> {
+        vec![Frame {
+            function: "script".to_string(),
+            line,
+        }]
+    }
+}
+
+// What an arithmetic, comparison or equality op makes of its operands, or
+// None when they have the wrong types. The compiler folds constants with
+// this too, so folded code can't behave differently from the VM.
+pub(crate) fn binary(op: Op, a: Value, b: Value, heap: &mut Heap) -> Option<Value> {
+    use Value::{ValBool, ValNumber, ValObj};
+    let value = match (op, a, b) {
+        (Op::OpEqual, a, b) => ValBool(a == b),
+        // Numbers add, strings concatenate
+        (Op::OpAdd, ValObj(a), ValObj(b)) => {
+            let joined = format!("{}{}", heap.as_string(a)?, heap.as_string(b)?);
+            ValObj(heap.intern(&joined))
+        }
+        (op, ValNumber(a), ValNumber(b)) => match op {
+            Op::OpAdd => ValNumber(a + b),
+            Op::OpSubtract => ValNumber(a - b),
+            Op::OpMultiply => ValNumber(a * b),
+            Op::OpDivide => ValNumber(a / b),
+            Op::OpModulo => ValNumber(a % b),
+            Op::OpGreater => ValBool(a > b),
+            Op::OpLess => ValBool(a < b),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(value)
+}
+
+// The type error when `binary` or `unary` rejects an op's operands
+pub(crate) fn operand_message(op: Op) -> &'static str {
+    match op {
+        Op::OpAdd => "Operands must be two numbers or two strings.",
+        Op::OpNegate => "Operand must be a number.",
+        _ => "Operands must be numbers.",
+    }
+}
+
+// Same for `-` and `!`
+pub(crate) fn unary(op: Op, value: Value) -> Option<Value> {
+    match (op, value) {
+        (Op::OpNegate, Value::ValNumber(v)) => Some(Value::ValNumber(-v)),
+        (Op::OpNot, value) => Some(Value::ValBool(is_falsey(value))),
+        _ => None,
     }
+}
+
+// nil and false are falsey, everything else is truthy
+pub(crate) fn is_falsey(value: Value) -> bool {
+    matches!(value, Value::ValBool(false) | Value::ValNil)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn binary_op<F>(&mut self, op: F)
-    where
-        F: Fn(f64, f64) -> f64,
-    {
-        if let (Some(Value::ValNumber(b)), Some(Value::ValNumber(a))) =
-            (self.stack.pop(), self.stack.pop())
-        {
-            self.stack.push(Value::ValNumber(op(a, b)));
+    #[test]
+    fn test_type_error_stops_execution() {
+        let mut chunk = Chunk::new();
+        let number = chunk.add_constant(Value::ValNumber(1.0));
+        let boolean = chunk.add_constant(Value::ValBool(true));
+        chunk.write(Op::OpConstant(number), 1);
+        chunk.write(Op::OpConstant(boolean), 2);
+        chunk.write(Op::OpSubtract, 2);
+        chunk.write(Op::OpConstant(number), 3);
+        chunk.write(Op::OpReturn, 3);
+
+        let mut vm = VirtualMachine::new(chunk);
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::TypeError);
+        assert_eq!(error.line, 2);
+        assert_eq!(error.to_string(), "Operands must be numbers.\n[line 2] in script");
+        assert!(vm.stack.is_empty());
+        assert_eq!(vm.ip, 7);
+    }
+
+    #[test]
+    fn test_undefined_global_is_a_runtime_error() {
+        let mut heap = Heap::new();
+        let name = heap.intern("missing");
+        let mut chunk = Chunk::new();
+        let index = chunk.add_constant(Value::ValObj(name));
+        chunk.write(Op::OpGetGlobal(index), 4);
+        chunk.write(Op::OpReturn, 4);
+
+        let mut vm = VirtualMachine::new(chunk);
+        vm.heap = heap;
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::UndefinedVariable);
+        assert_eq!(error.to_string(), "Undefined variable 'missing'.\n[line 4] in script");
+    }
+
+    #[test]
+    fn test_print_uses_lox_formatting() {
+        let mut heap = Heap::new();
+        let greeting = heap.intern("hi");
+        let mut chunk = Chunk::new();
+        for value in [
+            Value::ValNumber(3.0),
+            Value::ValNumber(2.5),
+            Value::ValNil,
+            Value::ValBool(true),
+            Value::ValObj(greeting),
+        ] {
+            let index = chunk.add_constant(value);
+            chunk.write(Op::OpConstant(index), 1);
+            chunk.write(Op::OpPrint, 1);
         }
+        chunk.write(Op::OpReturn, 1);
+
+        let mut vm = VirtualMachine::new(chunk).with_output(Output::Buffer(Vec::new()));
+        vm.heap = heap;
+        vm.run().unwrap();
+        assert_eq!(vm.output.captured(), Some("3\n2.5\nnil\ntrue\nhi\n".as_bytes()));
+        assert!(vm.stack.is_empty());
     }
 }
@@ -0,0 +1,146 @@
+use assignment6::compiler::Compiler;
+use assignment6::object::Heap;
+use assignment6::register::{translate, RegisterVm};
+use assignment6::virtual_machine::{Chunk, VirtualMachine};
+use assignment6::{Output, RuntimeError};
+
+// Every program runs on both backends, which must print the same and fail
+// the same way
+const PROGRAMS: [&str; 14] = [
+    "print 1 + 2 * 3 - 4 / 2; print -(5 - 3); print !nil;",
+    "print \"con\" + \"cat\"; print \"a\" == \"a\"; print 1 == nil;",
+    "var a = 1; var b = a; a = a + 1; print a; print b; print a = 10; print a;",
+    "{ var a = 1; var b = a; a = a + 1; print a; print b; print a = 10; print a; }",
+    "{ var a = 1; { var b = a + 1; { var c = b * a; a = c + b; } print b; } print a; }",
+    "{ var a = 1; print a + (a = 2); print a; print (a = 3) + a; }",
+    "var x = 3; if (x > 2) print \"big\"; else print \"small\"; if (nil) print 1; else print 2;",
+    "{ var x = 0; if (x < 1 and x >= 0 or false) print \"in\"; print x == 0 or x; print nil and x; }",
+    "{ var n = 0; for (var i = 0; i < 10; i = i + 1) { if (i / 3 > 1) n = n + i; } print n; }",
+    "var total = 0; var i = 0; while (i < 5) { var sq = i * i; total = total + sq; i = i + 1; } print total;",
+    "{ var a = 0; var b = 1; for (var i = 0; i < 20; i = i + 1) { var t = a + b; a = b; b = t; } print a; }",
+    "{ var s = \"\"; var i = 0; while (i < 3) { s = s + \"ab\"; i = i + 1; } print s; }",
+    "print 1; { var a = 2; print a - true; } print 3;",
+    "var a = 1; print a; print b; a = 2;",
+];
+
+struct Run {
+    output: String,
+    error: Option<String>,
+    dispatched: usize,
+}
+
+fn compiler(level: usize) -> Compiler {
+    Compiler::new()
+        .with_folding(level >= 1)
+        .with_peephole(level >= 1)
+        .with_superinstructions(level >= 2)
+}
+
+fn captured(output: &Output) -> String {
+    String::from_utf8_lossy(output.captured().unwrap()).into_owned()
+}
+
+// Steps until `step` says the program is done, counting the instructions
+// dispatched and keeping the error that stopped it, if any
+fn drive(mut step: impl FnMut() -> Option<Result<(), RuntimeError>>) -> (usize, Option<String>) {
+    let mut dispatched = 0;
+    let mut error = None;
+    while let Some(result) = step() {
+        dispatched += 1;
+        if let Err(e) = result {
+            error = Some(e.to_string());
+        }
+    }
+    (dispatched, error)
+}
+
+fn stack_run(chunk: &Chunk, heap: &Heap) -> Run {
+    let mut vm = VirtualMachine::new(chunk.clone()).with_output(Output::Buffer(Vec::new()));
+    vm.heap = heap.clone();
+    let (dispatched, error) = drive(|| (!vm.is_done()).then(|| vm.step()));
+    Run {
+        output: captured(&vm.output),
+        error,
+        dispatched,
+    }
+}
+
+fn register_run(chunk: &Chunk, heap: &Heap) -> Run {
+    let mut vm = RegisterVm::new(translate(chunk).unwrap()).with_output(Output::Buffer(Vec::new()));
+    vm.heap = heap.clone();
+    let (dispatched, error) = drive(|| (!vm.is_done()).then(|| vm.step()));
+    Run {
+        output: captured(&vm.output),
+        error,
+        dispatched,
+    }
+}
+
+// Every loop compiled on its first trip round
+#[cfg(feature = "jit")]
+fn jit_run(chunk: &Chunk, heap: &Heap) -> Run {
+    let mut vm = VirtualMachine::new(chunk.clone())
+        .with_output(Output::Buffer(Vec::new()))
+        .with_jit(1);
+    vm.heap = heap.clone();
+    let error = vm.run().err().map(|e| e.to_string());
+    Run {
+        output: captured(&vm.output),
+        error,
+        dispatched: 0,
+    }
+}
+
+#[test]
+fn test_backends_agree() {
+    for level in 0..=2 {
+        for source in PROGRAMS {
+            let mut heap = Heap::new();
+            let chunk = compiler(level).compile(source, &mut heap).unwrap();
+            let (stack, register) = (stack_run(&chunk, &heap), register_run(&chunk, &heap));
+            assert_eq!(register.output, stack.output, "-O{} {}", level, source);
+            assert_eq!(register.error, stack.error, "-O{} {}", level, source);
+        }
+    }
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn test_jit_agrees_with_interpreter() {
+    for level in 0..=2 {
+        for source in PROGRAMS {
+            let mut heap = Heap::new();
+            let chunk = compiler(level).compile(source, &mut heap).unwrap();
+            let (stack, jit) = (stack_run(&chunk, &heap), jit_run(&chunk, &heap));
+            assert_eq!(jit.output, stack.output, "-O{} {}", level, source);
+            assert_eq!(jit.error, stack.error, "-O{} {}", level, source);
+        }
+    }
+}
+
+#[test]
+fn test_errors_match() {
+    let mut heap = Heap::new();
+    let chunk = Compiler::new().compile(PROGRAMS[12], &mut heap).unwrap();
+    let run = register_run(&chunk, &heap);
+    assert_eq!(run.output, "1\n");
+    assert_eq!(run.error.unwrap(), "Operands must be numbers.\n[line 1] in script");
+}
+
+#[test]
+fn test_register_code_dispatches_less() {
+    // Summed over the programs, with the register code translated from the
+    // same -O1 stack code it's compared against
+    let total = |run: fn(&Chunk, &Heap) -> Run| -> usize {
+        PROGRAMS
+            .iter()
+            .map(|source| {
+                let mut heap = Heap::new();
+                let chunk = compiler(1).compile(source, &mut heap).unwrap();
+                run(&chunk, &heap).dispatched
+            })
+            .sum()
+    };
+    let (stack, register) = (total(stack_run), total(register_run));
+    assert!(register * 2 < stack, "{} vs {}", register, stack);
+}
@@ -0,0 +1,196 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn lox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lox"))
+        .args(args)
+        .output()
+        .expect("failed to start lox")
+}
+
+// A scratch file unique to this test, so tests can run in parallel
+fn script(name: &str, source: &str) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!("lox_cli_{}_{}", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn test_run_and_check() {
+    let ok = script("ok.lox", "var a = 2;\nprint a * 21;\n");
+    let output = lox(&["run", &ok]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "42\n");
+    assert_eq!(lox(&["check", &ok]).status.code(), Some(0));
+    assert_eq!(stdout(&lox(&["check", &ok])), "");
+}
+
+#[test]
+fn test_exit_codes() {
+    let bad_syntax = script("bad_syntax.lox", "print ;\n");
+    let bad_types = script("bad_types.lox", "print 1 + nil;\n");
+    assert_eq!(lox(&[]).status.code(), Some(64));
+    assert_eq!(lox(&["frobnicate", &bad_syntax]).status.code(), Some(64));
+    assert_eq!(lox(&["compile", &bad_syntax]).status.code(), Some(64));
+    assert_eq!(lox(&["check", &bad_syntax]).status.code(), Some(65));
+    assert_eq!(lox(&["run", &bad_syntax]).status.code(), Some(65));
+    assert_eq!(lox(&["check", &bad_types]).status.code(), Some(0));
+    assert_eq!(lox(&["run", &bad_types]).status.code(), Some(70));
+    assert_eq!(lox(&["run", "/nonexistent/script.lox"]).status.code(), Some(74));
+
+    let output = lox(&["check", &bad_syntax]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error[E0002]: Expect expression."));
+    assert!(stderr.contains(&format!("{}:1:7", bad_syntax)));
+}
+
+#[test]
+fn test_compile_then_run_chunk() {
+    let source = script("chunk.lox", "var s = \"lo\";\n{ var x = s + \"x\"; print x; }\n");
+    let chunk = script("chunk.loxc", "");
+    assert_eq!(lox(&["compile", &source, "-o", &chunk]).status.code(), Some(0));
+
+    let output = lox(&["run", &chunk]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "lox\n");
+    assert_eq!(stdout(&lox(&["disasm", &chunk])), stdout(&lox(&["disasm", &source])).replace(&source, &chunk));
+
+    let mut damaged = fs::read(&chunk).unwrap();
+    damaged.truncate(damaged.len() - 3);
+    let damaged_path = script("damaged.loxc", "");
+    fs::write(&damaged_path, damaged).unwrap();
+    assert_eq!(lox(&["run", &damaged_path]).status.code(), Some(65));
+}
+
+#[test]
+fn test_tokens() {
+    let source = script("tokens.lox", "print 1;");
+    let output = lox(&["tokens", &source]);
+    assert_eq!(stdout(&output), "   1 TokenPrint 5, \"print\"\n   | TokenNumber 1, \"1\"\n   | TokenSemicolon 1, \";\"\n   | TokenEof 0, \"\"\n");
+
+    let output = lox(&["tokens", "--json", &source]);
+    assert!(stdout(&output).starts_with("[\n  {\"type\": \"TokenPrint\", \"lexeme\": \"print\""));
+
+    let bad = script("bad_tokens.lox", "print @;");
+    assert_eq!(lox(&["tokens", &bad]).status.code(), Some(65));
+}
+
+#[test]
+fn test_run_with_trace() {
+    let source = script("trace.lox", "print 2 * 3;\n");
+    let output = lox(&["run", "--trace", &source]);
+    assert_eq!(stdout(&output), "6\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("          [ 2 ][ 3 ]\n0006    | OpMultiply\n"));
+
+    let output = lox(&["run", "--trace=json", &source]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.lines().count(), 5);
+    assert!(stderr.lines().nth(2).unwrap().contains("\"op\":\"OpMultiply\",\"stack\":[2,3]"));
+    assert_eq!(lox(&["run", "--trace=xml", &source]).status.code(), Some(64));
+}
+
+#[test]
+fn test_run_with_profile() {
+    let source = script("profile.lox", "var a = 1;\na = a + a;\nprint a;\n");
+    let csv = script("profile.csv", "");
+    let output = lox(&["run", "--profile-csv", &csv, &source]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "2\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("== profile: 10 instructions in "));
+    assert!(stderr.contains("\nopcode                count     total ms  time %     avg ns\n"));
+
+    let csv = fs::read_to_string(&csv).unwrap();
+    assert!(csv.starts_with("kind,name,count,total_ns\n"));
+    assert!(csv.contains("\nopcode,OpGetGlobal,3,"));
+    assert!(csv.contains("\nline,2,5,"));
+    assert!(csv.contains("\nfunction,script,10,"));
+    assert_eq!(lox(&["run", "--profile", "--frobnicate", &source]).status.code(), Some(64));
+}
+
+#[test]
+fn test_run_with_sampling() {
+    let source = script("sample.lox", "var a = 1;\na = a + a;\nprint a;\n");
+    let folded = script("sample.folded", "");
+    let output = lox(&["run", "--sample", &folded, "--sample-every", "5", &source]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "2\n");
+    assert_eq!(fs::read_to_string(&folded).unwrap(), "script:2 1\nscript:4 1\n");
+    assert_eq!(lox(&["run", "--sample", &folded, "--sample-every", "0", &source]).status.code(), Some(64));
+}
+
+#[test]
+fn test_cover() {
+    let full = script("cover_full.lox", "var a = 1;\nprint a;\n");
+    let partial = script("cover_partial.lox", "print 1;\nprint 1 + nil;\n\nprint 2;\n");
+    let info = script("cover.info", "");
+    let output = lox(&["cover", "-o", &info, &full, &partial]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(stdout(&output), "1\n1\n");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let row = |path: &str| stderr.lines().find(|line| line.starts_with(path)).unwrap().to_string();
+    assert!(row(&full).ends_with("  2/2  100.0%"));
+    assert!(row(&partial).ends_with("  2/3   66.7%  4"));
+    assert!(row("total").ends_with("  4/5   80.0%"));
+
+    let info = fs::read_to_string(&info).unwrap();
+    assert!(info.starts_with(&format!("TN:\nSF:{}\nDA:1,1\nDA:2,1\nLF:2\nLH:2\nend_of_record\n", full)));
+    assert!(info.ends_with("DA:1,1\nDA:2,1\nDA:4,0\nLF:3\nLH:2\nend_of_record\n"));
+    assert_eq!(lox(&["cover"]).status.code(), Some(64));
+
+    // The first failure decides the status: runtime (70), then compile (65)
+    let broken = script("cover_broken.lox", "print ;\n");
+    assert_eq!(lox(&["cover", &partial, &broken]).status.code(), Some(70));
+    assert_eq!(lox(&["cover", &broken, &partial]).status.code(), Some(65));
+}
+
+#[test]
+fn test_optimization_levels() {
+    let source = script(
+        "optimize.lox",
+        "var total = 0;\nfor (var i = 0; !(i >= 4); i = i + 1) {\n  total = total - -i * (2 - 1);\n}\nprint total;\n",
+    );
+    let plain = stdout(&lox(&["disasm", &source]));
+    let optimized = stdout(&lox(&["disasm", "-O1", &source]));
+    assert_eq!(stdout(&lox(&["disasm", "-O0", &source])), plain);
+    assert!(optimized.lines().count() < plain.lines().count());
+    // !(i >= 4) is two OpNots, each folded into the loop's exit jump
+    assert!(plain.contains("OpNot") && !optimized.contains("OpNot"));
+    assert!(plain.contains("OpMultiply") && !optimized.contains("OpMultiply"));
+
+    // i = i + 1 becomes a single add of a constant
+    let fused = stdout(&lox(&["disasm", "-O2", &source]));
+    assert!(!optimized.contains("OpAddConstant") && fused.contains("OpAddConstant"));
+
+    for level in ["-O0", "-O1", "-O2"] {
+        let output = lox(&["run", level, &source]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(stdout(&output), "6\n");
+    }
+    assert_eq!(lox(&["run", "-O3", &source]).status.code(), Some(64));
+}
+
+#[test]
+fn test_register_backend() {
+    let source = script("registers.lox", "{ var n = 0;\nfor (var i = 0; i < 4; i = i + 1) n = n + i;\nprint n; }\n");
+    let output = lox(&["run", "--registers", "-O1", &source]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "6\n");
+
+    let listing = stdout(&lox(&["disasm", "--registers", &source]));
+    assert!(listing.contains("ADD              r0, r0, r1"), "{}", listing);
+
+    let bad = script("registers_bad.lox", "print 1;\nprint -nil;\n");
+    let output = lox(&["run", "--registers", &bad]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(stdout(&output), "1\n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Operand must be a number.\n[line 2] in script"));
+    assert_eq!(lox(&["run", "--registers", "--trace", &bad]).status.code(), Some(64));
+}
@@ -0,0 +1,309 @@
+use std::fmt;
+
+use assignment6::virtual_machine::{RuntimeError, Value, VirtualMachine};
+
+// Where a breakpoint sits: a single instruction, or the first instruction of a source line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Offset(usize),
+    Line(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+// Only stop when the value on top of the stack satisfies `top <compare> value`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Condition {
+    pub compare: Compare,
+    pub value: Value,
+}
+
+impl Condition {
+    // Accepts "== 3", "top > 10", "!= nil" and so on
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let text = text.strip_prefix("top").unwrap_or(text).trim_start();
+        let operators = [
+            ("==", Compare::Equal),
+            ("!=", Compare::NotEqual),
+            ("<=", Compare::LessEqual),
+            (">=", Compare::GreaterEqual),
+            ("<", Compare::Less),
+            (">", Compare::Greater),
+        ];
+        let Some((rest, compare)) = operators
+            .iter()
+            .find_map(|(symbol, compare)| text.strip_prefix(symbol).map(|rest| (rest, *compare)))
+        else {
+            return Err("expected ==, !=, <, <=, > or >=".to_string());
+        };
+
+        let value = match rest.trim() {
+            "nil" => Value::ValNil,
+            "true" => Value::ValBool(true),
+            "false" => Value::ValBool(false),
+            number => Value::ValNumber(
+                number
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a number, bool or nil", number))?,
+            ),
+        };
+        if !matches!(compare, Compare::Equal | Compare::NotEqual) && !matches!(value, Value::ValNumber(_)) {
+            return Err("ordering comparisons need a number".to_string());
+        }
+        Ok(Self { compare, value })
+    }
+
+    pub fn holds(&self, top: Option<&Value>) -> bool {
+        let Some(&top) = top else {
+            return false;
+        };
+        match (self.compare, top, self.value) {
+            (Compare::Equal, a, b) => a == b,
+            (Compare::NotEqual, a, b) => a != b,
+            (compare, Value::ValNumber(a), Value::ValNumber(b)) => match compare {
+                Compare::Less => a < b,
+                Compare::LessEqual => a <= b,
+                Compare::Greater => a > b,
+                _ => a >= b,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self.compare {
+            Compare::Equal => "==",
+            Compare::NotEqual => "!=",
+            Compare::Less => "<",
+            Compare::LessEqual => "<=",
+            Compare::Greater => ">",
+            Compare::GreaterEqual => ">=",
+        };
+        let value = match self.value {
+            Value::ValBool(b) => b.to_string(),
+            Value::ValNumber(n) => n.to_string(),
+            Value::ValNil => "nil".to_string(),
+            Value::ValObj(_) => "<object>".to_string(),
+        };
+        write!(f, "top {} {}", symbol, value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub location: Location,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(usize),
+    Finished,
+    Error(RuntimeError),
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+        }
+    }
+
+    pub fn toggle(&mut self, location: Location) {
+        match self.breakpoints.iter().position(|bp| bp.location == location) {
+            Some(index) => {
+                self.breakpoints.remove(index);
+            }
+            None => self.breakpoints.push(Breakpoint {
+                location,
+                condition: None,
+            }),
+        }
+    }
+
+    // Attach (or clear) a condition, creating the breakpoint if needed
+    pub fn set_condition(&mut self, location: Location, condition: Option<Condition>) {
+        match self.breakpoints.iter_mut().find(|bp| bp.location == location) {
+            Some(bp) => bp.condition = condition,
+            None => self.breakpoints.push(Breakpoint { location, condition }),
+        }
+    }
+
+    // Keep offset breakpoints on the same instructions after the code at `at`
+    // goes from `before` bytes to `after`. An inserted instruction (`before`
+    // 0) pushes the one at `at` along, breakpoint and all; a removed one
+    // (`after` 0) takes its breakpoint with it.
+    pub fn instruction_resized(&mut self, at: usize, before: usize, after: usize) {
+        if after == 0 {
+            self.breakpoints.retain(|bp| bp.location != Location::Offset(at));
+        }
+        for bp in &mut self.breakpoints {
+            if let Location::Offset(offset) = &mut bp.location
+                && (*offset > at || (*offset == at && before == 0))
+            {
+                *offset = *offset + after - before;
+            }
+        }
+    }
+
+    // The breakpoint that decorates an instruction in the listing, if any
+    pub fn marker_at(&self, offset: usize, lines: &[usize]) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|bp| match bp.location {
+            Location::Offset(at) => at == offset,
+            Location::Line(line) => starts_line(lines, offset, line),
+        })
+    }
+
+    // True when the instruction at `vm.ip` is about to trigger a breakpoint
+    pub fn should_stop(&self, vm: &VirtualMachine) -> bool {
+        let top = vm.stack.last().map(|value| value.unpack());
+        self.breakpoints.iter().any(|bp| {
+            let here = match bp.location {
+                Location::Offset(at) => at == vm.ip,
+                Location::Line(line) => starts_line(&vm.chunk.lines, vm.ip, line),
+            };
+            here && bp.condition.is_none_or(|condition| condition.holds(top.as_ref()))
+        })
+    }
+
+    pub fn step(&self, vm: &mut VirtualMachine) -> StopReason {
+        if let Err(error) = vm.step() {
+            return StopReason::Error(error);
+        }
+        if vm.is_done() {
+            StopReason::Finished
+        } else {
+            StopReason::Stepped
+        }
+    }
+
+    // A step that also reports landing on a breakpoint, for continuous runs
+    pub fn advance(&self, vm: &mut VirtualMachine) -> StopReason {
+        match self.step(vm) {
+            StopReason::Stepped if self.should_stop(vm) => StopReason::Breakpoint(vm.ip),
+            reason => reason,
+        }
+    }
+
+    // Always executes at least one instruction so a resume never sticks on the
+    // breakpoint it is sitting on
+    pub fn resume(&self, vm: &mut VirtualMachine) -> StopReason {
+        if vm.is_done() {
+            return StopReason::Finished;
+        }
+        loop {
+            match self.advance(vm) {
+                StopReason::Stepped => {}
+                reason => return reason,
+            }
+        }
+    }
+
+    pub fn run_to_end(&self, vm: &mut VirtualMachine) -> StopReason {
+        match vm.run() {
+            Ok(()) => StopReason::Finished,
+            Err(error) => StopReason::Error(error),
+        }
+    }
+}
+
+// Line breakpoints fire once, on the first instruction of each run of that line
+fn starts_line(lines: &[usize], offset: usize, line: usize) -> bool {
+    lines.get(offset) == Some(&line) && (offset == 0 || lines[offset - 1] != line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assignment6::virtual_machine::{Chunk, Op};
+    use assignment6::Output;
+
+    // 1 + 2 on line 1, then * 10 on line 2, then return on line 3
+    fn sample() -> VirtualMachine {
+        let mut chunk = Chunk::new();
+        for (value, line) in [(1.0, 1), (2.0, 1)] {
+            let index = chunk.add_constant(Value::ValNumber(value));
+            chunk.write(Op::OpConstant(index), line);
+        }
+        chunk.write(Op::OpAdd, 1);
+        let ten = chunk.add_constant(Value::ValNumber(10.0));
+        chunk.write(Op::OpConstant(ten), 2);
+        chunk.write(Op::OpMultiply, 2);
+        chunk.write(Op::OpReturn, 3);
+        VirtualMachine::new(chunk).with_output(Output::Buffer(Vec::new()))
+    }
+
+    #[test]
+    fn test_resume_stops_at_offset_and_line_breakpoints() {
+        let mut vm = sample();
+        let mut debugger = Debugger::new();
+        debugger.toggle(Location::Offset(6));
+        debugger.toggle(Location::Line(2));
+
+        assert_eq!(debugger.resume(&mut vm), StopReason::Breakpoint(6));
+        assert_eq!(debugger.resume(&mut vm), StopReason::Breakpoint(7));
+        assert_eq!(vm.stack, vec![Value::ValNumber(3.0)]);
+        assert_eq!(debugger.resume(&mut vm), StopReason::Finished);
+        assert_eq!(vm.stack, vec![Value::ValNumber(30.0)]);
+
+        debugger.toggle(Location::Offset(6));
+        assert_eq!(debugger.breakpoints.len(), 1);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut vm = sample();
+        let mut debugger = Debugger::new();
+        for offset in 0..vm.chunk.code.len() {
+            debugger.set_condition(Location::Offset(offset), Some(Condition::parse("top >= 10").unwrap()));
+        }
+        assert_eq!(debugger.resume(&mut vm), StopReason::Breakpoint(10));
+        assert_eq!(vm.stack.last().map(|value| value.unpack()), Some(Value::ValNumber(10.0)));
+    }
+
+    #[test]
+    fn test_runtime_error_stops_the_run() {
+        let mut chunk = Chunk::new();
+        chunk.write(Op::OpNil, 1);
+        chunk.write(Op::OpNegate, 2);
+        chunk.write(Op::OpReturn, 3);
+        let mut vm = VirtualMachine::new(chunk);
+        let debugger = Debugger::new();
+
+        let StopReason::Error(error) = debugger.resume(&mut vm) else {
+            panic!("expected a runtime error");
+        };
+        assert_eq!(error.line, 2);
+        assert!(vm.is_done());
+        assert_eq!(debugger.resume(&mut vm), StopReason::Finished);
+    }
+
+    #[test]
+    fn test_condition_parsing() {
+        let condition = Condition::parse("!= nil").unwrap();
+        assert_eq!(condition.compare, Compare::NotEqual);
+        assert!(condition.holds(Some(&Value::ValBool(false))));
+        assert!(!condition.holds(None));
+        assert_eq!(Condition::parse("top>=2.5").unwrap().to_string(), "top >= 2.5");
+        assert!(Condition::parse("> true").is_err());
+        assert!(Condition::parse("3").is_err());
+        assert!(Condition::parse("== x").is_err());
+    }
+}
@@ -0,0 +1,352 @@
+use assignment6::bytecode::{Listing, OPERAND_MAX};
+use assignment6::object::Heap;
+use assignment6::verifier::verify;
+use assignment6::virtual_machine::{Chunk, Op, Value};
+
+// Operand-free instructions, by name
+const SIMPLE: [(&str, Op); 16] = [
+    ("OpAdd", Op::OpAdd),
+    ("OpSubtract", Op::OpSubtract),
+    ("OpMultiply", Op::OpMultiply),
+    ("OpDivide", Op::OpDivide),
+    ("OpModulo", Op::OpModulo),
+    ("OpNegate", Op::OpNegate),
+    ("OpNot", Op::OpNot),
+    ("OpEqual", Op::OpEqual),
+    ("OpGreater", Op::OpGreater),
+    ("OpLess", Op::OpLess),
+    ("OpNil", Op::OpNil),
+    ("OpTrue", Op::OpTrue),
+    ("OpFalse", Op::OpFalse),
+    ("OpPrint", Op::OpPrint),
+    ("OpPop", Op::OpPop),
+    ("OpReturn", Op::OpReturn),
+];
+
+// Literal syntax for constants: numbers, true, false, nil and "strings"
+pub fn parse_value(text: &str, heap: &mut Heap) -> Result<Value, String> {
+    let text = text.trim();
+    match text {
+        "nil" => return Ok(Value::ValNil),
+        "true" => return Ok(Value::ValBool(true)),
+        "false" => return Ok(Value::ValBool(false)),
+        _ => {}
+    }
+    if let Some(inner) = text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        return Ok(Value::ValObj(heap.intern(inner)));
+    }
+    text.parse()
+        .map(Value::ValNumber)
+        .map_err(|_| format!("'{}' is not a number, string, bool or nil", text))
+}
+
+// How an instruction is written in the editor, e.g. `OpConstant 3` or `OpGetGlobal a`
+pub fn instruction_text(chunk: &Chunk, offset: usize, heap: &Heap) -> String {
+    let op = chunk.op_at(offset);
+    let name = op.name().to_string();
+    match op {
+        Op::OpConstant(index) | Op::OpAddConstant(index) => {
+            format!("{} {}", name, value_text(&chunk.constant(index), heap))
+        }
+        Op::OpGetLocals(a, b) => format!("{} {} {}", name, a, b),
+        Op::OpLessLocalConst(slot, index) => {
+            format!("{} {} {}", name, slot, value_text(&chunk.constant(index), heap))
+        }
+        Op::OpDefineGlobal(index) | Op::OpGetGlobal(index) | Op::OpSetGlobal(index) => {
+            format!("{} {}", name, chunk.constants[index].format(heap))
+        }
+        Op::OpGetLocal(operand)
+        | Op::OpSetLocal(operand)
+        | Op::OpJump(operand)
+        | Op::OpJumpIfFalse(operand)
+        | Op::OpJumpIfTrue(operand)
+        | Op::OpLoop(operand) => format!("{} {}", name, operand),
+        _ => name,
+    }
+}
+
+// A constant written back in the syntax parse_value accepts
+pub fn value_text(value: &Value, heap: &Heap) -> String {
+    match value {
+        Value::ValObj(_) => format!("\"{}\"", value.format(heap)),
+        _ => value.format(heap),
+    }
+}
+
+// Parses `name [operand]`, adding any constant it needs to `chunk`. The `Op`
+// prefix is optional and names are case-insensitive.
+pub fn parse_instruction(text: &str, chunk: &mut Chunk, heap: &mut Heap) -> Result<Op, String> {
+    let text = text.trim();
+    let (name, operand) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let operand = operand.trim();
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_prefix("op").unwrap_or(&name);
+
+    if let Some(&(simple, op)) = SIMPLE.iter().find(|(simple, _)| simple[2..].eq_ignore_ascii_case(name)) {
+        if !operand.is_empty() {
+            return Err(format!("{} takes no operand", simple));
+        }
+        return Ok(op);
+    }
+
+    let needs = |what: &str| format!("{} needs {}", text, what);
+    match name {
+        "constant" => {
+            if operand.is_empty() {
+                return Err(needs("a value"));
+            }
+            let value = parse_value(operand, heap)?;
+            Ok(Op::OpConstant(add_constant(chunk, value)?))
+        }
+        "addconstant" => {
+            if operand.is_empty() {
+                return Err(needs("a value"));
+            }
+            let value = parse_value(operand, heap)?;
+            Ok(Op::OpAddConstant(add_constant(chunk, value)?))
+        }
+        "getlocals" => {
+            let slots = operand.split_once(char::is_whitespace);
+            let (a, b) = slots
+                .and_then(|(a, b)| Some((operand_number(a)?, operand_number(b.trim())?)))
+                .ok_or_else(|| needs("two slot numbers"))?;
+            Ok(Op::OpGetLocals(a, b))
+        }
+        "lesslocalconst" => {
+            let (slot, value) = operand
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| needs("a slot and a value"))?;
+            let slot = operand_number(slot).ok_or_else(|| needs("a slot number"))?;
+            let value = parse_value(value.trim(), heap)?;
+            Ok(Op::OpLessLocalConst(slot, add_constant(chunk, value)?))
+        }
+        "defineglobal" | "getglobal" | "setglobal" => {
+            if operand.is_empty() || operand.contains(char::is_whitespace) {
+                return Err(needs("a variable name"));
+            }
+            let index = add_constant(chunk, Value::ValObj(heap.intern(operand)))?;
+            Ok(match name {
+                "defineglobal" => Op::OpDefineGlobal(index),
+                "getglobal" => Op::OpGetGlobal(index),
+                _ => Op::OpSetGlobal(index),
+            })
+        }
+        "getlocal" | "setlocal" => {
+            let slot = operand_number(operand).ok_or_else(|| needs("a slot number"))?;
+            Ok(if name == "getlocal" { Op::OpGetLocal(slot) } else { Op::OpSetLocal(slot) })
+        }
+        "jump" | "jumpiffalse" | "jumpiftrue" | "loop" => {
+            let offset = operand_number(operand).ok_or_else(|| needs("an offset"))?;
+            Ok(match name {
+                "jump" => Op::OpJump(offset),
+                "jumpiffalse" => Op::OpJumpIfFalse(offset),
+                "jumpiftrue" => Op::OpJumpIfTrue(offset),
+                _ => Op::OpLoop(offset),
+            })
+        }
+        _ => Err(format!("unknown instruction '{}'", text)),
+    }
+}
+
+// Operands are encoded in two bytes, so anything larger can't be written
+fn operand_number(text: &str) -> Option<usize> {
+    text.parse::<u16>().ok().map(usize::from)
+}
+
+fn add_constant(chunk: &mut Chunk, value: Value) -> Result<usize, String> {
+    if chunk.constants.len() > OPERAND_MAX {
+        return Err("too many constants in the chunk".to_string());
+    }
+    Ok(chunk.add_constant(value))
+}
+
+// === Edits ===
+// Each edit works on a copy and only hands it back if the verifier accepts it,
+// so a bad edit never reaches the VM. Positions are byte offsets of
+// instruction starts. Edits go through the chunk's listing, where jumps hold
+// the index of the instruction they land on, so the jumps around an edit
+// keep their targets however many bytes the edit adds or removes.
+
+pub fn insert(chunk: &Chunk, heap: &mut Heap, at: usize, text: &str) -> Result<Chunk, String> {
+    let mut edited = chunk.clone();
+    let op = parse_instruction(text, &mut edited, heap)?;
+    let mut listing = chunk.listing();
+    let index = index_of(chunk, at, true)?;
+    // The new instruction takes the line of the one it pushes down
+    let line = listing.lines.get(index).or(listing.lines.last()).copied().unwrap_or(1);
+    // Jumps to `at` now land on the new instruction
+    retarget(&mut listing, |target| target + usize::from(target > index));
+    listing.code.insert(index, op);
+    listing.lines.insert(index, line);
+    place(&mut edited, listing, index, op);
+    validated(edited)
+}
+
+pub fn replace(chunk: &Chunk, heap: &mut Heap, at: usize, text: &str) -> Result<Chunk, String> {
+    let mut edited = chunk.clone();
+    let op = parse_instruction(text, &mut edited, heap)?;
+    let listing = chunk.listing();
+    let index = index_of(chunk, at, false)?;
+    place(&mut edited, listing, index, op);
+    validated(edited)
+}
+
+pub fn delete(chunk: &Chunk, at: usize) -> Result<Chunk, String> {
+    let mut edited = chunk.clone();
+    let mut listing = chunk.listing();
+    let index = index_of(chunk, at, false)?;
+    listing.code.remove(index);
+    listing.lines.remove(index);
+    // Jumps to `at` now land on the instruction after it
+    retarget(&mut listing, |target| target - usize::from(target > index));
+    edited.assemble(&listing);
+    validated(edited)
+}
+
+// Gives an OpConstant a new value. The value goes in a fresh pool entry so
+// nothing else sharing the old one changes.
+pub fn set_constant(chunk: &Chunk, heap: &mut Heap, at: usize, text: &str) -> Result<Chunk, String> {
+    let Some(Op::OpConstant(_)) = Op::decode(&chunk.code, at) else {
+        return Err(format!("{:04} is not an OpConstant", at));
+    };
+    let mut edited = chunk.clone();
+    let value = parse_value(text, heap)?;
+    let index = add_constant(&mut edited, value)?;
+    edited.rewrite(at, Op::OpConstant(index));
+    validated(edited)
+}
+
+// Which instruction in the listing starts at `at`. With `end`, the end of
+// the code counts too, as the place to append.
+fn index_of(chunk: &Chunk, at: usize, end: bool) -> Result<usize, String> {
+    let starts = chunk.instructions().map(|(offset, _)| offset);
+    let end = end.then_some(chunk.code.len());
+    starts
+        .chain(end)
+        .position(|offset| offset == at)
+        .ok_or_else(|| format!("no instruction starts at {:04}", at))
+}
+
+// Moves every jump's target in the listing
+fn retarget(listing: &mut Listing, target: impl Fn(usize) -> usize) {
+    for op in &mut listing.code {
+        *op = match *op {
+            Op::OpJump(to) => Op::OpJump(target(to)),
+            Op::OpJumpIfFalse(to) => Op::OpJumpIfFalse(target(to)),
+            Op::OpJumpIfTrue(to) => Op::OpJumpIfTrue(target(to)),
+            op => op,
+        };
+    }
+}
+
+// Assembles the listing into `chunk` with `op` as its instruction `index`.
+// A jump typed into the editor keeps the byte offset it was written with
+// rather than being read as a listing index.
+fn place(chunk: &mut Chunk, mut listing: Listing, index: usize, op: Op) {
+    let jump = matches!(op, Op::OpJump(_) | Op::OpJumpIfFalse(_) | Op::OpJumpIfTrue(_) | Op::OpLoop(_));
+    listing.code[index] = if jump { Op::OpJump(index + 1) } else { op };
+    chunk.assemble(&listing);
+    if jump {
+        let offset = listing.code[..index].iter().map(|op| op.size()).sum();
+        chunk.rewrite(offset, op);
+    }
+}
+
+fn validated(chunk: Chunk) -> Result<Chunk, String> {
+    verify(&chunk).map_err(|error| format!("rejected: {}", error))?;
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assignment6::compiler::Compiler;
+
+    fn sample(heap: &mut Heap) -> Chunk {
+        Compiler::new().compile("print 1 + 2;", heap).unwrap()
+    }
+
+    #[test]
+    fn test_edits_are_verified() {
+        let mut heap = Heap::new();
+        let chunk = sample(&mut heap);
+
+        let edited = insert(&chunk, &mut heap, 6, "OpConstant 10").unwrap();
+        let edited = insert(&edited, &mut heap, 9, "multiply").unwrap();
+        let edited = set_constant(&edited, &mut heap, 0, "\"x\"").unwrap();
+        assert_eq!(instruction_text(&edited, 0, &heap), "OpConstant \"x\"");
+        assert_eq!(instruction_text(&edited, 9, &heap), "OpMultiply");
+        assert_eq!(edited.lines, vec![1; 13]);
+
+        assert_eq!(
+            delete(&chunk, 0).unwrap_err(),
+            "rejected: 0003 stack underflow (depth 1, needs 2)"
+        );
+        assert_eq!(delete(&chunk, 1).unwrap_err(), "no instruction starts at 0001");
+        assert!(replace(&chunk, &mut heap, 0, "OpGetLocal 3").unwrap_err().contains("local slot 3"));
+        assert_eq!(
+            replace(&chunk, &mut heap, 0, "OpFrobnicate").unwrap_err(),
+            "unknown instruction 'OpFrobnicate'"
+        );
+        assert_eq!(replace(&chunk, &mut heap, 0, "OpAdd 3").unwrap_err(), "OpAdd takes no operand");
+    }
+
+    // The instruction each jump lands on
+    fn landings(chunk: &Chunk, heap: &Heap) -> Vec<String> {
+        chunk
+            .instructions()
+            .filter_map(|(offset, op)| op.jump_target(offset))
+            .map(|target| instruction_text(chunk, target, heap))
+            .collect()
+    }
+
+    #[test]
+    fn test_edits_keep_jumps_on_target() {
+        let mut heap = Heap::new();
+        let source = "var a = 0; while (a < 2) a = a + 1; print a;";
+        let chunk = Compiler::new().compile(source, &mut heap).unwrap();
+        assert_eq!(landings(&chunk, &heap), ["OpPop", "OpGetGlobal a"]);
+
+        // At the loop's start, which the loop then goes back to, and in its body
+        let edited = insert(&chunk, &mut heap, 6, "jump 0").unwrap();
+        let edited = insert(&edited, &mut heap, 26, "negate").unwrap();
+        assert_eq!(landings(&edited, &heap), ["OpGetGlobal a", "OpPop", "OpJump 0"]);
+
+        let edited = delete(&edited, 26).unwrap();
+        let edited = delete(&edited, 6).unwrap();
+        assert_eq!(edited.disassemble("c", &heap), chunk.disassemble("c", &heap));
+
+        // A shorter instruction in the loop body moves everything after it
+        let edited = replace(&chunk, &mut heap, 20, "true").unwrap();
+        assert_eq!(edited.code.len(), chunk.code.len() - 2);
+        assert_eq!(landings(&edited, &heap), ["OpPop", "OpGetGlobal a"]);
+
+        assert_eq!(instruction_text(&chunk, 28, &heap), "OpLoop 25");
+        assert!(replace(&chunk, &mut heap, 28, "loop 99").unwrap_err().contains("jump target"));
+        assert_eq!(replace(&chunk, &mut heap, 28, "loop 70000").unwrap_err(), "loop 70000 needs an offset");
+    }
+
+    #[test]
+    fn test_instruction_text_round_trips() {
+        let mut heap = Heap::new();
+        let plain = Compiler::new()
+            .compile("var g = \"s\"; { var l = g; l = nil; } g = true;", &mut heap)
+            .unwrap();
+        // Superinstructions too, which only the optimizer emits
+        let fused = Compiler::new()
+            .with_peephole(true)
+            .with_superinstructions(true)
+            .compile("{ var a = 1; var b = a + a; while (b < 9) b = b + 2; }", &mut heap)
+            .unwrap();
+        assert!(fused.instructions().any(|(_, op)| matches!(op, Op::OpLessLocalConst(..))));
+        for chunk in [plain, fused] {
+            for (offset, _) in chunk.instructions() {
+                let text = instruction_text(&chunk, offset, &heap);
+                let mut copy = chunk.clone();
+                let op = parse_instruction(&text, &mut copy, &mut heap).unwrap();
+                copy.rewrite(offset, op);
+                assert_eq!(instruction_text(&copy, offset, &heap), text);
+            }
+        }
+    }
+}
@@ -1,43 +1,181 @@
+use std::env;
 use std::io;
+use std::process;
+use std::time::{Duration, Instant};
+use assignment6::virtual_machine::{Chunk, Op, VirtualMachine};
+use assignment6::Output;
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
 use ratatui::{
     buffer::Buffer,
-    layout::Rect,
-    style::Stylize,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
     symbols::border,
     text::{Line, Text},
-    widgets::{Block, Paragraph, Widget},
+    widgets::{Block, LineGauge, Paragraph, Widget},
     DefaultTerminal, Frame,
 };
 
-mod virtual_machine;
-use virtual_machine::{Chunk, OpCode, Value, VirtualMachine};
+mod debugger;
+mod editor;
+mod panes;
+mod program;
+mod script;
+use debugger::{Condition, Debugger, Location, StopReason};
+use panes::Pane;
+use program::Program;
+use script::Recorder;
 
 fn main() -> io::Result<()> {
-    let mut terminal = ratatui::init();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let history = option(&mut args, "--history")
+        .map_or(DEFAULT_HISTORY, |limit| limit.parse().unwrap_or_else(|_| usage()));
+    let keys = option(&mut args, "--script");
+    let record = option(&mut args, "--record");
+    let size = option(&mut args, "--size");
+    let every_frame = flag(&mut args, "--every-frame");
+    let program = match args.as_slice() {
+        [] => Program::demo(),
+        [path] => Program::load(path).unwrap_or_else(|message| fail(65, message)),
+        _ => usage(),
+    };
+    let mut app = App::new(program).with_history(history);
 
-    // Create sample bytecode
-    let mut chunk = Chunk::new();
-    chunk.write(OpCode::OpConstant(Value::ValNumber(3.0)), 1);
-    chunk.write(OpCode::OpConstant(Value::ValNumber(4.0)), 1);
-    chunk.write(OpCode::OpAdd, 1);
-    chunk.write(OpCode::OpReturn, 1);
+    // Headless: play the key script and print what the screen would show
+    if let Some(path) = keys {
+        let text = std::fs::read_to_string(&path)
+            .unwrap_or_else(|error| fail(66, format!("could not read '{}': {}", path, error)));
+        let actions =
+            script::parse(&text).unwrap_or_else(|message| fail(65, format!("{}: {}", path, message)));
+        let (width, height) = size.map_or((DEFAULT_WIDTH, DEFAULT_HEIGHT), |size| {
+            size.split_once('x')
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                .unwrap_or_else(|| usage())
+        });
+        print!("{}", script::play(&mut app, &actions, width, height, every_frame)?);
+        return Ok(());
+    }
+    if size.is_some() || every_frame {
+        usage();
+    }
 
-    let app_result = App::new(chunk).run(&mut terminal);
+    if let Some(path) = record {
+        let recorder = Recorder::create(&path)
+            .unwrap_or_else(|error| fail(73, format!("could not create '{}': {}", path, error)));
+        app = app.with_recorder(recorder);
+    }
+    let mut terminal = ratatui::init();
+    let app_result = app.run(&mut terminal);
     ratatui::restore();
-    app_result
+    app_result?;
+    match app.recorder.take() {
+        Some(recorder) => recorder.finish(),
+        None => Ok(()),
+    }
+}
+
+// Removes `name <value>` from the arguments and returns the value
+fn option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let at = args.iter().position(|arg| arg == name)?;
+    if at + 1 == args.len() {
+        usage();
+    }
+    Some(args.drain(at..at + 2).nth(1).unwrap_or_default())
+}
+
+fn flag(args: &mut Vec<String>, name: &str) -> bool {
+    let at = args.iter().position(|arg| arg == name);
+    at.map(|at| args.remove(at)).is_some()
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: bytecode_vm_ratatui [--history <steps>] [--record <keys.txt>] [script.lox | chunk.loxc]");
+    eprintln!("       bytecode_vm_ratatui --script <keys.txt> [--size <width>x<height>] [--every-frame]");
+    eprintln!("                           [--history <steps>] [script.lox | chunk.loxc]");
+    process::exit(64);
+}
+
+fn fail(code: i32, message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(code);
+}
+
+// Steps that can be undone with Left before the oldest are forgotten
+const DEFAULT_HISTORY: usize = 10_000;
+
+// Screen size for --script when --size isn't given
+const DEFAULT_WIDTH: u16 = 120;
+const DEFAULT_HEIGHT: u16 = 32;
+
+// Source lines kept visible above the current one
+const SOURCE_CONTEXT: usize = 5;
+
+// Auto-step rates offered by +/-, in instructions per second
+const SPEEDS: [u32; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+// What a line typed at the status bar is for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PromptKind {
+    Condition, // condition for the breakpoint at the cursor
+    Insert,    // instruction to insert before the cursor
+    Replace,   // instruction to put in place of the cursor's
+    Constant,  // new value for the cursor's OpConstant
+    Save,      // path to write the chunk to
+}
+
+#[derive(Debug, Clone)]
+struct Prompt {
+    kind: PromptKind,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExecutionState {
+    Running,
+    Paused,
+    Halted,
+    Error,
 }
 
 #[derive(Debug)]
 pub struct App {
     vm: VirtualMachine,
+    program: Program, // pristine copy for restarts, including any edits
+    history: usize,
+    furthest: usize, // latest step reached, the right end of the timeline
+    debugger: Debugger,
+    cursor: usize,             // offset of the selected instruction in the code listing
+    focus: Pane,
+    scroll: [usize; Pane::ALL.len()], // first visible row; rows back from the end for Output
+    prompt: Option<Prompt>,
+    edited: bool, // the chunk no longer matches what was loaded
+    playing: bool,
+    speed: usize,          // index into SPEEDS
+    last_tick: Instant,    // when auto-stepping last ran
+    failed: bool,          // the run ended in a runtime error
+    status: String,
+    recorder: Option<Recorder>, // keys of this session, for --record
     exit: bool,
 }
 
 impl App {
-    pub fn new(chunk: Chunk) -> Self {
+    pub fn new(program: Program) -> Self {
         Self {
-            vm: VirtualMachine::new(chunk),
+            vm: fresh_vm(&program, DEFAULT_HISTORY),
+            program,
+            history: DEFAULT_HISTORY,
+            furthest: 0,
+            debugger: Debugger::new(),
+            cursor: 0,
+            focus: Pane::Bytecode,
+            scroll: [0; Pane::ALL.len()],
+            prompt: None,
+            edited: false,
+            playing: false,
+            speed: 3,
+            last_tick: Instant::now(),
+            failed: false,
+            status: "Ready".to_string(),
+            recorder: None,
             exit: false,
         }
     }
@@ -55,31 +193,474 @@ impl App {
     }
 
     fn handle_events(&mut self) -> io::Result<()> {
-        if event::poll(std::time::Duration::from_millis(200))? {
-            if let Event::Key(key_event) = event::read()? {
-                if key_event.kind == KeyEventKind::Press {
-                    self.handle_key_event(key_event);
-                }
+        // While playing, wake up in time for the next instruction (capped at ~60 fps)
+        let timeout = if self.playing {
+            Duration::from_secs_f64(1.0 / SPEEDS[self.speed] as f64).min(Duration::from_millis(16))
+        } else {
+            Duration::from_millis(200)
+        };
+        if event::poll(timeout)?
+            && let Event::Key(key_event) = event::read()?
+            && key_event.kind == KeyEventKind::Press
+        {
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record(key_event, self.playing)?;
             }
+            self.handle_key_event(key_event);
         }
+        self.tick(Instant::now());
         Ok(())
     }
 
+    // Run however many instructions are due at the current speed
+    fn tick(&mut self, now: Instant) {
+        if !self.playing {
+            return;
+        }
+        let due = (now.duration_since(self.last_tick).as_secs_f64() * SPEEDS[self.speed] as f64) as usize;
+        if due == 0 {
+            return;
+        }
+        self.last_tick = now;
+        for _ in 0..due {
+            match self.debugger.advance(&mut self.vm) {
+                StopReason::Stepped => {}
+                reason => {
+                    self.playing = false;
+                    self.stopped(reason);
+                    return;
+                }
+            }
+        }
+        self.furthest = self.furthest.max(self.step());
+        self.follow();
+    }
+
+    fn toggle_play(&mut self) {
+        self.playing = !self.playing && !self.vm.is_done();
+        self.last_tick = Instant::now();
+        self.status = if self.playing { "Playing" } else { "Paused" }.to_string();
+    }
+
+    fn execution_state(&self) -> ExecutionState {
+        if self.failed && self.vm.is_done() {
+            ExecutionState::Error
+        } else if self.vm.is_done() {
+            ExecutionState::Halted
+        } else if self.playing {
+            ExecutionState::Running
+        } else {
+            ExecutionState::Paused
+        }
+    }
+
     fn handle_key_event(&mut self, key_event: KeyEvent) {
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key_event);
+            return;
+        }
         match key_event.code {
             KeyCode::Char('q') => self.exit = true,
-            KeyCode::Right => self.vm.step_once(),
+            KeyCode::Char(' ') => self.toggle_play(),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.speed = (self.speed + 1).min(SPEEDS.len() - 1),
+            KeyCode::Char('-') => self.speed = self.speed.saturating_sub(1),
+            KeyCode::Tab => self.focus = self.focus.next(),
+            KeyCode::BackTab => self.focus = self.focus.previous(),
+            KeyCode::Right | KeyCode::Char('s') => {
+                let reason = self.debugger.step(&mut self.vm);
+                self.stopped(reason);
+            }
+            KeyCode::Left => {
+                self.failed = false;
+                self.status = if self.vm.step_back() {
+                    format!("Stepped back to {:04}", self.vm.ip)
+                } else {
+                    "No earlier step in history".to_string()
+                };
+                self.follow();
+            }
+            KeyCode::Char('[') => self.seek(self.step().saturating_sub(10)),
+            KeyCode::Char(']') => self.seek(self.step() + 10),
+            KeyCode::Home => self.seek(0),
+            KeyCode::End => self.seek(self.furthest),
+            KeyCode::Char('c') => {
+                let reason = self.debugger.resume(&mut self.vm);
+                self.stopped(reason);
+            }
+            KeyCode::Char('e') => {
+                let reason = self.debugger.run_to_end(&mut self.vm);
+                self.stopped(reason);
+            }
+            KeyCode::Char('r') => {
+                self.restart();
+                self.status = "Restarted".to_string();
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_by(1),
+            KeyCode::PageUp => self.scroll_by(-20),
+            KeyCode::PageDown => self.scroll_by(20),
+            KeyCode::Char('b') => self.debugger.toggle(Location::Offset(self.cursor)),
+            KeyCode::Char('l') => {
+                if let Some(&line) = self.vm.chunk.lines.get(self.cursor) {
+                    self.debugger.toggle(Location::Line(line));
+                }
+            }
+            KeyCode::Char('?') => self.open_prompt(PromptKind::Condition, String::new()),
+            KeyCode::Char('i') => self.open_prompt(PromptKind::Insert, String::new()),
+            KeyCode::Char('o') => {
+                let text = editor::instruction_text(&self.program.chunk, self.cursor, &self.program.heap);
+                self.open_prompt(PromptKind::Replace, text);
+            }
+            KeyCode::Char('v') => match Op::decode(&self.program.chunk.code, self.cursor) {
+                Some(Op::OpConstant(index)) => {
+                    let text = editor::value_text(&self.program.chunk.constant(index), &self.program.heap);
+                    self.open_prompt(PromptKind::Constant, text);
+                }
+                _ => self.status = format!("{:04} is not an OpConstant", self.cursor),
+            },
+            KeyCode::Char('x') | KeyCode::Delete => {
+                let edited = editor::delete(&self.program.chunk, self.cursor);
+                let size = self.program.chunk.op_at(self.cursor).size();
+                self.apply_edit(edited, size, format!("Deleted {:04}", self.cursor));
+            }
+            KeyCode::Char('w') => self.open_prompt(PromptKind::Save, self.program.save_path()),
             _ => {}
         }
     }
+
+    fn open_prompt(&mut self, kind: PromptKind, text: String) {
+        self.playing = false;
+        self.prompt = Some(Prompt { kind, text });
+    }
+
+    // Typing at the prompt: Enter submits, Esc cancels
+    fn handle_prompt_key(&mut self, key_event: KeyEvent) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        match key_event.code {
+            KeyCode::Char(c) => prompt.text.push(c),
+            KeyCode::Backspace => {
+                prompt.text.pop();
+            }
+            KeyCode::Esc => self.prompt = None,
+            KeyCode::Enter => {
+                let Some(Prompt { kind, text }) = self.prompt.take() else {
+                    return;
+                };
+                self.submit(kind, text);
+            }
+            _ => {}
+        }
+    }
+
+    fn submit(&mut self, kind: PromptKind, text: String) {
+        let at = self.cursor;
+        let (chunk, heap) = (&self.program.chunk, &mut self.program.heap);
+        match kind {
+            PromptKind::Condition => self.submit_condition(text),
+            PromptKind::Insert => {
+                let edited = editor::insert(chunk, heap, at, &text);
+                let size = edited.as_ref().map_or(0, |edited| edited.code.len() - chunk.code.len());
+                // Like typing text, the next insert goes after this one
+                if self.apply_edit(edited, 0, format!("Inserted at {:04}", at)) {
+                    self.cursor = at + size;
+                }
+            }
+            PromptKind::Replace => {
+                let size = chunk.op_at(at).size();
+                let edited = editor::replace(chunk, heap, at, &text);
+                self.apply_edit(edited, size, format!("Replaced {:04}", at));
+            }
+            PromptKind::Constant => {
+                let size = chunk.op_at(at).size();
+                let edited = editor::set_constant(chunk, heap, at, &text);
+                self.apply_edit(edited, size, format!("Constant at {:04} is now {}", at, text.trim()));
+            }
+            PromptKind::Save => {
+                let path = text.trim();
+                self.status = match self.program.save(path) {
+                    Ok(()) => {
+                        self.edited = false;
+                        format!("Saved chunk to {}", path)
+                    }
+                    Err(message) => format!("Save failed: {}", message),
+                };
+            }
+        }
+    }
+
+    // A verified edit replaces the program and restarts it from the top;
+    // a rejected one leaves everything as it was. `size` is how many bytes
+    // at the cursor the edit replaced, 0 for an insert.
+    fn apply_edit(&mut self, edited: Result<Chunk, String>, size: usize, done: String) -> bool {
+        match edited {
+            Ok(chunk) => {
+                let resized = size + chunk.code.len() - self.program.chunk.code.len();
+                self.debugger.instruction_resized(self.cursor, size, resized);
+                let cursor = instruction_start(&chunk, self.cursor);
+                self.program.chunk = chunk;
+                self.edited = true;
+                self.restart();
+                self.cursor = cursor;
+                self.status = done;
+                true
+            }
+            Err(message) => {
+                self.status = format!("Edit {}", message);
+                false
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.vm = fresh_vm(&self.program, self.history);
+        self.playing = false;
+        self.failed = false;
+        self.furthest = 0;
+        self.follow();
+    }
+
+    // Enter applies the condition to the cursor's instruction (empty clears)
+    fn submit_condition(&mut self, text: String) {
+        let location = Location::Offset(self.cursor);
+        if text.trim().is_empty() {
+            self.debugger.set_condition(location, None);
+            self.status = format!("Breakpoint at {:04} is unconditional", self.cursor);
+            return;
+        }
+        match Condition::parse(&text) {
+            Ok(condition) => {
+                self.debugger.set_condition(location, Some(condition));
+                self.status = format!("Breakpoint at {:04} when top {}", self.cursor, text.trim());
+            }
+            Err(message) => self.status = format!("Bad condition: {}", message),
+        }
+    }
+
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    pub fn with_history(mut self, limit: usize) -> Self {
+        self.history = limit;
+        self.vm = fresh_vm(&self.program, limit);
+        self
+    }
+
+    fn step(&self) -> usize {
+        self.vm.history.as_ref().map_or(0, |history| history.step())
+    }
+
+    // Move along the timeline: back through the undo log, or forward by
+    // re-executing, which replays exactly since programs take no input
+    fn seek(&mut self, target: usize) {
+        self.failed = false;
+        while self.step() > target && self.vm.step_back() {}
+        while self.step() < target && !self.vm.is_done() {
+            if let Err(error) = self.vm.step() {
+                self.stopped(StopReason::Error(error));
+                return;
+            }
+        }
+        self.furthest = self.furthest.max(self.step());
+        self.status = format!("Step {}", self.step());
+        self.follow();
+    }
+
+    // Up/Down move the instruction cursor in the bytecode pane and scroll elsewhere
+    fn scroll_by(&mut self, rows: isize) {
+        if self.focus == Pane::Bytecode {
+            let starts: Vec<usize> = self.vm.chunk.instructions().map(|(offset, _)| offset).collect();
+            let row = starts.iter().position(|&offset| offset == self.cursor).unwrap_or(0);
+            let row = row.saturating_add_signed(rows).min(starts.len().saturating_sub(1));
+            self.cursor = starts.get(row).copied().unwrap_or(0);
+            return;
+        }
+        // Output counts from the bottom, so scrolling up moves away from the end
+        let rows = if self.focus == Pane::Output { -rows } else { rows };
+        let scroll = &mut self.scroll[self.focus.index()];
+        *scroll = scroll.saturating_add_signed(rows);
+    }
+
+    // Bring the bytecode cursor and source view to where execution is
+    fn follow(&mut self) {
+        self.cursor = instruction_start(&self.vm.chunk, self.vm.ip);
+        let line = self.current_line().unwrap_or(1);
+        self.scroll[Pane::Source.index()] = line.saturating_sub(SOURCE_CONTEXT + 1);
+    }
+
+    fn stopped(&mut self, reason: StopReason) {
+        self.furthest = self.furthest.max(self.step());
+        self.failed = matches!(reason, StopReason::Error(_));
+        self.status = match reason {
+            StopReason::Stepped => format!("Stepped to {:04}", self.vm.ip),
+            StopReason::Breakpoint(offset) => format!("Hit breakpoint at {:04}", offset),
+            StopReason::Finished => "Finished".to_string(),
+            StopReason::Error(error) => format!("Runtime error: {}", error.message),
+        };
+        self.follow();
+    }
+}
+
+// Output is captured so `print` doesn't scribble over the terminal
+fn fresh_vm(program: &Program, history: usize) -> VirtualMachine {
+    let mut vm = VirtualMachine::new(program.chunk.clone()).with_output(Output::Buffer(Vec::new()));
+    vm.heap = program.heap.clone();
+    vm.enable_history(history);
+    vm
+}
+
+// Offset of the last instruction starting at or before `offset`, so the
+// cursor stays on an instruction when the code shrinks or execution ends
+fn instruction_start(chunk: &Chunk, offset: usize) -> usize {
+    let starts = chunk.instructions().map(|(start, _)| start);
+    starts.take_while(|&start| start <= offset).last().unwrap_or(0)
+}
+
+impl App {
+    // Line of the instruction about to run, or of the last one once finished
+    fn current_line(&self) -> Option<usize> {
+        let lines = &self.vm.chunk.lines;
+        let offset = if self.vm.is_done() { self.vm.ip.checked_sub(1)? } else { self.vm.ip };
+        lines.get(offset).copied()
+    }
+
+    // Focused pane gets a thick yellow border
+    fn pane_block(&self, pane: Pane, title: &str) -> Block<'static> {
+        let block = Block::bordered().title(format!(" {} ", title));
+        if self.focus == pane {
+            block.border_set(border::THICK).yellow()
+        } else {
+            block
+        }
+    }
+
+    fn render_pane(&self, pane: Pane, lines: Vec<Line<'static>>, area: Rect, buf: &mut Buffer) {
+        let rows = (area.height as usize).saturating_sub(2);
+        let scroll = self.scroll[pane.index()];
+        let first = if pane == Pane::Output {
+            lines.len().saturating_sub(rows + scroll)
+        } else {
+            scroll.min(lines.len().saturating_sub(1))
+        };
+        let visible: Vec<Line> = lines.into_iter().skip(first).take(rows).collect();
+        Paragraph::new(Text::from(visible))
+            .block(self.pane_block(pane, pane.title()))
+            .render(area, buf);
+    }
+
+    fn render_source(&self, area: Rect, buf: &mut Buffer) {
+        let Some(source) = &self.program.source else {
+            Paragraph::new("(compiled chunk, no source)")
+                .block(self.pane_block(Pane::Source, &self.program.name))
+                .render(area, buf);
+            return;
+        };
+
+        let current = self.current_line();
+        let lines: Vec<Line> = source
+            .lines()
+            .enumerate()
+            .map(|(index, text)| {
+                let row = Line::from(format!("{:4} {}", index + 1, text));
+                if Some(index + 1) == current { row.reversed() } else { row }
+            })
+            .collect();
+        let rows = (area.height as usize).saturating_sub(2);
+        let first = self.scroll[Pane::Source.index()].min(lines.len().saturating_sub(1));
+        let visible: Vec<Line> = lines.into_iter().skip(first).take(rows).collect();
+        Paragraph::new(Text::from(visible))
+            .block(self.pane_block(Pane::Source, &self.program.name))
+            .render(area, buf);
+    }
+
+    fn render_code(&self, area: Rect, buf: &mut Buffer) {
+        // Keep the cursor row on screen for long chunks
+        let rows = (area.height as usize).saturating_sub(2).max(1);
+        let chunk = &self.vm.chunk;
+        let row = chunk.instructions().take_while(|&(offset, _)| offset < self.cursor).count();
+        let first = (row + 1).saturating_sub(rows);
+
+        let mut lines = Vec::new();
+        for (i, _) in chunk.instructions().skip(first).take(rows) {
+            let breakpoint = self.debugger.marker_at(i, &chunk.lines);
+            let marker = match breakpoint {
+                Some(bp) if bp.condition.is_some() => "◉",
+                Some(bp) if matches!(bp.location, Location::Line(_)) => "◆",
+                Some(_) => "●",
+                None => " ",
+            };
+            let pointer = if i == self.vm.ip && !self.vm.is_done() { "→" } else { " " };
+            let mut text = format!(
+                "{}{} {}",
+                marker,
+                pointer,
+                chunk.disassemble_instruction(i, &self.vm.heap)
+            );
+            if let Some(condition) = breakpoint.and_then(|bp| bp.condition) {
+                text.push_str(&format!("  [{}]", condition));
+            }
+            let row = Line::from(text);
+            lines.push(if i == self.cursor { row.reversed() } else { row });
+        }
+
+        Paragraph::new(Text::from(lines))
+            .block(self.pane_block(Pane::Bytecode, if self.edited { "Bytecode (edited)" } else { "Bytecode" }))
+            .render(area, buf);
+    }
+
+    // Slider from step 0 to the furthest step reached; the undo log covers the
+    // part from `earliest` on
+    fn render_timeline(&self, area: Rect, buf: &mut Buffer) {
+        let earliest = self.vm.history.as_ref().map_or(0, |history| history.earliest());
+        let ratio = if self.furthest == 0 { 0.0 } else { self.step() as f64 / self.furthest as f64 };
+        LineGauge::default()
+            .label(format!(
+                "Step {}/{} (back to {}) ",
+                self.step(),
+                self.furthest,
+                earliest
+            ))
+            .filled_style(Style::new().blue())
+            .ratio(ratio.clamp(0.0, 1.0))
+            .render(area, buf);
+    }
 }
 
 impl Widget for &App {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let title = Line::from(" Virtual Machine ".bold());
         let instructions = Line::from(vec![
+            " Play ".into(),
+            "<Space>".blue().bold(),
+            " Speed ".into(),
+            "<+ ->".blue().bold(),
             " Step ".into(),
             "<Right>".blue().bold(),
+            " Back ".into(),
+            "<Left>".blue().bold(),
+            " Timeline ".into(),
+            "<[ ] Home End>".blue().bold(),
+            " Continue ".into(),
+            "<C>".blue().bold(),
+            " To end ".into(),
+            "<E>".blue().bold(),
+            " Restart ".into(),
+            "<R>".blue().bold(),
+            " Break ".into(),
+            "<B>".blue().bold(),
+            " Line ".into(),
+            "<L>".blue().bold(),
+            " Condition ".into(),
+            "<?>".blue().bold(),
+            " Edit ".into(),
+            "<I O V X>".blue().bold(),
+            " Save ".into(),
+            "<W>".blue().bold(),
+            " Focus ".into(),
+            "<Tab>".blue().bold(),
             " Quit ".into(),
             "<Q>".blue().bold(),
         ]);
@@ -87,20 +668,213 @@ impl Widget for &App {
             .title(title.centered())
             .title_bottom(instructions.centered())
             .border_set(border::THICK);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let status = match &self.prompt {
+            Some(Prompt { kind, text }) => {
+                let label = match kind {
+                    PromptKind::Condition => format!("Break at {:04} when top", self.cursor),
+                    PromptKind::Insert => format!("Insert at {:04}", self.cursor),
+                    PromptKind::Replace => format!("Replace {:04}", self.cursor),
+                    PromptKind::Constant => format!("Constant at {:04}", self.cursor),
+                    PromptKind::Save => "Save chunk to".to_string(),
+                };
+                Line::from(format!("{}: {}_", label, text)).yellow()
+            }
+            None => Line::from(self.status.clone()),
+        };
+        let (state, color) = match self.execution_state() {
+            ExecutionState::Running => (" RUNNING ", Color::Green),
+            ExecutionState::Paused => (" PAUSED ", Color::Yellow),
+            ExecutionState::Halted => (" HALTED ", Color::Blue),
+            ExecutionState::Error => (" ERROR ", Color::Red),
+        };
+        let bar = Line::from(vec![
+            state.bold().fg(Color::Black).bg(color),
+            format!(" {} ips   ip {:04}   step {}", SPEEDS[self.speed], self.vm.ip, self.step()).into(),
+        ]);
+        let header = vec![bar, status];
+
+        let [header_area, timeline_area, main_area, bottom_area] = Layout::vertical([
+            Constraint::Length(header.len() as u16),
+            Constraint::Length(1),
+            Constraint::Min(6),
+            Constraint::Length(10),
+        ])
+        .areas(inner);
+        let [source_area, code_area, stack_area] = Layout::horizontal([
+            Constraint::Percentage(35),
+            Constraint::Percentage(40),
+            Constraint::Percentage(25),
+        ])
+        .areas(main_area);
+        let [constants_area, globals_area, heap_area, frames_area, output_area] =
+            Layout::horizontal([Constraint::Ratio(1, 5); 5]).areas(bottom_area);
+
+        Paragraph::new(Text::from(header)).render(header_area, buf);
+        self.render_timeline(timeline_area, buf);
+        self.render_source(source_area, buf);
+        self.render_code(code_area, buf);
+        self.render_pane(Pane::Stack, panes::stack(&self.vm), stack_area, buf);
+        self.render_pane(Pane::Constants, panes::constants(&self.vm), constants_area, buf);
+        self.render_pane(Pane::Globals, panes::globals(&self.vm), globals_area, buf);
+        self.render_pane(Pane::Heap, panes::heap(&self.vm), heap_area, buf);
+        self.render_pane(Pane::Frames, panes::frames(&self.vm), frames_area, buf);
+        self.render_pane(Pane::Output, panes::output(&self.vm), output_area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assignment6::compiler::Compiler;
+    use assignment6::object::Heap;
+
+    fn screen(app: &App) -> String {
+        let area = Rect::new(0, 0, 120, 32);
+        let mut buf = Buffer::empty(area);
+        app.render(area, &mut buf);
+        (0..area.height)
+            .map(|y| (0..area.width).map(|x| buf[(x, y)].symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_step_back_and_seek() {
+        let mut app = App::new(Program::demo()).with_history(3);
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('e')));
+        let end = app.step();
+        assert!(end > 3);
+
+        app.handle_key_event(KeyEvent::from(KeyCode::Left));
+        assert_eq!(app.step(), end - 1);
+        app.handle_key_event(KeyEvent::from(KeyCode::Home));
+        assert_eq!(app.step(), end - 3); // the oldest steps have been dropped
+        app.handle_key_event(KeyEvent::from(KeyCode::End));
+        assert_eq!(app.step(), end);
+        assert!(screen(&app).contains(&format!("Step {}/{} (back to {})", end, end, end - 3)));
+    }
+
+    #[test]
+    fn test_seek_replays_into_a_runtime_error() {
+        let mut heap = Heap::new();
+        let chunk = Compiler::new().compile("print 1;\nprint -nil;\n", &mut heap).unwrap();
+        let program = Program { name: "<test>".to_string(), source: None, chunk, heap };
+        let mut app = App::new(program).with_history(100);
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('e')));
+        assert_eq!(app.execution_state(), ExecutionState::Error);
+
+        app.handle_key_event(KeyEvent::from(KeyCode::Home));
+        assert_ne!(app.execution_state(), ExecutionState::Error);
+        app.handle_key_event(KeyEvent::from(KeyCode::End));
+        assert_eq!(app.execution_state(), ExecutionState::Error);
+        let text = screen(&app);
+        assert!(text.contains(" ERROR "));
+        assert!(text.contains("Runtime error: Operand must be a number."));
+    }
+
+    #[test]
+    fn test_source_pane_follows_execution() {
+        let mut app = App::new(Program::demo());
+        let text = screen(&app);
+        assert!(text.contains("   1 var a = 3;"));
+        assert!(text.contains("   1  number  3"));
+        assert!(text.contains("→ 0000    1 OpConstant"));
 
-        let mut lines = vec![
-            Line::from(format!("Instruction Pointer: {}", self.vm.ip)),
-            Line::from(format!("Stack: {:?}", self.vm.stack)),
-            Line::from("Chunk Code:".to_string()),
-        ];
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('e')));
+        let text = screen(&app);
+        assert!(text.contains("Finished"));
+        assert_eq!(app.current_line(), Some(4)); // OpReturn sits on the line after the last newline
+        assert!(text.contains("│7  "));
+        assert!(text.contains("a = 3"));
+        assert!(text.contains("(empty)"));
+    }
+
+    #[test]
+    fn test_play_runs_at_speed_and_stops_on_breakpoint() {
+        let mut app = App::new(Program::demo());
+        app.debugger.toggle(Location::Offset(18));
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('+'))); // 20 ips
+        app.handle_key_event(KeyEvent::from(KeyCode::Char(' ')));
+        assert_eq!(app.execution_state(), ExecutionState::Running);
+
+        let start = app.last_tick;
+        app.tick(start + Duration::from_millis(100)); // two instructions due
+        assert_eq!(app.vm.ip, 6);
+        app.tick(start + Duration::from_secs(10));
+        assert_eq!(app.vm.ip, 18);
+        assert_eq!(app.execution_state(), ExecutionState::Paused);
+        assert!(screen(&app).contains("Hit breakpoint at 0018"));
+
+        app.handle_key_event(KeyEvent::from(KeyCode::Char(' ')));
+        app.tick(app.last_tick + Duration::from_secs(10));
+        assert_eq!(app.execution_state(), ExecutionState::Halted);
+        assert!(screen(&app).contains(" HALTED "));
+    }
 
-        for (i, op) in self.vm.chunk.code.iter().enumerate() {
-            let pointer = if self.vm.ip > 0 && i == self.vm.ip - 1 { "→" } else { " " };
-            lines.push(Line::from(format!(" {} {:?}", pointer, op)));
+    #[test]
+    fn test_focus_and_scrolling() {
+        let mut app = App::new(Program::demo());
+        app.handle_key_event(KeyEvent::from(KeyCode::Tab));
+        assert_eq!(app.focus, Pane::Stack);
+        app.handle_key_event(KeyEvent::from(KeyCode::Down));
+        assert_eq!((app.cursor, app.scroll[Pane::Stack.index()]), (0, 1));
+
+        app.handle_key_event(KeyEvent::from(KeyCode::BackTab));
+        app.handle_key_event(KeyEvent::from(KeyCode::Down));
+        assert_eq!(app.cursor, 3); // the second instruction, after the first's three bytes
+        app.handle_key_event(KeyEvent::from(KeyCode::PageDown));
+        assert_eq!(app.cursor, 20);
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            app.handle_key_event(KeyEvent::from(KeyCode::Char(c)));
         }
+        app.handle_key_event(KeyEvent::from(KeyCode::Enter));
+    }
 
-        Paragraph::new(Text::from(lines))
-            .block(block)
-            .render(area, buf);
+    #[test]
+    fn test_live_edits_are_verified_and_saved() {
+        // demo: 0 const 3, 3 define a, 6 const 4, 9 define b, 12 get a, 15 get b, 18 add, 19 print, 20 return
+        let mut app = App::new(Program::demo());
+        app.debugger.toggle(Location::Offset(19));
+        app.cursor = 18;
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('i')));
+        type_text(&mut app, "OpConstant 10");
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('i')));
+        type_text(&mut app, "OpMultiply");
+        assert_eq!(app.status, "Inserted at 0021");
+        assert_eq!(app.debugger.breakpoints[0].location, Location::Offset(23)); // still on OpPrint
+
+        app.cursor = 0;
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('v')));
+        assert_eq!(app.prompt.as_ref().unwrap().text, "3");
+        app.handle_key_event(KeyEvent::from(KeyCode::Backspace));
+        type_text(&mut app, "5");
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('c')));
+        assert_eq!(app.status, "Hit breakpoint at 0023");
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('e')));
+        assert_eq!(app.vm.output.captured(), Some("45\n".as_bytes())); // a + b * 10
+
+        app.cursor = 18;
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('x')));
+        assert!(app.status.starts_with("Edit rejected:"), "{}", app.status);
+        assert_eq!(app.program.chunk.code.len(), 25);
+
+        let path = env::temp_dir().join(format!("tui_edit_{}.loxc", process::id()));
+        let path = path.to_string_lossy().into_owned();
+        app.handle_key_event(KeyEvent::from(KeyCode::Char('w')));
+        app.prompt.as_mut().unwrap().text.clear();
+        type_text(&mut app, &path);
+        assert_eq!(app.status, format!("Saved chunk to {}", path));
+        let saved = Program::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            saved.chunk.disassemble("chunk", &saved.heap),
+            app.program.chunk.disassemble("chunk", &app.program.heap)
+        );
     }
 }
@@ -0,0 +1,143 @@
+use assignment6::object::{Heap, Obj};
+use assignment6::virtual_machine::{Value, VirtualMachine};
+use ratatui::text::Line;
+
+// Panes that can take keyboard focus, in Tab order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pane {
+    Source,
+    Bytecode,
+    Stack,
+    Constants,
+    Globals,
+    Heap,
+    Frames,
+    Output,
+}
+
+impl Pane {
+    pub const ALL: [Pane; 8] = [
+        Pane::Source,
+        Pane::Bytecode,
+        Pane::Stack,
+        Pane::Constants,
+        Pane::Globals,
+        Pane::Heap,
+        Pane::Frames,
+        Pane::Output,
+    ];
+
+    pub fn index(self) -> usize {
+        Pane::ALL.iter().position(|&pane| pane == self).unwrap_or(0)
+    }
+
+    pub fn next(self) -> Pane {
+        Pane::ALL[(self.index() + 1) % Pane::ALL.len()]
+    }
+
+    pub fn previous(self) -> Pane {
+        Pane::ALL[(self.index() + Pane::ALL.len() - 1) % Pane::ALL.len()]
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Pane::Source => "Source",
+            Pane::Bytecode => "Bytecode",
+            Pane::Stack => "Stack",
+            Pane::Constants => "Constants",
+            Pane::Globals => "Globals",
+            Pane::Heap => "Heap",
+            Pane::Frames => "Frames",
+            Pane::Output => "Output",
+        }
+    }
+}
+
+pub fn type_name(value: &Value, heap: &Heap) -> &'static str {
+    match value {
+        Value::ValBool(_) => "bool",
+        Value::ValNumber(_) => "number",
+        Value::ValNil => "nil",
+        Value::ValObj(handle) => match heap.get(*handle) {
+            Obj::ObjString(_) => "string",
+        },
+    }
+}
+
+// Strings are quoted here so "1" and 1 can be told apart
+fn show(value: &Value, heap: &Heap) -> String {
+    match value {
+        Value::ValObj(_) => format!("{:?}", value.format(heap)),
+        _ => value.format(heap),
+    }
+}
+
+// Top of the stack first, each entry with its slot index
+pub fn stack(vm: &VirtualMachine) -> Vec<Line<'static>> {
+    if vm.stack.is_empty() {
+        return vec![Line::from("(empty)")];
+    }
+    vm.stack
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(slot, value)| {
+            let top = if slot + 1 == vm.stack.len() { " ← top" } else { "" };
+            Line::from(format!("[{:3}] {}{}", slot, show(&value.unpack(), &vm.heap), top))
+        })
+        .collect()
+}
+
+pub fn constants(vm: &VirtualMachine) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::from(format!("{:>4}  {:<7} value", "#", "type"))];
+    lines.extend(vm.chunk.constants.iter().enumerate().map(|(index, value)| {
+        let value = &value.unpack();
+        Line::from(format!(
+            "{:4}  {:<7} {}",
+            index,
+            type_name(value, &vm.heap),
+            show(value, &vm.heap)
+        ))
+    }));
+    lines
+}
+
+// Sorted by name so the table doesn't reshuffle between steps
+pub fn globals(vm: &VirtualMachine) -> Vec<Line<'static>> {
+    let mut entries: Vec<(String, String)> = vm
+        .globals
+        .iter()
+        .map(|(name, value)| {
+            let name = vm.heap.as_string(*name).unwrap_or("?").to_string();
+            (name, show(value, &vm.heap))
+        })
+        .collect();
+    entries.sort();
+    if entries.is_empty() {
+        return vec![Line::from("(none)")];
+    }
+    entries
+        .into_iter()
+        .map(|(name, value)| Line::from(format!("{} = {}", name, value)))
+        .collect()
+}
+
+pub fn heap(vm: &VirtualMachine) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::from(format!("{:>4}  {:<7} {:>6}  value", "ref", "type", "bytes"))];
+    lines.extend(vm.heap.objects().iter().enumerate().map(|(index, object)| match object {
+        Obj::ObjString(text) => Line::from(format!("{:4}  {:<7} {:6}  {:?}", index, "string", text.len(), text)),
+    }));
+    lines
+}
+
+// The script is the only frame until functions exist
+pub fn frames(vm: &VirtualMachine) -> Vec<Line<'static>> {
+    let offset = if vm.is_done() { vm.ip.saturating_sub(1) } else { vm.ip };
+    let line = vm.chunk.lines.get(offset).copied().unwrap_or(0);
+    vec![Line::from(format!("script @{:04} line {}", vm.ip, line))]
+}
+
+pub fn output(vm: &VirtualMachine) -> Vec<Line<'static>> {
+    let captured = String::from_utf8_lossy(vm.output.captured().unwrap_or_default()).into_owned();
+    captured.lines().map(|text| Line::from(text.to_string())).collect()
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::Path;
+
+use assignment6::compiler::Compiler;
+use assignment6::loxc::{self, MAGIC};
+use assignment6::object::Heap;
+use assignment6::virtual_machine::Chunk;
+use lox_diagnostics::render_all;
+
+// Shown when no file is given on the command line
+const DEMO: &str = "var a = 3;\nvar b = 4;\nprint a + b;\n";
+
+// What the debugger runs: a chunk, the heap holding its strings, and the
+// source it came from when there is one
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub name: String,
+    pub source: Option<String>,
+    pub chunk: Chunk,
+    pub heap: Heap,
+}
+
+impl Program {
+    pub fn demo() -> Self {
+        Self::compile("<demo>", DEMO.to_string()).expect("demo program compiles")
+    }
+
+    // A .loxc chunk is recognised by its magic number; anything else is compiled as source
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|error| format!("could not read '{}': {}", path, error))?;
+        if bytes.starts_with(MAGIC) {
+            let mut heap = Heap::new();
+            let chunk = loxc::decode(&bytes, &mut heap)
+                .map_err(|error| format!("invalid chunk '{}': {}", path, error))?;
+            return Ok(Self {
+                name: path.to_string(),
+                source: None,
+                chunk,
+                heap,
+            });
+        }
+        let source = String::from_utf8(bytes).map_err(|_| format!("'{}' is not UTF-8 source", path))?;
+        Self::compile(path, source)
+    }
+
+    // Where `w` offers to write the chunk: next to the file it came from
+    pub fn save_path(&self) -> String {
+        if self.name.starts_with('<') {
+            return format!("{}.loxc", self.name.trim_matches(['<', '>']));
+        }
+        Path::new(&self.name).with_extension("loxc").to_string_lossy().into_owned()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, loxc::encode(&self.chunk, &self.heap))
+            .map_err(|error| format!("could not write '{}': {}", path, error))
+    }
+
+    fn compile(name: &str, source: String) -> Result<Self, String> {
+        let mut heap = Heap::new();
+        match Compiler::new().compile(&source, &mut heap) {
+            Ok(chunk) => Ok(Self {
+                name: name.to_string(),
+                source: Some(source),
+                chunk,
+                heap,
+            }),
+            Err(diagnostics) => Err(render_all(&diagnostics, &source, name).trim_end().to_string()),
+        }
+    }
+}
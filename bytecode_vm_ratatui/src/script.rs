@@ -0,0 +1,205 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{backend::TestBackend, buffer::Buffer, Terminal};
+
+use crate::App;
+
+// Keys with a name of their own; any other single character stands for itself
+const NAMED: [(&str, KeyCode); 15] = [
+    ("Space", KeyCode::Char(' ')),
+    ("Enter", KeyCode::Enter),
+    ("Esc", KeyCode::Esc),
+    ("Backspace", KeyCode::Backspace),
+    ("Delete", KeyCode::Delete),
+    ("Tab", KeyCode::Tab),
+    ("BackTab", KeyCode::BackTab),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+];
+
+// One line of a key script:
+//   Right        a key, by name or as its character
+//   text OpAdd   each character typed in turn
+//   wait 500     let 500ms pass, so playing runs on
+// Blank lines and lines starting with "# " are skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Key(KeyEvent),
+    Wait(Duration),
+}
+
+pub fn key_name(code: KeyCode) -> Option<String> {
+    if let Some((name, _)) = NAMED.iter().find(|(_, named)| *named == code) {
+        return Some(name.to_string());
+    }
+    match code {
+        KeyCode::Char(c) => Some(c.to_string()),
+        _ => None,
+    }
+}
+
+pub fn parse_key(name: &str) -> Result<KeyCode, String> {
+    if let Some(&(_, code)) = NAMED.iter().find(|(named, _)| *named == name) {
+        return Ok(code);
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(KeyCode::Char(c)),
+        _ => Err(format!("unknown key '{}'", name)),
+    }
+}
+
+pub fn parse(script: &str) -> Result<Vec<Action>, String> {
+    let mut actions = Vec::new();
+    for (index, line) in script.lines().enumerate() {
+        let at = |message: String| format!("line {}: {}", index + 1, message);
+        if line.trim().is_empty() || line.starts_with("# ") {
+            continue;
+        }
+        if let Some(text) = line.strip_prefix("text ") {
+            actions.extend(text.chars().map(|c| Action::Key(KeyEvent::from(KeyCode::Char(c)))));
+        } else if let Some(ms) = line.strip_prefix("wait ") {
+            let ms = ms.trim().parse().map_err(|_| at(format!("bad wait '{}'", ms.trim())))?;
+            actions.push(Action::Wait(Duration::from_millis(ms)));
+        } else {
+            actions.push(Action::Key(KeyEvent::from(parse_key(line.trim()).map_err(at)?)));
+        }
+    }
+    Ok(actions)
+}
+
+fn frame_text(buffer: &Buffer) -> String {
+    let area = buffer.area;
+    let mut text = String::new();
+    for y in 0..area.height {
+        let row: String = (0..area.width).map(|x| buffer[(x, y)].symbol()).collect();
+        text.push_str(row.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+// Feed `actions` to the app with no terminal attached. Time only moves on
+// `wait`, so a script plays back the same way every run. Returns the final
+// frame, or every frame headed by the action that produced it.
+pub fn play(
+    app: &mut App,
+    actions: &[Action],
+    width: u16,
+    height: u16,
+    every_frame: bool,
+) -> io::Result<String> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height))?;
+    let mut clock = app.last_tick;
+    let mut frames = String::new();
+    let mut draw = |app: &App, label: &str, frames: &mut String| -> io::Result<()> {
+        let completed = terminal.draw(|frame| app.draw(frame))?;
+        if every_frame {
+            frames.push_str(&format!("--- {} ---\n", label));
+        } else {
+            frames.clear();
+        }
+        frames.push_str(&frame_text(completed.buffer));
+        Ok(())
+    };
+
+    draw(app, "start", &mut frames)?;
+    for action in actions {
+        if app.exit {
+            break;
+        }
+        let label = match action {
+            Action::Key(key) => {
+                app.handle_key_event(*key);
+                key_name(key.code).unwrap_or_default()
+            }
+            Action::Wait(duration) => {
+                // Starting to play resets the app's own clock, so wait from whichever is later
+                clock = clock.max(app.last_tick) + *duration;
+                app.tick(clock);
+                format!("wait {}", duration.as_millis())
+            }
+        };
+        draw(app, &label, &mut frames)?;
+    }
+    Ok(frames)
+}
+
+// Writes the keys of a live session in the script format. Time spent
+// playing is kept as `wait` lines so auto-stepping replays too.
+#[derive(Debug)]
+pub struct Recorder {
+    out: BufWriter<File>,
+    last: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            last: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, key: KeyEvent, playing: bool) -> io::Result<()> {
+        let now = Instant::now();
+        if playing {
+            writeln!(self.out, "wait {}", now.duration_since(self.last).as_millis())?;
+        }
+        self.last = now;
+        match key_name(key.code) {
+            Some(name) => writeln!(self.out, "{}", name),
+            None => Ok(()), // nothing the app responds to
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+
+    #[test]
+    fn test_parse_script() {
+        let actions = parse("# step twice\nRight\ns\n\ntext 1 \nwait 250\nSpace\n").unwrap();
+        let keys: Vec<String> = actions
+            .iter()
+            .map(|action| match action {
+                Action::Key(key) => key_name(key.code).unwrap(),
+                Action::Wait(duration) => format!("wait {}", duration.as_millis()),
+            })
+            .collect();
+        assert_eq!(keys, ["Right", "s", "1", "Space", "wait 250", "Space"]);
+        assert_eq!(parse("Right\nF13\n").unwrap_err(), "line 2: unknown key 'F13'");
+        assert_eq!(parse("wait soon\n").unwrap_err(), "line 1: bad wait 'soon'");
+    }
+
+    #[test]
+    fn test_play_is_repeatable() {
+        let script = parse("+\nSpace\nwait 100\nwait 100\nSpace\nRight\n").unwrap();
+        let run = |every_frame| play(&mut App::new(Program::demo()), &script, 120, 32, every_frame).unwrap();
+
+        let last = run(false);
+        assert!(last.contains("Stepped to 0015"), "{}", last);
+        assert_eq!(last.lines().count(), 32);
+        assert_eq!(run(false), last);
+
+        let all = run(true);
+        assert_eq!(all.matches("\n--- ").count() + 1, 7);
+        assert!(all.starts_with("--- start ---\n"));
+        assert!(all.ends_with(&last));
+    }
+}
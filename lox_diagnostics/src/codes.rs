@@ -0,0 +1,18 @@
+// Error codes shared by every Lox front end. Codes are never reused, so a
+// code means the same thing whichever compiler reported it.
+
+// The scanner could not form a token (unknown character, unterminated string)
+pub const INVALID_TOKEN: &str = "E0001";
+// A prefix expression was required but the token cannot start one
+pub const EXPECTED_EXPRESSION: &str = "E0002";
+// A specific token was required, e.g. a closing parenthesis
+pub const EXPECTED_TOKEN: &str = "E0003";
+// A local variable was declared twice in the same scope
+pub const DUPLICATE_VARIABLE: &str = "E0004";
+// A local variable was read inside its own initializer
+pub const SELF_REFERENCING_INITIALIZER: &str = "E0005";
+// The left-hand side of `=` is not something that can be assigned to
+pub const INVALID_ASSIGNMENT_TARGET: &str = "E0006";
+// The program needs more than the bytecode can encode: too many constants or
+// locals, or a jump too long for its operand
+pub const LIMIT_EXCEEDED: &str = "E0007";
@@ -0,0 +1,163 @@
+// Compile error reporting shared by the Lox scanners and compilers.
+// Diagnostics point into the source through a Span and render rustc-style:
+//
+// error[E0002]: Expect expression.
+//  --> script.lox:1:5
+//   |
+// 1 | 1 + ;
+//   |     ^
+
+pub mod codes;
+
+// ---------- Span ----------
+// Byte range in the source plus the 1-based line and column where it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Span { start, end, line, column }
+    }
+}
+
+// ---------- Diagnostic ----------
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            code,
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    // Render with the offending source line and a caret underline.
+    // `name` is the file name shown in the location line.
+    pub fn render(&self, source: &str, name: &str) -> String {
+        let line_text = source_line(source, self.span.start);
+        let gutter = " ".repeat(self.span.line.to_string().len());
+
+        let mut out = format!("error[{}]: {}\n", self.code, self.message);
+        out.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter, name, self.span.line, self.span.column
+        ));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(format!("{} | {}", self.span.line, line_text).trim_end());
+        out.push('\n');
+        out.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            indent(line_text, self.span.column),
+            "^".repeat(underline_width(source, &self.span, line_text)),
+        ));
+        if let Some(help) = &self.help {
+            out.push_str(&format!("{} |\n", gutter));
+            out.push_str(&format!("{} = help: {}\n", gutter, help));
+        }
+        out
+    }
+}
+
+// Render a list of diagnostics separated by blank lines
+pub fn render_all(diagnostics: &[Diagnostic], source: &str, name: &str) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render(source, name))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Column (1-based, in characters) of a byte offset within its line
+pub fn column_of(source: &str, offset: usize) -> usize {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    source[line_start..offset].chars().count() + 1
+}
+
+// The full line of source containing `offset`, without its newline
+fn source_line(source: &str, offset: usize) -> &str {
+    let offset = offset.min(source.len());
+    let start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+    source[start..end].trim_end_matches('\r')
+}
+
+// Whitespace that lines the caret up under `column`, keeping tabs as tabs
+fn indent(line_text: &str, column: usize) -> String {
+    line_text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect()
+}
+
+// Number of carets, clipped to the first line of the span and at least one
+fn underline_width(source: &str, span: &Span, line_text: &str) -> usize {
+    let end = span.end.min(source.len()).max(span.start.min(source.len()));
+    let text = &source[span.start.min(source.len())..end];
+    let on_line = text.split('\n').next().unwrap_or("").chars().count();
+    let remaining = line_text.chars().count().saturating_sub(span.column.saturating_sub(1));
+    on_line.min(remaining).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_points_at_span() {
+        let source = "var a = 1;\nprint a + @;\n";
+        let span = Span::new(21, 22, 2, column_of(source, 21));
+        let diagnostic = Diagnostic::error(codes::INVALID_TOKEN, "Unknown character.", span);
+
+        assert_eq!(
+            diagnostic.render(source, "test.lox"),
+            "error[E0001]: Unknown character.\n \
+             --> test.lox:2:11\n  \
+             |\n\
+             2 | print a + @;\n  \
+             |           ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_with_help_and_wide_span() {
+        let source = "x = (1 + 2";
+        let span = Span::new(4, 10, 1, 5);
+        let diagnostic = Diagnostic::error(codes::EXPECTED_TOKEN, "Expect ')' after expression.", span)
+            .with_help("add a closing `)`");
+
+        let rendered = diagnostic.render(source, "test.lox");
+        assert!(rendered.contains("1 | x = (1 + 2\n  |     ^^^^^^\n"));
+        assert!(rendered.ends_with("  |\n  = help: add a closing `)`\n"));
+    }
+
+    #[test]
+    fn test_span_at_end_of_input() {
+        let source = "1 +\n";
+        let span = Span::new(4, 4, 2, column_of(source, 4));
+        let rendered = Diagnostic::error(codes::EXPECTED_EXPRESSION, "Expect expression.", span)
+            .render(source, "test.lox");
+        assert!(rendered.contains("--> test.lox:2:1\n"));
+        assert!(rendered.ends_with("2 |\n  | ^\n"));
+    }
+}
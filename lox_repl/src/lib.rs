@@ -0,0 +1,50 @@
+// Line editing shared by the Lox REPLs. Each crate's Repl decides what a
+// line means; this crate reads the lines with rustyline and keeps history
+// in ~/.lox_history, or in the working directory when there is no home.
+
+use std::env;
+use std::path::PathBuf;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+// What the editing loop needs from a REPL
+pub trait LineHandler {
+    // Changes while input is incomplete, e.g. "> " then "... "
+    fn prompt(&self) -> &'static str;
+    fn feed(&mut self, line: &str);
+}
+
+pub fn history_path() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_default()
+        .join(".lox_history")
+}
+
+// Reads lines until end of input. Ctrl-C abandons the line being typed but
+// keeps the session.
+pub fn run(handler: &mut impl LineHandler) {
+    let mut editor = DefaultEditor::new().expect("Failed to start line editor");
+    let history = history_path();
+    let _ = editor.load_history(&history);
+
+    loop {
+        match editor.readline(handler.prompt()) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                }
+                handler.feed(&line);
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                eprintln!("{}", error);
+                break;
+            }
+        }
+    }
+
+    let _ = editor.save_history(&history);
+}